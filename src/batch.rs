@@ -0,0 +1,140 @@
+//! Lists of urls to be downloaded in one go.
+//!
+//! Each line of the list holds a url, optionally followed by a directory and a base name for
+//! that url's files, in the same form they'd take on the command line:
+//!
+//! ```text
+//! # Blank lines and lines beginning with # are ignored.
+//! https://e-hentai.org/g/123456/abcdef1234/
+//! https://gelbooru.com/index.php?page=post&s=list&tags=korra korra
+//! https://imgur.com/a/gN55w imgur -n album
+//! ```
+
+use std::{
+    fmt, fs,
+    io::{self, Read},
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Entry {
+    pub url: String,
+    pub directory: Option<String>,
+    pub name: Option<String>,
+}
+
+/// Writes the entry as a line of a list, as it would be read.
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.url)?;
+        if let Some(directory) = &self.directory {
+            write!(f, " {}", directory)?;
+        }
+        if let Some(name) = &self.name {
+            write!(f, " -n {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a list of urls from a file, or from stdin given `-`.
+pub fn read(path: &str) -> io::Result<Vec<Entry>> {
+    let text = if path == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        text
+    } else {
+        fs::read_to_string(path)?
+    };
+
+    text.lines()
+        .enumerate()
+        .filter_map(|(idx, line)| parse_line(line).map(|entry| (idx, entry)))
+        .map(|(idx, entry)| {
+            entry.map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} line {}: {}", path, idx + 1, e),
+                )
+            })
+        })
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<Result<Entry, &'static str>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let mut fields = line.split_whitespace();
+    let mut entry = Entry {
+        url: fields.next()?.into(),
+        ..Default::default()
+    };
+
+    while let Some(field) = fields.next() {
+        match field {
+            "-n" | "--name" => match fields.next() {
+                Some(name) => entry.name = Some(name.into()),
+                None => return Some(Err("expected a name")),
+            },
+            directory if entry.directory.is_none() => entry.directory = Some(directory.into()),
+            _ => return Some(Err("unexpected field")),
+        }
+    }
+
+    Some(Ok(entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Entry;
+
+    #[test]
+    fn parse_line() {
+        let entry = |url: &str, directory: Option<&str>, name: Option<&str>| Entry {
+            url: url.into(),
+            directory: directory.map(Into::into),
+            name: name.map(Into::into),
+        };
+
+        let cases = &[
+            ("", None),
+            ("  # comment", None),
+            (
+                "https://a.com/1",
+                Some(Ok(entry("https://a.com/1", None, None))),
+            ),
+            (
+                "https://a.com/1\tdir",
+                Some(Ok(entry("https://a.com/1", Some("dir"), None))),
+            ),
+            (
+                "https://a.com/1 dir -n name",
+                Some(Ok(entry("https://a.com/1", Some("dir"), Some("name")))),
+            ),
+            (
+                "https://a.com/1 --name name",
+                Some(Ok(entry("https://a.com/1", None, Some("name")))),
+            ),
+            ("https://a.com/1 dir -n", Some(Err("expected a name"))),
+            ("https://a.com/1 dir other", Some(Err("unexpected field"))),
+        ];
+
+        for (line, expected) in cases {
+            assert_eq!(&super::parse_line(line), expected, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn entries_read_back_as_written() {
+        let entry = Entry {
+            url: "https://a.com/1".into(),
+            directory: Some("dir".into()),
+            name: Some("name".into()),
+        };
+        let line = entry.to_string();
+        assert_eq!("https://a.com/1 dir -n name", line);
+        assert_eq!(super::parse_line(&line), Some(Ok(entry)));
+    }
+}
@@ -0,0 +1,139 @@
+//! Http clients that know how hard to try.
+//!
+//! These are reqwest's blocking clients with a [`RetryPolicy`] attached. Requests are built the
+//! same way as ever, and each one carries the policy of the client that built it, so that
+//! [`Retry::send_with_retry`](crate::retry::Retry::send_with_retry) needn't be told.
+
+use std::sync::Arc;
+
+use reqwest::{
+    blocking::{self, Response},
+    cookie::CookieStore,
+    header::{HeaderMap, HeaderName, HeaderValue},
+    IntoUrl,
+};
+use serde::Serialize;
+
+use crate::retry::RetryPolicy;
+
+#[derive(Clone, Debug)]
+pub struct Client {
+    inner: blocking::Client,
+    retry: RetryPolicy,
+}
+
+impl Client {
+    pub fn builder(retry: RetryPolicy) -> ClientBuilder {
+        ClientBuilder {
+            inner: blocking::Client::builder(),
+            retry,
+        }
+    }
+
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.request(self.inner.get(url))
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.request(self.inner.post(url))
+    }
+
+    fn request(&self, inner: blocking::RequestBuilder) -> RequestBuilder {
+        RequestBuilder {
+            inner,
+            retry: self.retry,
+        }
+    }
+}
+
+pub struct ClientBuilder {
+    inner: blocking::ClientBuilder,
+    retry: RetryPolicy,
+}
+
+impl ClientBuilder {
+    pub fn user_agent(self, value: &str) -> Self {
+        self.map(|inner| inner.user_agent(value))
+    }
+
+    pub fn default_headers(self, headers: HeaderMap) -> Self {
+        self.map(|inner| inner.default_headers(headers))
+    }
+
+    pub fn cookie_provider<C: CookieStore + 'static>(self, store: Arc<C>) -> Self {
+        self.map(|inner| inner.cookie_provider(store))
+    }
+
+    pub fn referer(self, enable: bool) -> Self {
+        self.map(|inner| inner.referer(enable))
+    }
+
+    pub fn build(self) -> reqwest::Result<Client> {
+        Ok(Client {
+            inner: self.inner.build()?,
+            retry: self.retry,
+        })
+    }
+
+    fn map(self, f: impl FnOnce(blocking::ClientBuilder) -> blocking::ClientBuilder) -> Self {
+        Self {
+            inner: f(self.inner),
+            retry: self.retry,
+        }
+    }
+}
+
+pub struct RequestBuilder {
+    inner: blocking::RequestBuilder,
+    retry: RetryPolicy,
+}
+
+impl RequestBuilder {
+    pub fn header(self, key: HeaderName, value: HeaderValue) -> Self {
+        self.map(|inner| inner.header(key, value))
+    }
+
+    pub fn headers(self, headers: HeaderMap) -> Self {
+        self.map(|inner| inner.headers(headers))
+    }
+
+    pub fn basic_auth(self, username: &str, password: Option<&str>) -> Self {
+        self.map(|inner| inner.basic_auth(username, password))
+    }
+
+    pub fn bearer_auth(self, token: &str) -> Self {
+        self.map(|inner| inner.bearer_auth(token))
+    }
+
+    pub fn form<T: Serialize + ?Sized>(self, form: &T) -> Self {
+        self.map(|inner| inner.form(form))
+    }
+
+    pub fn json<T: Serialize + ?Sized>(self, json: &T) -> Self {
+        self.map(|inner| inner.json(json))
+    }
+
+    /// Sends the request just the once, whatever the policy says.
+    pub fn send(self) -> reqwest::Result<Response> {
+        self.inner.send()
+    }
+
+    /// Requests with streaming bodies can't be cloned.
+    pub fn try_clone(&self) -> Option<Self> {
+        Some(Self {
+            inner: self.inner.try_clone()?,
+            retry: self.retry,
+        })
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    fn map(self, f: impl FnOnce(blocking::RequestBuilder) -> blocking::RequestBuilder) -> Self {
+        Self {
+            inner: f(self.inner),
+            retry: self.retry,
+        }
+    }
+}
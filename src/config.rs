@@ -1,4 +1,4 @@
-use std::{collections::HashMap, fs};
+use std::{collections::HashMap, fs, sync::OnceLock};
 
 use directories::UserDirs;
 
@@ -14,8 +14,14 @@ impl Configuration {
     /// Constructs a new config provider based on a provided path.
     ///
     /// Should there be no configuration at the provided path, a blank
-    /// configuration provider will be produced.
+    /// configuration provider will be produced. The file is only read once; later calls
+    /// get a copy of what was read the first time.
     pub fn init() -> Self {
+        static CONFIG: OnceLock<Configuration> = OnceLock::new();
+        CONFIG.get_or_init(Configuration::read).clone()
+    }
+
+    fn read() -> Self {
         let text = UserDirs::new()
             .map(|dirs| dirs.home_dir().join(".imgrab.conf"))
             .and_then(|conf| fs::read_to_string(conf).ok());
@@ -26,7 +32,7 @@ impl Configuration {
             // use for it right now
             // text,
         })
-        .unwrap_or_else(Default::default)
+        .unwrap_or_default()
     }
 
     pub fn get_config(&self, key: Key) -> Result<&str> {
@@ -35,18 +41,36 @@ impl Configuration {
             .map(AsRef::as_ref)
             .ok_or(Error::Configuration(key))
     }
+
+    /// The cookies.txt file named for each site, along with the name of the site.
+    pub fn cookie_files(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.config.iter().filter_map(|(key, value)| match key {
+            Key::Cookies(site) => Some((site.as_str(), value.as_str())),
+            _ => None,
+        })
+    }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Key {
     AtfBooruApi,
     AtfBooruUser,
     BleachUser,
     BleachPass,
+    Blacklist,
+    /// A cookies.txt file for the named site.
+    Cookies(String),
+    DanbooruHosts,
     EHentaiPass,
     EHentaiUser,
+    GelbooruHosts,
     GelbooruUser,
     ImgurClientId,
+    Library,
+    MoebooruHosts,
+    Quality,
+    Retries,
+    RetryDelay,
     SankakuPass,
     SankakuUser,
 }
@@ -58,13 +82,24 @@ impl Key {
             "atfb_user" => Some(Key::AtfBooruUser),
             "bleach_username" => Some(Key::BleachUser),
             "bleach_password" => Some(Key::BleachPass),
+            "blacklist" => Some(Key::Blacklist),
+            "danbooru_hosts" => Some(Key::DanbooruHosts),
             "ehentai_password" => Some(Key::EHentaiPass),
             "ehentai_username" => Some(Key::EHentaiUser),
+            "gelbooru_hosts" => Some(Key::GelbooruHosts),
             "gelbooru_user" => Some(Key::GelbooruUser),
             "imgur_client_id" => Some(Key::ImgurClientId),
+            "library" => Some(Key::Library),
+            "moebooru_hosts" => Some(Key::MoebooruHosts),
+            "quality" => Some(Key::Quality),
+            "retries" => Some(Key::Retries),
+            "retry_delay" => Some(Key::RetryDelay),
             "sankaku_password" => Some(Key::SankakuPass),
             "sankaku_username" => Some(Key::SankakuUser),
-            _ => None,
+            _ => identifier
+                .strip_prefix("cookies.")
+                .filter(|site| !site.is_empty())
+                .map(|site| Key::Cookies(site.into())),
         }
     }
 }
@@ -93,7 +128,9 @@ mod tests {
                             # Comment\n\
                             ehentai_password=bar\n\
                             gelbooru_user=1234\n\
-                            imgur_client_id=baz\n";
+                            imgur_client_id=baz\n\
+                            cookies.E-Hentai=~/eh.txt\n\
+                            cookies.=~/all.txt\n";
 
     #[test]
     fn can_extract_config() {
@@ -102,5 +139,7 @@ mod tests {
         assert_eq!("bar", config[&Key::EHentaiPass]);
         assert_eq!("1234", config[&Key::GelbooruUser]);
         assert_eq!("baz", config[&Key::ImgurClientId]);
+        assert_eq!("~/eh.txt", config[&Key::Cookies("E-Hentai".into())]);
+        assert_eq!(5, config.len());
     }
 }
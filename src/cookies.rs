@@ -0,0 +1,173 @@
+//! Cookies imported from a browser, for sites where we can't log in ourselves.
+//!
+//! Some sites want a login we can't perform (or a captcha we can't solve), but a browser that's
+//! already signed in has everything we need. Exported in the Netscape cookies.txt format, a
+//! file is given in [`Settings`] for the site it was exported from, and that site's clients
+//! start with a jar holding its cookies. No other site sees them, and even then each cookie
+//! goes only to the domain it came from.
+
+use std::{fs, io, path::Path, sync::Arc};
+
+use reqwest::{cookie::Jar, Url};
+
+use crate::settings::Settings;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Cookie {
+    domain: String,
+    include_subdomains: bool,
+    path: String,
+    secure: bool,
+    /// Seconds since the epoch, or zero for a session cookie.
+    expires: i64,
+    name: String,
+    value: String,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires != 0 && self.expires <= now
+    }
+
+    /// The cookie as a server would have set it, along with the url it came from.
+    fn to_set_cookie(&self) -> Option<(String, Url)> {
+        let host = self.domain.trim_start_matches('.');
+        let url = format!("https://{}{}", host, self.path).parse().ok()?;
+
+        let mut header = format!("{}={}; Path={}", self.name, self.value, self.path);
+        if self.include_subdomains {
+            header += "; Domain=";
+            header += host;
+        }
+        if self.secure {
+            header += "; Secure";
+        }
+
+        Some((header, url))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CookieFile {
+    cookies: Vec<Cookie>,
+}
+
+impl CookieFile {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            cookies: read_cookies(&fs::read_to_string(path)?).collect(),
+        })
+    }
+
+    fn fill(&self, jar: &Jar) {
+        let now = chrono::Utc::now().timestamp();
+        let cookies = self
+            .cookies
+            .iter()
+            .filter(|cookie| !cookie.is_expired(now))
+            .filter_map(Cookie::to_set_cookie);
+
+        for (header, url) in cookies {
+            jar.add_cookie_str(&header, &url);
+        }
+    }
+}
+
+/// A new cookie jar holding the cookies imported in these settings, to which a site may add its
+/// own.
+pub fn jar(settings: &Settings) -> Arc<Jar> {
+    let jar = Jar::default();
+    for imported in settings.cookies.values() {
+        imported.fill(&jar);
+    }
+    Arc::new(jar)
+}
+
+/// True where a cookie of this name was imported for the domain, and has yet to expire.
+pub fn is_imported(settings: &Settings, domain: &str, name: &str) -> bool {
+    let now = chrono::Utc::now().timestamp();
+    settings.cookies.values().any(|imported| {
+        imported.cookies.iter().any(|cookie| {
+            cookie.name == name
+                && cookie.domain.trim_start_matches('.') == domain
+                && !cookie.is_expired(now)
+        })
+    })
+}
+
+fn read_cookies(text: &str) -> impl Iterator<Item = Cookie> + '_ {
+    text.lines().filter_map(|line| {
+        // Curl and friends mark http-only cookies by prefixing them with what looks like a
+        // comment. Actual comments are ignored.
+        let line = match line.strip_prefix("#HttpOnly_") {
+            Some(line) => line,
+            None if line.starts_with('#') => return None,
+            None => line,
+        };
+
+        let mut fields = line.trim_end_matches('\r').split('\t');
+        let domain = fields.next().filter(|domain| !domain.is_empty())?;
+        let include_subdomains = fields.next()?.eq_ignore_ascii_case("true");
+        let path = fields.next()?;
+        let secure = fields.next()?.eq_ignore_ascii_case("true");
+        let expires = fields.next()?.parse().ok()?;
+        let name = fields.next()?;
+        let value = fields.next().unwrap_or_default();
+
+        Some(Cookie {
+            domain: domain.into(),
+            include_subdomains,
+            path: path.into(),
+            secure,
+            expires,
+            name: name.into(),
+            value: value.into(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::cookie::CookieStore;
+
+    use super::CookieFile;
+
+    static CONTENT: &str = "# Netscape HTTP Cookie File\n\
+                            \n\
+                            .e-hentai.org\tTRUE\t/\tFALSE\t0\tipb_member_id\t1234\n\
+                            #HttpOnly_forums.e-hentai.org\tFALSE\t/\tTRUE\t0\tipb_pass_hash\tabcd\r\n\
+                            .e-hentai.org\tTRUE\t/\tFALSE\t1000\tstale\tgone\n\
+                            broken line\n";
+
+    #[test]
+    fn can_read_cookies() {
+        let cookies: Vec<_> = super::read_cookies(CONTENT).collect();
+        assert_eq!(3, cookies.len());
+        assert!(cookies[0].include_subdomains);
+        assert_eq!("forums.e-hentai.org", cookies[1].domain);
+        assert!(cookies[1].secure);
+        assert_eq!("abcd", cookies[1].value);
+    }
+
+    #[test]
+    fn cookies_go_to_their_own_domain() {
+        let file = CookieFile {
+            cookies: super::read_cookies(CONTENT).collect(),
+        };
+        let jar = reqwest::cookie::Jar::default();
+        file.fill(&jar);
+
+        let header = |url: &str| {
+            jar.cookies(&url.parse().unwrap())
+                .map(|value| value.to_str().unwrap().to_owned())
+        };
+
+        assert_eq!(
+            Some("ipb_member_id=1234"),
+            header("https://e-hentai.org/g/1/a/").as_deref()
+        );
+        let forums = header("https://forums.e-hentai.org/").unwrap();
+        assert!(forums.contains("ipb_pass_hash=abcd"));
+        assert_eq!(None, header("https://exhentai.org/"));
+    }
+}
@@ -8,15 +8,18 @@ use crate::config::Key;
 
 #[derive(Debug)]
 pub enum Error {
+    /// A site refused our credentials; holds the site's name.
+    Authentication(&'static str),
     Configuration(Key),
     Extraction(ExtractionFailure, String),
     Io(io::Error),
     Network(reqwest::Error),
+    Rejected(Rejection, u16, String),
     Unsupported(UnsupportedError, String),
     Url(url::ParseError),
 
     // This is a catch-all for bullshit like int parsing errors.
-    Other(String, Box<(dyn error::Error + 'static)>),
+    Other(String, Box<dyn error::Error + Send + Sync + 'static>),
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -25,15 +28,41 @@ pub enum ExtractionFailure {
     ImageUrl,
 }
 
+/// The reason a response was refused in place of the media we asked for.
+#[derive(Clone, Debug)]
+pub enum Rejection {
+    Status,
+    ContentType(String),
+    Signature,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum UnsupportedError {
     Domain,
     Route,
 }
 
+impl Error {
+    /// A short, stable name for the kind of error, for output meant for other programs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Authentication(_) => "authentication",
+            Error::Configuration(_) => "configuration",
+            Error::Extraction(..) => "extraction",
+            Error::Io(_) => "io",
+            Error::Network(_) => "network",
+            Error::Rejected(..) => "rejected",
+            Error::Unsupported(..) => "unsupported",
+            Error::Url(_) => "url",
+            Error::Other(..) => "other",
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Error::Authentication(site) => write!(f, "Unable to sign in to {}", site),
             Error::Configuration(key) => write!(f, "Configuration not provided: {:?}", key),
             Error::Extraction(kind, url) => match kind {
                 ExtractionFailure::Metadata => {
@@ -43,6 +72,15 @@ impl Display for Error {
             },
             Error::Io(e) => e.fmt(f),
             Error::Network(e) => e.fmt(f),
+            Error::Rejected(kind, status, url) => match kind {
+                Rejection::Status => write!(f, "Server responded {} at {}", status, url),
+                Rejection::ContentType(content_type) => {
+                    write!(f, "Expected media but got {} at {}", content_type, url)
+                }
+                Rejection::Signature => {
+                    write!(f, "Response is not a recognized image or video at {}", url)
+                }
+            },
             Error::Unsupported(UnsupportedError::Domain, url) => {
                 write!(f, "Unsupported domain: {}", url)
             }
@@ -58,10 +96,12 @@ impl Display for Error {
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
+            Error::Authentication(_) => None,
             Error::Configuration(_) => None,
             Error::Extraction(..) => None,
             Error::Io(e) => Some(e),
             Error::Network(e) => Some(e),
+            Error::Rejected(..) => None,
             Error::Unsupported(..) => None,
             Error::Url(e) => Some(e),
             Error::Other(_, e) => Some(e.as_ref()),
@@ -0,0 +1,166 @@
+//! Json lines printed in place of text under --json.
+//!
+//! Every line on stdout is one json object whose `event` field says what it describes. Fields
+//! may be added to an event over time, but none will be removed or change meaning. Paths are
+//! relative to the working directory where possible, and `index` is the item's one-based
+//! position in the gallery, counting any items skipped with --skip.
+//!
+//! - `gallery`: a download is starting. `url`, `site` and `name` (the gallery name imgrab came
+//!   up with, if any), `directory` and `total`, the number of items expected where it's known.
+//! - `page`: a page of the gallery was fetched. `page` counts pages fetched during this run;
+//!   `items` is how many items were on it.
+//! - `saved`: an item was downloaded. `index`, `path`, `url` (of the media), `source` (what the
+//!   ledger knows it by) and `bytes`. With a library, `duplicate_of` names an existing copy of
+//!   the same content when --duplicates=report.
+//! - `linked`: an item was hard-linked to a copy already in the library. `index`, `path`,
+//!   `url`, `source` and `existing`.
+//! - `skipped`: an item wasn't downloaded. `index`, `reason` and `path`, the file we already
+//!   have. The reason is `existing` for an item downloaded before, or `duplicate` for content
+//!   already in the library.
+//! - `failed`: an item couldn't be downloaded. `index`, `kind` and `message`.
+//! - `error`: a gallery couldn't be downloaded at all. `url` (left out where the error isn't
+//!   about any one url, e.g. an unreadable --input), `kind` and `message`.
+//! - `summary`: everything is done. `files`, `bytes`, `failures`, `duplicates`,
+//!   `partial_files` (left over from earlier runs and removed), `elapsed`, in seconds, and
+//!   `failed`, a list of objects with `url`, `kind`, `message` and, for items, `index`.
+//! - `item`: under --dry-run, an item that would be downloaded. `index`, `source`, `url`,
+//!   `path` and, where the site provides it, `metadata`.
+//!
+//! Error kinds are `authentication`, `configuration`, `extraction`, `io`, `network`,
+//! `rejected`, `unsupported`, `url` and `other`.
+
+use std::{fmt, path::Path};
+
+use imgrab::{Error, Metadata};
+use serde::Serialize;
+
+use crate::failures::Failure;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Gallery {
+        url: &'a str,
+        site: Option<&'a str>,
+        name: Option<&'a str>,
+        directory: &'a Path,
+        total: Option<usize>,
+    },
+    Page {
+        page: usize,
+        items: usize,
+    },
+    Saved {
+        index: usize,
+        path: &'a Path,
+        url: &'a str,
+        source: &'a str,
+        bytes: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        duplicate_of: Option<&'a Path>,
+    },
+    Linked {
+        index: usize,
+        path: &'a Path,
+        url: &'a str,
+        source: &'a str,
+        existing: &'a Path,
+    },
+    Skipped {
+        index: usize,
+        reason: SkipReason,
+        path: &'a Path,
+    },
+    Failed {
+        index: usize,
+        kind: &'static str,
+        message: String,
+    },
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        url: Option<&'a str>,
+        kind: &'static str,
+        message: String,
+    },
+    Summary {
+        files: usize,
+        bytes: u64,
+        failures: usize,
+        duplicates: usize,
+        partial_files: usize,
+        elapsed: f64,
+        failed: &'a [Failure],
+    },
+    Item {
+        index: usize,
+        source: &'a str,
+        url: &'a str,
+        path: &'a Path,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        metadata: Option<&'a Metadata>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    Existing,
+    Duplicate,
+}
+
+impl<'a> Event<'a> {
+    pub fn failed(index: usize, e: &Error) -> Self {
+        Event::Failed {
+            index,
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+
+    pub fn error(url: Option<&'a str>, e: &Error) -> Self {
+        Event::Error {
+            url,
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Event<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        f.write_str(&line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{Event, SkipReason};
+
+    #[test]
+    fn events_are_tagged() {
+        let event = Event::Skipped {
+            index: 3,
+            reason: SkipReason::Existing,
+            path: Path::new("gallery/003.jpg"),
+        };
+        assert_eq!(
+            r#"{"event":"skipped","index":3,"reason":"existing","path":"gallery/003.jpg"}"#,
+            event.to_string()
+        );
+
+        let event = Event::Page { page: 2, items: 40 };
+        assert_eq!(r#"{"event":"page","page":2,"items":40}"#, event.to_string());
+    }
+
+    #[test]
+    fn failures_carry_a_kind() {
+        let e = imgrab::Error::Io(std::io::ErrorKind::NotFound.into());
+        let event = Event::failed(1, &e);
+        assert!(event
+            .to_string()
+            .starts_with(r#"{"event":"failed","index":1,"kind":"io","message":"#));
+    }
+}
@@ -0,0 +1,200 @@
+//! What went wrong, and what that means for the exit status.
+//!
+//! Failures are kept until the end of the run so they can be listed in the summary and, on
+//! request, written out in the form read by --input. Since a second run skips whatever the
+//! first one saved, feeding that list back in retries only what failed.
+
+use std::{fmt::Write, fs, io, path::Path, process::ExitCode};
+
+use imgrab::Error;
+use serde::Serialize;
+
+use crate::batch::Entry;
+
+/// The exit status of a run.
+///
+/// Usage errors are reported by clap, which exits with 2; we leave that alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+    Success,
+    /// Nothing could be downloaded.
+    Failure,
+    /// Some items were downloaded, but not all.
+    Partial,
+    Unsupported,
+    Configuration,
+    Authentication,
+}
+
+pub static EXIT_CODES: &str = "\
+Exit codes:
+  0  everything was downloaded
+  1  nothing could be downloaded
+  3  some items failed
+  4  the url isn't supported
+  5  configuration is missing
+  6  a site refused our credentials";
+
+impl Exit {
+    pub fn for_error(e: &Error) -> Self {
+        match e {
+            Error::Unsupported(..) => Exit::Unsupported,
+            Error::Configuration(_) => Exit::Configuration,
+            Error::Authentication(_) => Exit::Authentication,
+            _ => Exit::Failure,
+        }
+    }
+
+    /// The status for a run which saved (or already had) `files` files, given its failures.
+    ///
+    /// Where nothing at all was downloaded and everything failed for the same reason, that
+    /// reason is the status.
+    pub fn for_failures(files: usize, failures: &[Failure]) -> Self {
+        let first = match failures.first() {
+            Some(failure) => failure.exit,
+            None => return Exit::Success,
+        };
+
+        if files > 0 {
+            Exit::Partial
+        } else if failures.iter().all(|failure| failure.exit == first) {
+            first
+        } else {
+            Exit::Failure
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            Exit::Success => 0,
+            Exit::Failure => 1,
+            Exit::Partial => 3,
+            Exit::Unsupported => 4,
+            Exit::Configuration => 5,
+            Exit::Authentication => 6,
+        }
+    }
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        ExitCode::from(exit.code())
+    }
+}
+
+/// An item, or a whole gallery, that couldn't be downloaded.
+#[derive(Clone, Debug, Serialize)]
+pub struct Failure {
+    /// The gallery, as it would be listed to download it again.
+    #[serde(skip)]
+    pub gallery: Entry,
+    /// The item's one-based position in the gallery, or none where the gallery itself failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    /// The item's source where it's known, or else the gallery's url.
+    pub url: String,
+    pub kind: &'static str,
+    pub message: String,
+    #[serde(skip)]
+    pub exit: Exit,
+}
+
+impl Failure {
+    pub fn new(gallery: &Entry, index: Option<usize>, url: Option<&str>, e: &Error) -> Self {
+        Self {
+            gallery: gallery.clone(),
+            index,
+            url: url.unwrap_or(&gallery.url).into(),
+            kind: e.kind(),
+            message: e.to_string(),
+            exit: Exit::for_error(e),
+        }
+    }
+
+    /// A gallery that couldn't be downloaded at all.
+    pub fn gallery(gallery: &Entry, e: &Error) -> Self {
+        Self::new(gallery, None, None, e)
+    }
+}
+
+/// Writes failures as a list for --input, with each gallery's failed items in comments above it.
+pub fn write_list(path: &Path, failures: &[Failure]) -> io::Result<()> {
+    fs::write(path, format_list(failures))
+}
+
+fn format_list(failures: &[Failure]) -> String {
+    let mut list = String::new();
+    let mut failures = failures.iter().peekable();
+
+    while let Some(failure) = failures.next() {
+        let _ = match failure.index {
+            Some(index) => writeln!(list, "# {} {}: {}", index, failure.url, failure.message),
+            None => writeln!(list, "# {}", failure.message),
+        };
+
+        // Failures come grouped by gallery, and each gallery is listed once.
+        let is_last = failures
+            .peek()
+            .map(|next| next.gallery != failure.gallery)
+            .unwrap_or(true);
+        if is_last {
+            let _ = writeln!(list, "{}", failure.gallery);
+        }
+    }
+
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use imgrab::{config::Key, error::UnsupportedError, Error};
+
+    use crate::batch::Entry;
+
+    use super::{Exit, Failure};
+
+    fn entry(url: &str) -> Entry {
+        Entry {
+            url: url.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exit_for_failures() {
+        let a = entry("https://a.com/1");
+        let unsupported = Error::Unsupported(UnsupportedError::Route, a.url.clone());
+        let configuration = Error::Configuration(Key::SankakuUser);
+        let failures = [
+            Failure::gallery(&a, &unsupported),
+            Failure::gallery(&a, &configuration),
+        ];
+
+        assert_eq!(Exit::Success, Exit::for_failures(3, &[]));
+        assert_eq!(Exit::Partial, Exit::for_failures(3, &failures));
+        assert_eq!(Exit::Unsupported, Exit::for_failures(0, &failures[..1]));
+        assert_eq!(Exit::Failure, Exit::for_failures(0, &failures));
+    }
+
+    #[test]
+    fn format_list() {
+        let a = Entry {
+            directory: Some("dir".into()),
+            ..entry("https://a.com/1")
+        };
+        let b = entry("https://b.com/2");
+        let e = Error::Authentication("B");
+        let failures = [
+            Failure::new(&a, Some(3), Some("https://a.com/p/3"), &e),
+            Failure::new(&a, Some(7), None, &e),
+            Failure::gallery(&b, &e),
+        ];
+
+        let expected = "# 3 https://a.com/p/3: Unable to sign in to B\n\
+                        # 7 https://a.com/1: Unable to sign in to B\n\
+                        https://a.com/1 dir\n\
+                        # Unable to sign in to B\n\
+                        https://b.com/2\n";
+        assert_eq!(expected, super::format_list(&failures));
+    }
+}
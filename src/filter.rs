@@ -0,0 +1,321 @@
+//! Choosing which posts to download, by what the site's listing says about them.
+//!
+//! Boorus cap the number of tags in a search, and some quietly ignore negated tags. A
+//! [`Filter`], given by way of the [`Settings`](crate::Settings) a gallery was resolved with,
+//! makes up for that on our end: every page a pager reads is checked against it, and
+//! posts it turns away are dropped before anything is downloaded (or even resolved). Only what
+//! the listing itself provides is considered, by way of [`Downloadable::post`]; items a site
+//! lists without any such details are let through.
+
+use crate::gallery::{Downloadable, Page, Pager};
+
+/// What a listing says about a post, before anything is downloaded.
+#[derive(Clone, Debug, Default)]
+pub struct Post<'a> {
+    pub tags: Vec<&'a str>,
+    pub rating: Option<&'a str>,
+    /// The file's extension, e.g. png or webm.
+    pub file_type: Option<&'a str>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub score: Option<i64>,
+}
+
+impl<'a> Post<'a> {
+    /// A post described by a space-separated tag string, as served by most boorus.
+    pub fn with_tags(tags: &'a str) -> Self {
+        Self {
+            tags: tags.split_whitespace().collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Takes the file type from the extension of a media url.
+    pub fn with_file_url(mut self, url: &'a str) -> Self {
+        let path = url.split(['?', '#']).next().unwrap_or_default();
+        let name = path.rsplit('/').next().unwrap_or_default();
+        self.file_type = name.rsplit_once('.').map(|(_, extension)| extension);
+        self
+    }
+}
+
+/// Criteria a post must meet to be downloaded. An empty filter lets everything through.
+///
+/// Where a post doesn't say, e.g., how wide it is, any criterion concerning its width is given
+/// the benefit of the doubt.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Tags a post must have, all of them.
+    pub include: Vec<String>,
+    /// Tags a post mustn't have, any of them.
+    pub exclude: Vec<String>,
+    /// Acceptable ratings. A single letter stands for any rating starting with it, so that `e`
+    /// matches `explicit`.
+    pub ratings: Vec<String>,
+    /// Acceptable file extensions.
+    pub file_types: Vec<String>,
+    pub min_width: Option<u32>,
+    pub min_height: Option<u32>,
+    pub min_score: Option<i64>,
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty()
+            && self.exclude.is_empty()
+            && self.ratings.is_empty()
+            && self.file_types.is_empty()
+            && self.min_width.is_none()
+            && self.min_height.is_none()
+            && self.min_score.is_none()
+    }
+
+    pub fn accepts(&self, post: &Post) -> bool {
+        let has_tag = |tag: &String| post.tags.iter().any(|candidate| candidate == tag);
+
+        self.include.iter().all(has_tag)
+            && !self.exclude.iter().any(has_tag)
+            && matches_any(&self.ratings, post.rating, is_same_rating)
+            && matches_any(&self.file_types, post.file_type, is_same_file_type)
+            && at_least(post.width, self.min_width)
+            && at_least(post.height, self.min_height)
+            && at_least(post.score, self.min_score)
+    }
+
+    /// Reads pages until one has a post the filter lets through, or the pager runs dry.
+    ///
+    /// A page that's been filtered down to nothing mustn't be mistaken for the end of the
+    /// gallery.
+    pub(crate) fn next_page<P: Pager>(
+        &self,
+        pager: &mut P,
+        context: &P::Context,
+    ) -> crate::Result<Page<P::Item>> {
+        if self.is_empty() {
+            return pager.next_page(context);
+        }
+
+        loop {
+            let mut page = pager.next_page(context)?;
+            if page.is_empty() {
+                return Ok(page);
+            }
+
+            self.filter_page(&mut page, context);
+            if !page.is_empty() {
+                return Ok(page);
+            }
+        }
+    }
+
+    /// Drops whatever the filter turns away from a page that's already been read.
+    pub(crate) fn filter_page<T: Downloadable>(&self, page: &mut Page<T>, context: &T::Context) {
+        if self.is_empty() {
+            return;
+        }
+
+        page.retain(|item| {
+            item.post(context)
+                .map(|post| self.accepts(&post))
+                .unwrap_or(true)
+        });
+    }
+}
+
+fn at_least<T: PartialOrd>(value: Option<T>, min: Option<T>) -> bool {
+    match (value, min) {
+        (Some(value), Some(min)) => value >= min,
+        _ => true,
+    }
+}
+
+fn matches_any(
+    accepted: &[String],
+    value: Option<&str>,
+    matches: impl Fn(&str, &str) -> bool,
+) -> bool {
+    match value {
+        Some(value) if !accepted.is_empty() => {
+            accepted.iter().any(|accepted| matches(accepted, value))
+        }
+        _ => true,
+    }
+}
+
+fn is_same_rating(accepted: &str, rating: &str) -> bool {
+    let initial = |s: &str| s.chars().next().map(|c| c.to_ascii_lowercase());
+    accepted.eq_ignore_ascii_case(rating)
+        || (accepted.len() == 1 || rating.len() == 1) && initial(accepted) == initial(rating)
+}
+
+fn is_same_file_type(accepted: &str, file_type: &str) -> bool {
+    let normalize = |s: &str| match s.to_ascii_lowercase().as_str() {
+        "jpeg" => String::from("jpg"),
+        s => s.to_owned(),
+    };
+    normalize(accepted.trim_start_matches('.')) == normalize(file_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gallery::{Downloadable, Media, Page, Pager, ResponseGalleryItem};
+
+    use super::{Filter, Post};
+
+    /// A post known by its tags alone.
+    struct Tagged(&'static str);
+
+    impl Downloadable for Tagged {
+        type Context = ();
+        type Output = ResponseGalleryItem;
+
+        fn download(self, _: &()) -> crate::Result<Self::Output> {
+            unreachable!()
+        }
+
+        fn resolve(self, _: &()) -> crate::Result<Media> {
+            unreachable!()
+        }
+
+        fn source(&self, _: &()) -> String {
+            self.0.into()
+        }
+
+        fn post(&self, _: &()) -> Option<Post<'_>> {
+            Some(Post::with_tags(self.0))
+        }
+    }
+
+    struct Listing(Vec<Vec<&'static str>>);
+
+    impl Pager for Listing {
+        type Context = ();
+        type Item = Tagged;
+
+        fn next_page(&mut self, _: &()) -> crate::Result<Page<Tagged>> {
+            if self.0.is_empty() {
+                return Ok(Page::Empty);
+            }
+            Ok(self.0.remove(0).into_iter().map(Tagged).collect())
+        }
+    }
+
+    fn tags(page: Page<Tagged>) -> Vec<&'static str> {
+        match page {
+            Page::Items(items) => items.into_iter().map(|item| item.0).collect(),
+            Page::Empty => Vec::new(),
+        }
+    }
+
+    fn post() -> Post<'static> {
+        Post {
+            rating: Some("explicit"),
+            width: Some(1200),
+            height: None,
+            score: Some(15),
+            ..Post::with_tags("1girl solo sweat")
+                .with_file_url("https://img.example.com/images/ab/cd/abcd.JPEG?1234")
+        }
+    }
+
+    #[test]
+    fn reads_file_type_from_url() {
+        assert_eq!(Some("JPEG"), post().file_type);
+        assert_eq!(
+            None,
+            Post::default()
+                .with_file_url("https://example.com/images/abcd")
+                .file_type
+        );
+    }
+
+    #[test]
+    fn empty_filter_accepts_everything() {
+        assert!(Filter::default().is_empty());
+        assert!(Filter::default().accepts(&post()));
+    }
+
+    #[test]
+    fn reads_past_pages_filtered_to_nothing() {
+        let listing = || {
+            Listing(vec![
+                vec!["comic"],
+                vec!["solo", "comic solo"],
+                vec!["solo"],
+            ])
+        };
+        let filter = Filter {
+            exclude: vec![String::from("comic")],
+            ..Default::default()
+        };
+
+        let mut filtered = listing();
+        assert_eq!(
+            vec!["solo"],
+            tags(filter.next_page(&mut filtered, &()).unwrap())
+        );
+        assert_eq!(
+            vec!["solo"],
+            tags(filter.next_page(&mut filtered, &()).unwrap())
+        );
+        assert!(filter.next_page(&mut filtered, &()).unwrap().is_empty());
+
+        let mut unfiltered = listing();
+        let page = Filter::default().next_page(&mut unfiltered, &()).unwrap();
+        assert_eq!(vec!["comic"], tags(page));
+    }
+
+    #[test]
+    fn filters_by_tag() {
+        let filter = Filter {
+            include: vec![String::from("solo"), String::from("sweat")],
+            ..Default::default()
+        };
+        assert!(filter.accepts(&post()));
+
+        let filter = Filter {
+            exclude: vec![String::from("comic"), String::from("1girl")],
+            ..Default::default()
+        };
+        assert!(!filter.accepts(&post()));
+    }
+
+    #[test]
+    fn filters_by_rating_and_file_type() {
+        let filter = Filter {
+            ratings: vec![String::from("q"), String::from("e")],
+            file_types: vec![String::from("jpg")],
+            ..Default::default()
+        };
+        assert!(filter.accepts(&post()));
+
+        let filter = Filter {
+            ratings: vec![String::from("safe")],
+            ..Default::default()
+        };
+        assert!(!filter.accepts(&post()));
+
+        let filter = Filter {
+            file_types: vec![String::from("png"), String::from("webm")],
+            ..Default::default()
+        };
+        assert!(!filter.accepts(&post()));
+    }
+
+    #[test]
+    fn unknown_values_get_the_benefit_of_the_doubt() {
+        let filter = Filter {
+            min_width: Some(1000),
+            min_height: Some(5000),
+            min_score: Some(10),
+            ..Default::default()
+        };
+        assert!(filter.accepts(&post()));
+
+        let filter = Filter {
+            min_score: Some(20),
+            ..Default::default()
+        };
+        assert!(!filter.accepts(&post()));
+    }
+}
@@ -1,14 +1,12 @@
-pub mod atfbooru;
 pub mod beautymuscle;
 pub mod bitchesgirls;
-pub mod bleachbooru;
+pub mod booru;
 pub mod ehentai;
 pub mod exhentai;
 pub mod fappeningbook;
 pub mod fitnakedgirls;
 pub mod flist;
 pub mod fucks_pics;
-pub mod gelbooru;
 pub mod girlswithmuscle;
 pub mod hdporn;
 pub mod hentai_foundry;
@@ -22,32 +20,78 @@ pub mod sankakubeta;
 pub mod scrolller;
 pub mod thefitgirlz;
 
+mod boxed;
+
 use std::{
     borrow::Cow,
     collections::VecDeque,
-    io::{self, Write},
+    io::{self, Read, Write},
     iter::FromIterator,
+    sync::Arc,
 };
 
 use reqwest::blocking::Response;
 
-use crate::storage::NameContext;
+use crate::{
+    error::{Error, Rejection},
+    filter::{Filter, Post},
+    metadata::Metadata,
+    settings::Settings,
+    site::Site,
+    storage::NameContext,
+};
 
-pub(crate) fn build_client() -> prelude::Client {
+pub use boxed::{BoxedGallery, BoxedItem, BoxedPending};
+
+/// Every supported site. A new gallery module needs an entry here to be reachable.
+pub static SITES: &[&Site] = &[
+    &booru::ALL_THE_FALLEN,
+    &beautymuscle::SITE,
+    &bitchesgirls::SITE,
+    &booru::BLEACHBOORU,
+    &booru::DANBOORU,
+    &ehentai::SITE,
+    &exhentai::SITE,
+    &fappeningbook::SITE,
+    &fitnakedgirls::SITE,
+    &flist::SITE,
+    &fucks_pics::SITE,
+    &booru::GELBOORU,
+    &girlswithmuscle::SITE,
+    &hdporn::SITE,
+    &hentai_foundry::SITE,
+    &imgur::SITE,
+    &booru::KONACHAN,
+    &nhentai::SITE,
+    &novelcrow::SITE,
+    &nsfwalbum::SITE,
+    &booru::REALBOORU,
+    &rule34::SITE,
+    &rule34_us::SITE,
+    &booru::SAFEBOORU,
+    &sankakubeta::SITE,
+    &scrolller::SITE,
+    &booru::TBIB,
+    &thefitgirlz::SITE,
+    &booru::XBOORU,
+    &booru::YANDERE,
+];
+
+pub(crate) fn build_client(settings: &Settings) -> prelude::Client {
     use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
 
     let mut headers = HeaderMap::new();
     headers.insert(ACCEPT, HeaderValue::from_static("text/html"));
-    prelude::Client::builder()
-        .user_agent(prelude::USER_AGENT)
+    prelude::client_builder(settings)
         .default_headers(headers)
         .build()
         .unwrap()
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub enum Page<T> {
     Items(VecDeque<T>),
+    #[default]
     Empty,
 }
 
@@ -66,13 +110,19 @@ impl<T> Page<T> {
         }
     }
 
-    fn is_empty(&self) -> bool {
+    pub(crate) fn is_empty(&self) -> bool {
         match self {
             Page::Items(items) => items.is_empty(),
             Page::Empty => true,
         }
     }
 
+    pub(crate) fn retain(&mut self, f: impl FnMut(&T) -> bool) {
+        if let Page::Items(items) = self {
+            items.retain(f);
+        }
+    }
+
     fn drain(&mut self, count: usize) {
         if let Page::Items(items) = self {
             let _ = items.drain(..count);
@@ -95,47 +145,110 @@ impl<A> FromIterator<A> for Page<A> {
     }
 }
 
-impl<T> Default for Page<T> {
-    fn default() -> Self {
-        Page::Empty
-    }
-}
-
 pub trait Gallery {
+    type Context;
     type Item: GalleryItem;
+    type Pending: Downloadable<Context = Self::Context, Output = Self::Item>;
 
-    /// Retrieve the next gallery item for download.
-    fn next(&mut self) -> Option<crate::Result<Self::Item>>;
+    /// Retrieve the next gallery item without downloading it.
+    ///
+    /// Any paging happens here, so this is always called from a single thread. The pending
+    /// item itself may be downloaded elsewhere by way of the gallery's shared context.
+    fn next_pending(&mut self) -> Option<crate::Result<Self::Pending>>;
+
+    /// The context required to download pending items.
+    fn context(&self) -> &Arc<Self::Context>;
 
     /// Attempts to advance the gallery by n items.
     ///
     /// Returns the number of items actually skipped.
     fn advance_by(&mut self, n: usize) -> crate::Result<usize>;
+
+    /// Metadata describing the gallery as a whole, where the site provides any.
+    fn metadata(&self) -> Option<&Metadata> {
+        None
+    }
+
+    /// The number of items left in the gallery, where it's known or can be estimated.
+    fn remaining(&self) -> Option<usize> {
+        None
+    }
+
+    /// The number of items fetched but not yet handed out, for galleries read a page at a time.
+    ///
+    /// When this is zero, the next call to `next_pending` will fetch a page.
+    fn buffered(&self) -> Option<usize> {
+        None
+    }
 }
 
 pub trait GalleryItem {
-    fn context(&self) -> NameContext;
+    fn context(&self) -> NameContext<'_>;
+
+    /// Confirms that the item is something worth saving.
+    ///
+    /// This is called before the item is given a name; an item which fails validation is
+    /// reported as a failure instead of being written to disk.
+    fn validate(&mut self) -> crate::Result<()>;
+
     fn write<W: Write + ?Sized>(self, writer: &mut W) -> crate::Result<u64>;
+
+    /// Metadata describing the item, where the site provides any.
+    fn metadata(&self) -> Option<&Metadata> {
+        None
+    }
+
+    /// The size of the item in bytes, where the server says.
+    fn content_length(&self) -> Option<u64> {
+        None
+    }
 }
 
 /// A basic gallery item implemented on only a bare [`reqwest::blocking::Response`]
 pub struct ResponseGalleryItem {
     response: Response,
+    head: Vec<u8>,
+    metadata: Option<Metadata>,
 }
 
 impl ResponseGalleryItem {
     pub fn new(response: Response) -> Self {
-        Self { response }
+        Self {
+            response,
+            head: Vec::new(),
+            metadata: None,
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: Option<Metadata>) -> Self {
+        self.metadata = metadata;
+        self
     }
 }
 
 impl GalleryItem for ResponseGalleryItem {
-    fn context(&self) -> NameContext {
+    fn context(&self) -> NameContext<'_> {
         NameContext::from_response(&self.response)
+            .with_head(&self.head)
+            .with_metadata(self.metadata.as_ref())
+    }
+
+    fn validate(&mut self) -> crate::Result<()> {
+        self.head = validate_media(&mut self.response)?;
+        Ok(())
     }
 
     fn write<W: io::Write + ?Sized>(mut self, writer: &mut W) -> crate::Result<u64> {
-        Ok(self.response.copy_to(writer)?)
+        writer.write_all(&self.head)?;
+        Ok(self.head.len() as u64 + self.response.copy_to(writer)?)
+    }
+
+    fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.response.content_length()
     }
 }
 
@@ -143,6 +256,8 @@ impl GalleryItem for ResponseGalleryItem {
 pub struct NamedGalleryItem {
     name: String,
     response: Response,
+    head: Vec<u8>,
+    metadata: Option<Metadata>,
 }
 
 impl NamedGalleryItem {
@@ -150,45 +265,230 @@ impl NamedGalleryItem {
         Self {
             response,
             name: name.into(),
+            head: Vec::new(),
+            metadata: None,
         }
     }
+
+    pub fn with_metadata(mut self, metadata: Option<Metadata>) -> Self {
+        self.metadata = metadata;
+        self
+    }
 }
 
 impl GalleryItem for NamedGalleryItem {
-    fn context(&self) -> NameContext {
-        NameContext::new(self.response.url().as_ref(), Some(Cow::from(&self.name)))
+    fn context(&self) -> NameContext<'_> {
+        NameContext::from_response(&self.response)
+            .with_name(&*self.name)
+            .with_head(&self.head)
+            .with_metadata(self.metadata.as_ref())
+    }
+
+    fn validate(&mut self) -> crate::Result<()> {
+        self.head = validate_media(&mut self.response)?;
+        Ok(())
     }
 
     fn write<W: io::Write + ?Sized>(mut self, writer: &mut W) -> crate::Result<u64> {
-        Ok(self.response.copy_to(writer)?)
+        writer.write_all(&self.head)?;
+        Ok(self.head.len() as u64 + self.response.copy_to(writer)?)
+    }
+
+    fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.response.content_length()
     }
 }
 
+/// The number of bytes read from the front of a response in order to identify it.
+const SNIFF_LEN: u64 = 16;
+
+/// Checks that a response actually contains media.
+///
+/// Sites are fond of answering a request for an image with a 404, a Cloudflare challenge, or
+/// a perfectly cheerful 200 and an html page explaining the image has been removed. Returns the
+/// first few bytes of the body, which have to be read from the response in order to check them.
+fn validate_media(response: &mut Response) -> crate::Result<Vec<u8>> {
+    use reqwest::header::CONTENT_TYPE;
+
+    let status = response.status();
+    let url = response.url().to_string();
+    if !status.is_success() {
+        return Err(Error::Rejected(Rejection::Status, status.as_u16(), url));
+    }
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|header| header.to_str().ok())
+        .map(|header| header.split(';').next().unwrap_or_default().trim());
+
+    if let Some(content_type) = content_type.filter(|&content_type| is_document(content_type)) {
+        return Err(Error::Rejected(
+            Rejection::ContentType(content_type.into()),
+            status.as_u16(),
+            url,
+        ));
+    }
+
+    let mut head = Vec::with_capacity(SNIFF_LEN as usize);
+    response.take(SNIFF_LEN).read_to_end(&mut head)?;
+    if sniff(&head).is_none() {
+        return Err(Error::Rejected(Rejection::Signature, status.as_u16(), url));
+    }
+
+    Ok(head)
+}
+
+fn is_document(content_type: &str) -> bool {
+    let content_type = content_type.to_ascii_lowercase();
+    content_type.starts_with("text/")
+        || matches!(
+            &*content_type,
+            "application/json" | "application/xml" | "application/xhtml+xml"
+        )
+}
+
+/// Identifies image and video formats by their magic numbers, returning the usual extension
+/// for the format.
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    static SIGNATURES: &[(usize, &[u8], &str)] = &[
+        (0, b"\xFF\xD8\xFF", "jpg"),
+        (0, b"\x89PNG\r\n\x1A\n", "png"),
+        (0, b"GIF87a", "gif"),
+        (0, b"GIF89a", "gif"),
+        (0, b"BM", "bmp"),
+        (0, b"II*\0", "tif"),
+        (0, b"MM\0*", "tif"),
+        (0, b"\x1A\x45\xDF\xA3", "webm"),
+        (0, b"FLV", "flv"),
+        (0, b"FWS", "swf"),
+        (0, b"CWS", "swf"),
+        (0, b"ZWS", "swf"),
+        (4, b"ftypqt", "mov"),
+        (4, b"ftypavif", "avif"),
+        (4, b"ftypheic", "heic"),
+        (4, b"ftyp", "mp4"),
+        (8, b"WEBP", "webp"),
+        (8, b"AVI ", "avi"),
+    ];
+
+    SIGNATURES
+        .iter()
+        .find(|(offset, signature, _)| {
+            // Riff containers only identify themselves after the chunk size.
+            head.get(*offset..offset + signature.len()) == Some(*signature)
+                && (*offset != 8 || head.starts_with(b"RIFF"))
+                // Two letters are too easy to hit in text, so bitmaps also need the header's
+                // reserved fields to be zero.
+                && (*signature != b"BM" || head.get(6..10) == Some(&[0; 4]))
+        })
+        .map(|&(_, _, extension)| extension)
+}
+
 pub trait Downloadable {
     type Context;
     type Output: GalleryItem;
 
     /// Materialize a downloadable item as a gallery item.
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output>;
+
+    /// Resolves the item to its media without downloading it.
+    ///
+    /// Finding the media may take a request or two, e.g. to read a post page, but the media
+    /// itself is never requested.
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media>;
+
+    /// A stable identifier for the item, usually its post or page url.
+    ///
+    /// This has to be available without making any requests, because it's how we recognize
+    /// items we've already downloaded.
+    fn source(&self, context: &Self::Context) -> String;
+
+    /// The md5 of the item's media, for sites that publish one.
+    ///
+    /// Like the source, this must be available without making any requests; it lets us
+    /// recognize content we already have before downloading it again.
+    fn md5(&self, _context: &Self::Context) -> Option<String> {
+        None
+    }
+
+    /// What the listing says about the item, for sites that say anything at all.
+    ///
+    /// This is what a [`Filter`](crate::filter::Filter) judges the item by, so it too must be
+    /// available without making any requests.
+    fn post(&self, _context: &Self::Context) -> Option<Post<'_>> {
+        None
+    }
+}
+
+/// The location of an item's media, as resolved without downloading it.
+#[derive(Clone, Debug)]
+pub struct Media {
+    pub url: String,
+    /// A name for the file, where the gallery provides one in place of the url's.
+    pub name: Option<String>,
+    pub metadata: Option<Metadata>,
+}
+
+impl Media {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            name: None,
+            metadata: None,
+        }
+    }
+
+    pub fn named(url: impl Into<String>, name: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            name: Some(name.into()),
+            metadata: None,
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub fn context(&self) -> NameContext<'_> {
+        NameContext::new(&self.url, self.name.as_deref().map(Cow::from))
+            .with_metadata(self.metadata.as_ref())
+    }
 }
 
 pub trait Pager {
     type Context;
     type Item: Downloadable<Context = Self::Context>;
     fn next_page(&mut self, context: &Self::Context) -> crate::Result<Page<Self::Item>>;
+
+    /// The number of items in the pages yet to be read, where it's known or can be estimated.
+    fn remaining(&self) -> Option<usize> {
+        None
+    }
 }
 
 pub struct UnpagedGallery<T: Downloadable> {
-    context: T::Context,
+    context: Arc<T::Context>,
     items: VecDeque<T>,
 }
 
 impl<T: Downloadable> Gallery for UnpagedGallery<T> {
+    type Context = T::Context;
     type Item = T::Output;
+    type Pending = T;
 
-    fn next(&mut self) -> Option<crate::Result<Self::Item>> {
-        let item = self.items.pop_front()?;
-        Some(item.download(&self.context))
+    fn next_pending(&mut self) -> Option<crate::Result<Self::Pending>> {
+        self.items.pop_front().map(Ok)
+    }
+
+    fn context(&self) -> &Arc<Self::Context> {
+        &self.context
     }
 
     fn advance_by(&mut self, n: usize) -> crate::Result<usize> {
@@ -201,12 +501,17 @@ impl<T: Downloadable> Gallery for UnpagedGallery<T> {
             Ok(len)
         }
     }
+
+    fn remaining(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
 }
 
 pub struct PagedGallery<T: Pager> {
-    context: T::Context,
+    context: Arc<T::Context>,
     pager: T,
     current: Page<T::Item>,
+    filter: Filter,
 }
 
 impl<T: Pager> PagedGallery<T> {
@@ -217,7 +522,7 @@ impl<T: Pager> PagedGallery<T> {
     ) -> crate::Result<usize> {
         loop {
             if self.current.is_empty() {
-                self.current = self.pager.next_page(&self.context)?;
+                self.current = self.filter.next_page(&mut self.pager, &self.context)?;
             }
 
             if self.current.len() > skip_remaining {
@@ -236,19 +541,24 @@ impl<T> Gallery for PagedGallery<T>
 where
     T: Pager,
 {
+    type Context = T::Context;
     type Item = <<T as Pager>::Item as Downloadable>::Output;
+    type Pending = T::Item;
 
-    fn next(&mut self) -> Option<crate::Result<Self::Item>> {
+    fn next_pending(&mut self) -> Option<crate::Result<Self::Pending>> {
         if self.current.is_empty() {
-            self.current = match self.pager.next_page(&self.context) {
+            self.current = match self.filter.next_page(&mut self.pager, &self.context) {
                 Ok(page) if page.is_empty() => return None,
                 Ok(page) => page,
                 Err(e) => return Some(Err(e)),
             };
         }
 
-        let item = self.current.pop()?;
-        Some(item.download(&self.context))
+        self.current.pop().map(Ok)
+    }
+
+    fn context(&self) -> &Arc<Self::Context> {
+        &self.context
     }
 
     fn advance_by(&mut self, n: usize) -> crate::Result<usize> {
@@ -256,22 +566,46 @@ where
         let skip_remaining = n;
         self.default_advance_by(skipped, skip_remaining)
     }
+
+    fn remaining(&self) -> Option<usize> {
+        Some(self.pager.remaining()? + self.current.len())
+    }
+
+    fn buffered(&self) -> Option<usize> {
+        Some(self.current.len())
+    }
 }
 
 mod prelude {
+    pub(crate) use crate::site::boxed;
     pub use crate::{
+        client::Client,
         error::{Error, ExtractionFailure, UnsupportedError},
+        filter::Post,
         gallery::{
-            Downloadable, NamedGalleryItem, Page, PagedGallery, Pager, ResponseGalleryItem,
+            Downloadable, Media, NamedGalleryItem, Page, PagedGallery, Pager, ResponseGalleryItem,
             UnpagedGallery,
         },
+        metadata::Metadata,
+        quality::{Kind, Quality, Variant},
+        retry::Retry,
+        session::Session,
+        settings::Settings,
+        site::Site,
     };
-    pub use reqwest::blocking::{Client, Response};
-    pub use std::collections::VecDeque;
+    pub use reqwest::blocking::Response;
+    pub use std::{collections::VecDeque, sync::Arc};
 
     pub static USER_AGENT: &str =
         "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/106.0.0.0 Safari/537.36";
 
+    /// A client builder with our user agent and a jar holding the site's imported cookies.
+    pub fn client_builder(settings: &Settings) -> crate::client::ClientBuilder {
+        Client::builder(settings.retry)
+            .user_agent(USER_AGENT)
+            .cookie_provider(crate::cookies::jar(settings))
+    }
+
     pub struct ImageLink(String);
 
     impl<T: Into<String>> From<T> for ImageLink {
@@ -286,7 +620,40 @@ mod prelude {
         type Output = ResponseGalleryItem;
 
         fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
-            Ok(ResponseGalleryItem::new(context.get(self.0).send()?))
+            Ok(ResponseGalleryItem::new(
+                context.get(self.0).send_with_retry()?,
+            ))
+        }
+
+        fn resolve(self, _: &Self::Context) -> crate::Result<Media> {
+            Ok(Media::new(self.0))
+        }
+
+        fn source(&self, _: &Self::Context) -> String {
+            self.0.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn sniff() {
+        let cases: &[(&[u8], Option<&str>)] = &[
+            (b"\xFF\xD8\xFF\xE0\0\x10JFIF\0", Some("jpg")),
+            (b"\x89PNG\r\n\x1A\n\0\0\0\rIHDR", Some("png")),
+            (b"RIFF\x24\x10\0\0WEBPVP8 ", Some("webp")),
+            (b"\0\0\0\x20ftypisom\0\0\x02\0", Some("mp4")),
+            (b"<!DOCTYPE html><html>", None),
+            (b"{\"success\":false}", None),
+            (b"WEBP", None),
+            (b"BM\x36\x10\x0E\0\0\0\0\0\x36\0", Some("bmp")),
+            (b"BMP is a bitmap format", None),
+            (b"BM", None),
+        ];
+
+        for &(head, expected) in cases {
+            assert_eq!(super::sniff(head), expected);
         }
     }
 }
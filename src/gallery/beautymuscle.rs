@@ -3,7 +3,17 @@ use regex::Regex;
 
 use super::prelude::*;
 
-pub fn extract(url: &str) -> crate::Result<(PagedGallery<BmPager>, Option<String>)> {
+pub static SITE: Site = Site {
+    name: "BeautyMuscle",
+    hosts: &["www.beautymuscle.net"],
+    routes: &["/pin/tag/<tag>/", "/?s=<search>"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(PagedGallery<BmPager>, Option<String>)> {
     let pattern = Regex::new(r"/pin/tag/([^/]+)/?|\?s=([^&]+)").unwrap();
     let captures = pattern.captures(url).ok_or_else(|| {
         Error::Unsupported(
@@ -22,11 +32,15 @@ pub fn extract(url: &str) -> crate::Result<(PagedGallery<BmPager>, Option<String
     // and take 1) the page count and 2) the first set of results, because
     // otherwise there's no real way to know when this pager needs to stop.
 
-    let context = Context::new();
+    let context = Context::new(settings);
     let name_from_query = query.name_from_query().map(|s| s.to_string());
     let mut pager = BmPager::new(query);
 
-    let text = context.client.get(pager.next_url()).send()?.text()?;
+    let text = context
+        .client
+        .get(pager.next_url())
+        .send_with_retry()?
+        .text()?;
     let pattern = Regex::new(r"Page \d+ of (\d+)").unwrap();
     let count = pattern
         .captures(&text)
@@ -43,8 +57,9 @@ pub fn extract(url: &str) -> crate::Result<(PagedGallery<BmPager>, Option<String
 
     let gallery = PagedGallery {
         current: context.read_thumbs(&text),
-        context,
+        context: Arc::new(context),
         pager,
+        filter: settings.filter.clone(),
     };
 
     Ok((gallery, name_from_query))
@@ -115,7 +130,11 @@ impl Pager for BmPager {
             return Ok(Page::Empty);
         }
 
-        let text = context.client.get(self.next_url()).send()?.text()?;
+        let text = context
+            .client
+            .get(self.next_url())
+            .send_with_retry()?
+            .text()?;
         Ok(context.read_thumbs(&text))
     }
 }
@@ -126,9 +145,9 @@ pub struct Context {
 }
 
 impl Context {
-    fn new() -> Self {
+    fn new(settings: &Settings) -> Self {
         Self {
-            client: Client::builder().user_agent(USER_AGENT).build().unwrap(),
+            client: client_builder(settings).build().unwrap(),
             thumbnail_size_pattern: Regex::new(r"(-\d+x\d+)\.").unwrap(),
         }
     }
@@ -167,6 +186,17 @@ impl Downloadable for Url {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
-        Ok(ResponseGalleryItem::new(context.client.get(self.0).send()?))
+        let media = self.resolve(context)?;
+        Ok(ResponseGalleryItem::new(
+            context.client.get(media.url).send_with_retry()?,
+        ))
+    }
+
+    fn resolve(self, _: &Self::Context) -> crate::Result<Media> {
+        Ok(Media::new(self.0))
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        self.0.clone()
     }
 }
@@ -4,22 +4,36 @@ use super::prelude::*;
 
 static AJAX_BASE_ROUTE: &str = "https://bitchesgirls.com/ajax/modelpage/";
 
-pub fn extract(url: &str) -> crate::Result<(PagedGallery<BitchesPager>, Option<String>)> {
-    let client = build_client(url);
+pub static SITE: Site = Site {
+    name: "BitchesGirls",
+    hosts: &["bitchesgirls.com"],
+    routes: &["/<album>/"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(PagedGallery<BitchesPager>, Option<String>)> {
+    let client = build_client(settings, url);
     let route = url
         .strip_prefix("https://bitchesgirls.com/")
         .ok_or_else(|| Error::Unsupported(UnsupportedError::Route, format!("bad url: {url}")))?;
 
     let init_url = format!("{AJAX_BASE_ROUTE}{route}1/");
-    let init_response: AlbumResponse = client.get(init_url).send()?.json()?;
-    let pager = BitchesPager::new(route, init_response.pages_amount);
+    let init_response: AlbumResponse = client.get(init_url).send_with_retry()?.json()?;
+    let pager = BitchesPager::new(
+        route,
+        init_response.pages_amount,
+        init_response.album.content.len(),
+    );
 
     Ok((
         PagedGallery {
-            context: Context {
+            context: Arc::new(Context {
                 client,
                 cdn: init_response.cdn_name,
-            },
+            }),
             current: init_response
                 .album
                 .content
@@ -27,6 +41,7 @@ pub fn extract(url: &str) -> crate::Result<(PagedGallery<BitchesPager>, Option<S
                 .map(|content| Item(content.original))
                 .collect(),
             pager,
+            filter: settings.filter.clone(),
         },
         Some(init_response.album.album_id),
     ))
@@ -48,13 +63,16 @@ impl Context {
 pub struct BitchesPager {
     route: String,
     pages: Box<dyn Iterator<Item = usize>>,
+    /// The size of a full page, which we take to be the size of the first.
+    page_size: usize,
 }
 
 impl BitchesPager {
-    fn new(route: &str, count: i64) -> Self {
+    fn new(route: &str, count: i64, page_size: usize) -> Self {
         Self {
             route: route.into(),
             pages: Box::new((1..=count).skip(1).map(|n| n as usize)),
+            page_size,
         }
     }
 }
@@ -70,7 +88,7 @@ impl Pager for BitchesPager {
             None => return Ok(Page::Empty),
         };
 
-        let response: AlbumResponse = context.client.get(url).send()?.json()?;
+        let response: AlbumResponse = context.client.get(url).send_with_retry()?.json()?;
 
         Ok(response
             .album
@@ -79,6 +97,10 @@ impl Pager for BitchesPager {
             .map(|content| Item(content.original))
             .collect())
     }
+
+    fn remaining(&self) -> Option<usize> {
+        Some(self.pages.size_hint().0 * self.page_size)
+    }
 }
 
 pub struct Item(String);
@@ -89,13 +111,21 @@ impl Downloadable for Item {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
-        let url = context.cdn_url(&self.0);
+        let media = self.resolve(context)?;
         Ok(context
             .client
-            .get(url)
-            .send()
+            .get(media.url)
+            .send_with_retry()
             .map(ResponseGalleryItem::new)?)
     }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
+        Ok(Media::new(context.cdn_url(&self.0)))
+    }
+
+    fn source(&self, context: &Self::Context) -> String {
+        context.cdn_url(&self.0)
+    }
 }
 
 #[derive(Deserialize)]
@@ -118,7 +148,7 @@ pub struct Content {
     original: String,
 }
 
-fn build_client(referer: &str) -> Client {
+fn build_client(settings: &Settings, referer: &str) -> Client {
     use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, REFERER};
 
     let mut headers = HeaderMap::new();
@@ -126,8 +156,7 @@ fn build_client(referer: &str) -> Client {
     headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
     headers.insert(REFERER, HeaderValue::from_str(referer).unwrap());
 
-    Client::builder()
-        .user_agent(USER_AGENT)
+    client_builder(settings)
         .referer(false)
         .default_headers(headers)
         .build()
@@ -0,0 +1,294 @@
+//! Engines for the booru software shared by many sites.
+//!
+//! Danbooru, Moebooru and Gelbooru each power any number of sites, which differ in little more
+//! than their host and how one signs in. Each engine takes a base url and credentials in its own
+//! style; the sites below are instances of them.
+//!
+//! Instances not listed here can be named in ~/.imgrab.conf by host, under `danbooru_hosts`,
+//! `moebooru_hosts` or `gelbooru_hosts` (separated by spaces). These are used anonymously.
+
+pub mod danbooru;
+pub mod gelbooru;
+pub mod moebooru;
+
+use std::sync::OnceLock;
+
+use regex::Regex;
+use url::Url;
+
+use crate::{
+    config::{Configuration, Key},
+    site::Extractor,
+};
+
+use super::prelude::*;
+
+pub static ALL_THE_FALLEN: Site = Site {
+    name: "All The Fallen",
+    hosts: &["booru.allthefallen.moe"],
+    routes: danbooru::ROUTES,
+    extractor: |url, settings| {
+        boxed(url, settings, |url, settings| {
+            let auth = danbooru::Auth::from_config(Key::AtfBooruUser, Key::AtfBooruApi)?;
+            danbooru::extract(url, settings, "https://booru.allthefallen.moe", Some(auth))
+        })
+    },
+};
+
+pub static BLEACHBOORU: Site = Site {
+    name: "Bleachbooru",
+    hosts: &["bleachbooru.org"],
+    routes: moebooru::ROUTES,
+    extractor: |url, settings| {
+        boxed(url, settings, |url, settings| {
+            let auth = moebooru::Auth::from_config(
+                Key::BleachUser,
+                Key::BleachPass,
+                "choujin-steiner--{}--",
+            )?;
+            moebooru::extract(url, settings, "https://bleachbooru.org", Some(auth))
+        })
+    },
+};
+
+pub static DANBOORU: Site = Site {
+    name: "Danbooru",
+    hosts: &["danbooru.donmai.us"],
+    routes: danbooru::ROUTES,
+    extractor: |url, settings| {
+        boxed(url, settings, |url, settings| {
+            danbooru::extract(url, settings, "https://danbooru.donmai.us", None)
+        })
+    },
+};
+
+pub static GELBOORU: Site = Site {
+    name: "Gelbooru",
+    hosts: &["gelbooru.com"],
+    routes: gelbooru::ROUTES,
+    extractor: |url, settings| {
+        boxed(url, settings, |url, settings| {
+            let auth = gelbooru::Auth::from_config(Key::GelbooruUser)?;
+            gelbooru::extract(url, settings, "https://gelbooru.com", Some(auth))
+        })
+    },
+};
+
+pub static KONACHAN: Site = Site {
+    name: "Konachan",
+    hosts: &["konachan.com"],
+    routes: moebooru::ROUTES,
+    extractor: |url, settings| {
+        boxed(url, settings, |url, settings| {
+            moebooru::extract(url, settings, "https://konachan.com", None)
+        })
+    },
+};
+
+pub static REALBOORU: Site = Site {
+    name: "Realbooru",
+    hosts: &["realbooru.com"],
+    routes: gelbooru::ROUTES,
+    extractor: |url, settings| {
+        boxed(url, settings, |url, settings| {
+            gelbooru::extract(url, settings, "https://realbooru.com", None)
+        })
+    },
+};
+
+pub static SAFEBOORU: Site = Site {
+    name: "Safebooru",
+    hosts: &["safebooru.org"],
+    routes: gelbooru::ROUTES,
+    extractor: |url, settings| {
+        boxed(url, settings, |url, settings| {
+            gelbooru::extract(url, settings, "https://safebooru.org", None)
+        })
+    },
+};
+
+pub static TBIB: Site = Site {
+    name: "The Big ImageBoard",
+    hosts: &["tbib.org"],
+    routes: gelbooru::ROUTES,
+    extractor: |url, settings| {
+        boxed(url, settings, |url, settings| {
+            gelbooru::extract(url, settings, "https://tbib.org", None)
+        })
+    },
+};
+
+pub static XBOORU: Site = Site {
+    name: "Xbooru",
+    hosts: &["xbooru.com"],
+    routes: gelbooru::ROUTES,
+    extractor: |url, settings| {
+        boxed(url, settings, |url, settings| {
+            gelbooru::extract(url, settings, "https://xbooru.com", None)
+        })
+    },
+};
+
+pub static YANDERE: Site = Site {
+    name: "yande.re",
+    hosts: &["yande.re"],
+    routes: moebooru::ROUTES,
+    extractor: |url, settings| {
+        boxed(url, settings, |url, settings| {
+            moebooru::extract(url, settings, "https://yande.re", None)
+        })
+    },
+};
+
+/// Instances named in the config file, each served by its own host.
+pub(crate) fn configured_sites() -> &'static [Site] {
+    static SITES: OnceLock<Vec<Site>> = OnceLock::new();
+    SITES.get_or_init(|| {
+        let config = Configuration::init();
+        let engines: [(Key, &'static [&'static str], Extractor); 3] = [
+            (Key::DanbooruHosts, danbooru::ROUTES, |url, settings| {
+                boxed(url, settings, |url, settings| {
+                    danbooru::extract(url, settings, &origin(url)?, None)
+                })
+            }),
+            (Key::GelbooruHosts, gelbooru::ROUTES, |url, settings| {
+                boxed(url, settings, |url, settings| {
+                    gelbooru::extract(url, settings, &origin(url)?, None)
+                })
+            }),
+            (Key::MoebooruHosts, moebooru::ROUTES, |url, settings| {
+                boxed(url, settings, |url, settings| {
+                    moebooru::extract(url, settings, &origin(url)?, None)
+                })
+            }),
+        ];
+
+        let mut sites = Vec::new();
+        for (key, routes, extractor) in engines {
+            let hosts = config.get_config(key).unwrap_or_default();
+            for host in hosts.split_whitespace() {
+                // These live as long as the process does, just like the sites above.
+                let host: &'static str = Box::leak(host.to_owned().into_boxed_str());
+                sites.push(Site {
+                    name: host,
+                    hosts: Box::leak(Box::new([host])),
+                    routes,
+                    extractor,
+                });
+            }
+        }
+        sites
+    })
+}
+
+/// What every engine needs to make its requests.
+pub struct Context {
+    client: Client,
+    base_url: String,
+    quality: Quality,
+}
+
+impl Context {
+    pub(crate) fn new(settings: &Settings, base_url: &str) -> Self {
+        use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+
+        Self {
+            client: client_builder(settings)
+                .default_headers(headers)
+                .build()
+                .unwrap(),
+            base_url: base_url.trim_end_matches('/').into(),
+            quality: settings.quality,
+        }
+    }
+
+    /// Makes a url served by the site absolute; some instances serve their media from
+    /// elsewhere, and some don't bother with the host.
+    fn absolute_url(&self, url: &str) -> String {
+        if url.starts_with("http") {
+            url.into()
+        } else if url.starts_with("//") {
+            format!("https:{url}")
+        } else {
+            format!("{}{}", self.base_url, url)
+        }
+    }
+}
+
+/// A variant of a post, where the site gave a url for it.
+fn variant(
+    context: &Context,
+    kind: Kind,
+    url: Option<&str>,
+    width: Option<u32>,
+    height: Option<u32>,
+) -> Option<Variant<String>> {
+    let url = url.filter(|url| !url.is_empty())?;
+    Some(Variant::new(kind, context.absolute_url(url)).with_size(width, height))
+}
+
+/// The variant of a post to download, from its original and whichever others the site gave.
+fn choose(
+    context: &Context,
+    original: Variant<String>,
+    others: impl IntoIterator<Item = Option<Variant<String>>>,
+) -> Variant<String> {
+    let variants = std::iter::once(original)
+        .chain(others.into_iter().flatten())
+        .collect();
+    context.quality.choose(variants).unwrap()
+}
+
+fn origin(url: &str) -> crate::Result<String> {
+    Ok(Url::parse(url)?.origin().ascii_serialization())
+}
+
+fn read_tags(url: &str) -> crate::Result<&str> {
+    let pattern = Regex::new(r#"tags=([^&]+)"#).unwrap();
+    Ok(pattern
+        .captures(url)
+        .ok_or_else(|| Error::Unsupported(UnsupportedError::Route, url.into()))?
+        .get(1)
+        .unwrap()
+        .as_str())
+}
+
+fn get_single_tag(tags: &str) -> Option<&str> {
+    let mut tags = tags.split('+');
+    let single = tags.next();
+    single.filter(|_| tags.next().is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Settings;
+
+    #[test]
+    fn can_read_tags() -> crate::Result<()> {
+        let url = "https://gelbooru.com/index.php?page=post&s=list&tags=text+tags";
+        assert_eq!("text+tags", super::read_tags(url)?);
+        assert_eq!(None, super::get_single_tag("text+tags"));
+        assert_eq!(Some("text"), super::get_single_tag("text"));
+        Ok(())
+    }
+
+    #[test]
+    fn makes_urls_absolute() {
+        let context = super::Context::new(&Settings::default(), "https://bleachbooru.org/");
+        assert_eq!(
+            "https://bleachbooru.org/data/image/ab.jpg",
+            context.absolute_url("/data/image/ab.jpg")
+        );
+        assert_eq!(
+            "https://files.yande.re/image/ab.jpg",
+            context.absolute_url("https://files.yande.re/image/ab.jpg")
+        );
+        assert_eq!(
+            "https://img.tbib.org/images/ab.jpg",
+            context.absolute_url("//img.tbib.org/images/ab.jpg")
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Danbooru and its descendants, which serve posts as json from /posts.json.
+//!
+//! Credentials are optional on most instances: a user name and api key, sent as basic auth.
+//! Posts a visitor isn't allowed to see are listed without a file url, and we pass over them.
+//! Samples are 850 pixels wide, and exist only for originals wider than that.
+
+use serde::Deserialize;
+
+use crate::{
+    config::{Configuration, Key},
+    gallery::prelude::*,
+};
+
+use super::Context;
+
+pub static ROUTES: &[&str] = &["/posts?tags=<tags>"];
+
+pub struct Auth {
+    user: String,
+    api_key: String,
+}
+
+impl Auth {
+    pub fn from_config(user: Key, api_key: Key) -> crate::Result<Self> {
+        let config = Configuration::init();
+        Ok(Self {
+            user: config.get_config(user)?.into(),
+            api_key: config.get_config(api_key)?.into(),
+        })
+    }
+}
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+    base_url: &str,
+    auth: Option<Auth>,
+) -> crate::Result<(PagedGallery<DanbooruPager>, Option<String>)> {
+    let tags = super::read_tags(url)?;
+    let gallery = PagedGallery {
+        context: Arc::new(Context::new(settings, base_url)),
+        pager: DanbooruPager {
+            auth,
+            tags: tags.into(),
+            page: 1,
+            is_complete: false,
+        },
+        current: Page::Empty,
+        filter: settings.filter.clone(),
+    };
+
+    Ok((gallery, super::get_single_tag(tags).map(Into::into)))
+}
+
+pub struct DanbooruPager {
+    auth: Option<Auth>,
+    tags: String,
+    page: usize,
+    is_complete: bool,
+}
+
+impl Pager for DanbooruPager {
+    type Context = Context;
+
+    type Item = Image;
+
+    fn next_page(&mut self, context: &Self::Context) -> crate::Result<Page<Self::Item>> {
+        // A page of posts we can't see is still a page; it isn't the end of the gallery.
+        while !self.is_complete {
+            let url = format!(
+                "{}/posts.json?limit=100&page={}&tags={}",
+                context.base_url, self.page, self.tags
+            );
+            self.page += 1;
+
+            let mut request = context.client.get(url);
+            if let Some(auth) = &self.auth {
+                request = request.basic_auth(&auth.user, Some(&auth.api_key));
+            }
+
+            let images: VecDeque<Image> = request.send_with_retry()?.json()?;
+            self.is_complete = images.is_empty();
+
+            let page: Page<_> = images
+                .into_iter()
+                .filter(|image| !image.file_url.is_empty())
+                .collect();
+            if !page.is_empty() {
+                return Ok(page);
+            }
+        }
+
+        Ok(Page::Empty)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Image {
+    id: Option<i64>,
+    #[serde(default)]
+    file_url: String,
+    large_file_url: Option<String>,
+    preview_file_url: Option<String>,
+    has_large: Option<bool>,
+    #[serde(default)]
+    tag_string: String,
+    tag_string_artist: Option<String>,
+    rating: Option<String>,
+    md5: Option<String>,
+    image_width: Option<u32>,
+    image_height: Option<u32>,
+    created_at: Option<String>,
+    score: Option<i64>,
+}
+
+impl Image {
+    fn variant(&self, context: &Context) -> Variant<String> {
+        const SAMPLE_WIDTH: u32 = 850;
+
+        let original = Variant::new(Kind::Original, context.absolute_url(&self.file_url))
+            .with_size(self.image_width, self.image_height);
+        let sample_url = self
+            .large_file_url
+            .as_deref()
+            .filter(|_| self.has_large == Some(true));
+        let sample_height = self
+            .image_width
+            .zip(self.image_height)
+            // Flash and deleted posts may report no size at all.
+            .filter(|&(width, _)| width > 0)
+            .map(|(width, height)| (height as u64 * SAMPLE_WIDTH as u64 / width as u64) as u32);
+
+        super::choose(
+            context,
+            original,
+            [
+                super::variant(
+                    context,
+                    Kind::Sample,
+                    sample_url,
+                    Some(SAMPLE_WIDTH),
+                    sample_height,
+                ),
+                super::variant(
+                    context,
+                    Kind::Preview,
+                    self.preview_file_url.as_deref(),
+                    None,
+                    None,
+                ),
+            ],
+        )
+    }
+}
+
+impl Downloadable for Image {
+    type Context = Context;
+
+    type Output = ResponseGalleryItem;
+
+    fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
+        let media = self.resolve(context)?;
+        Ok(context
+            .client
+            .get(media.url)
+            .send_with_retry()
+            .map(ResponseGalleryItem::new)?
+            .with_metadata(media.metadata))
+    }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
+        let variant = self.variant(context);
+        let metadata = Metadata {
+            id: self.id.map(|id| id.to_string()),
+            source: self.id.map(|id| format!("{}/posts/{id}", context.base_url)),
+            tags: Metadata::split_tags(&self.tag_string),
+            rating: self.rating,
+            artist: self.tag_string_artist.filter(|artist| !artist.is_empty()),
+            width: variant.width,
+            height: variant.height,
+            md5: self.md5.filter(|_| variant.is_original()),
+            uploaded: self.created_at,
+            ..Default::default()
+        };
+        Ok(Media::new(variant.value).with_metadata(metadata))
+    }
+
+    fn source(&self, context: &Self::Context) -> String {
+        context.absolute_url(&self.file_url)
+    }
+
+    fn md5(&self, context: &Self::Context) -> Option<String> {
+        self.md5
+            .clone()
+            .filter(|_| self.variant(context).is_original())
+    }
+
+    fn post(&self, _: &Self::Context) -> Option<Post<'_>> {
+        Some(Post {
+            rating: self.rating.as_deref(),
+            width: self.image_width,
+            height: self.image_height,
+            score: self.score,
+            ..Post::with_tags(&self.tag_string).with_file_url(&self.file_url)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{quality::Quality, Settings};
+
+    use super::{Context, Image};
+
+    #[test]
+    fn tolerates_posts_without_size() {
+        let image: Image = serde_json::from_str(
+            r#"{"id":1,"file_url":"https://cdn.donmai.us/original/ab/cd/abcd.swf","large_file_url":"https://cdn.donmai.us/original/ab/cd/abcd.swf","has_large":true,"image_width":0,"image_height":0}"#,
+        )
+        .unwrap();
+        let context = Context::new(&Settings::default(), "https://danbooru.donmai.us");
+        assert_eq!(
+            "https://cdn.donmai.us/original/ab/cd/abcd.swf",
+            image.variant(&context).value
+        );
+    }
+
+    #[test]
+    fn each_gallery_chooses_by_its_own_quality() {
+        let image: Image = serde_json::from_str(
+            r#"{"id":1,"file_url":"https://cdn.donmai.us/original/ab/cd/abcd.jpg","large_file_url":"https://cdn.donmai.us/sample/ab/cd/sample-abcd.jpg","has_large":true,"image_width":1700,"image_height":2000}"#,
+        )
+        .unwrap();
+        let original = Context::new(&Settings::default(), "https://danbooru.donmai.us");
+        let sample = Context::new(
+            &Settings {
+                quality: Quality::Sample,
+                ..Default::default()
+            },
+            "https://danbooru.donmai.us",
+        );
+        assert_eq!(
+            "https://cdn.donmai.us/original/ab/cd/abcd.jpg",
+            image.variant(&original).value
+        );
+        assert_eq!(
+            "https://cdn.donmai.us/sample/ab/cd/sample-abcd.jpg",
+            image.variant(&sample).value
+        );
+    }
+}
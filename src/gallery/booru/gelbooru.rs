@@ -0,0 +1,281 @@
+//! The Gelbooru "DAPI", served from /index.php?page=dapi by Gelbooru and its many forks.
+//!
+//! Gelbooru itself wraps its posts in an object, while older forks return a bare array (or,
+//! given a search with no results, nothing at all). Older forks also leave out the file url, in
+//! which case it's put together from the post's directory and image name.
+
+use serde::Deserialize;
+
+use crate::{
+    config::{Configuration, Key},
+    gallery::prelude::*,
+};
+
+use super::Context;
+
+pub static ROUTES: &[&str] = &["/index.php?page=post&s=list&tags=<tags>"];
+
+pub struct Auth {
+    user_id: String,
+}
+
+impl Auth {
+    pub fn from_config(user_id: Key) -> crate::Result<Self> {
+        Ok(Self {
+            user_id: Configuration::init().get_config(user_id)?.into(),
+        })
+    }
+}
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+    base_url: &str,
+    auth: Option<Auth>,
+) -> crate::Result<(PagedGallery<GelbooruPager>, Option<String>)> {
+    // The user-supplied URL will presumably be copied from the web interface, but we are
+    // really not interested in the url itself. We pretty much only want the search tags.
+    let tags = super::read_tags(url)?;
+    let gallery = PagedGallery {
+        context: Arc::new(Context::new(settings, base_url)),
+        pager: GelbooruPager {
+            auth,
+            tags: tags.into(),
+            page: 0,
+            is_complete: false,
+        },
+        current: Page::Empty,
+        filter: settings.filter.clone(),
+    };
+
+    Ok((gallery, super::get_single_tag(tags).map(Into::into)))
+}
+
+pub struct GelbooruPager {
+    auth: Option<Auth>,
+    tags: String,
+    page: usize,
+    is_complete: bool,
+}
+
+impl GelbooruPager {
+    // I originally had a custom Serialize implementation for the request. That did not work
+    // because it automatically escapes plus signs, which must be retained intact in order for
+    // the damned API to work.
+    fn format_request(&self, base_url: &str) -> String {
+        let mut url = format!(
+            "{base_url}/index.php?page=dapi&s=post&q=index&limit=100&tags={}&pid={}&json=1",
+            self.tags, self.page
+        );
+        if let Some(auth) = &self.auth {
+            url += &format!("&api_key=anonymous&user_id={}", auth.user_id);
+        }
+        url
+    }
+}
+
+impl Pager for GelbooruPager {
+    type Context = Context;
+
+    type Item = Image;
+
+    fn next_page(&mut self, context: &Self::Context) -> crate::Result<Page<Self::Item>> {
+        if self.is_complete {
+            return Ok(Page::Empty);
+        }
+
+        let request = self.format_request(&context.base_url);
+        self.page += 1;
+
+        let text = context.client.get(request).send_with_retry()?.text()?;
+        match read_posts(&text)? {
+            Some(posts) if !posts.is_empty() => Ok(Page::Items(posts)),
+            _ => {
+                self.is_complete = true;
+                Ok(Page::Empty)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Response {
+    Wrapped {
+        #[serde(rename = "post")]
+        posts: Option<VecDeque<Image>>,
+    },
+    Bare(VecDeque<Image>),
+}
+
+pub(crate) fn read_posts(text: &str) -> crate::Result<Option<VecDeque<Image>>> {
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let response = serde_json::from_str(text)
+        .map_err(|e| Error::Extraction(ExtractionFailure::Metadata, e.to_string()))?;
+    Ok(match response {
+        Response::Wrapped { posts } => posts,
+        Response::Bare(posts) => Some(posts),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Image {
+    id: Option<i64>,
+    #[serde(default)]
+    file_url: String,
+    directory: Option<serde_json::Value>,
+    image: Option<String>,
+    sample_url: Option<String>,
+    sample_width: Option<u32>,
+    sample_height: Option<u32>,
+    preview_url: Option<String>,
+    preview_width: Option<u32>,
+    preview_height: Option<u32>,
+    #[serde(default)]
+    tags: String,
+    rating: Option<String>,
+    #[serde(alias = "hash")]
+    md5: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    owner: Option<String>,
+    score: Option<i64>,
+}
+
+impl Image {
+    pub fn id(&self) -> Option<i64> {
+        self.id
+    }
+
+    fn media_url(&self, context: &Context) -> String {
+        if !self.file_url.is_empty() {
+            return context.absolute_url(&self.file_url);
+        }
+
+        let directory = match &self.directory {
+            Some(serde_json::Value::String(directory)) => directory.clone(),
+            Some(directory) => directory.to_string(),
+            None => String::new(),
+        };
+        let image = self.image.as_deref().unwrap_or_default();
+        format!("{}/images/{directory}/{image}", context.base_url)
+    }
+
+    fn variant(&self, context: &Context) -> Variant<String> {
+        let original = Variant::new(Kind::Original, self.media_url(context))
+            .with_size(self.width, self.height);
+        // Where there's no sample, some forks report its size as zero.
+        let sample_width = self.sample_width.filter(|&width| width > 0);
+        let sample_height = self.sample_height.filter(|&height| height > 0);
+        super::choose(
+            context,
+            original,
+            [
+                super::variant(
+                    context,
+                    Kind::Sample,
+                    self.sample_url
+                        .as_deref()
+                        .filter(|_| sample_width.is_some()),
+                    sample_width,
+                    sample_height,
+                ),
+                super::variant(
+                    context,
+                    Kind::Preview,
+                    self.preview_url.as_deref(),
+                    self.preview_width,
+                    self.preview_height,
+                ),
+            ],
+        )
+    }
+}
+
+impl Downloadable for Image {
+    type Context = Context;
+
+    type Output = ResponseGalleryItem;
+
+    fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
+        let media = self.resolve(context)?;
+        Ok(context
+            .client
+            .get(media.url)
+            .send_with_retry()
+            .map(ResponseGalleryItem::new)?
+            .with_metadata(media.metadata))
+    }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
+        let variant = self.variant(context);
+        let metadata = Metadata {
+            id: self.id.map(|id| id.to_string()),
+            source: self
+                .id
+                .map(|id| format!("{}/index.php?page=post&s=view&id={id}", context.base_url)),
+            tags: Metadata::split_tags(&self.tags),
+            rating: self.rating,
+            uploader: self.owner,
+            width: variant.width,
+            height: variant.height,
+            md5: self.md5.filter(|_| variant.is_original()),
+            ..Default::default()
+        };
+        Ok(Media::new(variant.value).with_metadata(metadata))
+    }
+
+    fn source(&self, context: &Self::Context) -> String {
+        self.media_url(context)
+    }
+
+    fn md5(&self, context: &Self::Context) -> Option<String> {
+        self.md5
+            .clone()
+            .filter(|_| self.variant(context).is_original())
+    }
+
+    fn post(&self, _: &Self::Context) -> Option<Post<'_>> {
+        let post = Post {
+            rating: self.rating.as_deref(),
+            width: self.width,
+            height: self.height,
+            score: self.score,
+            ..Post::with_tags(&self.tags)
+        };
+        match self.image.as_deref() {
+            Some(image) if self.file_url.is_empty() => Some(post.with_file_url(image)),
+            _ => Some(post.with_file_url(&self.file_url)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Settings;
+
+    use super::Context;
+
+    #[test]
+    fn can_read_either_response() -> crate::Result<()> {
+        let wrapped = r#"{"@attributes":{"limit":100,"offset":0,"count":1},"post":[{"id":1,"file_url":"https://img3.gelbooru.com/images/ab/cd/abcd.png","md5":"abcd","tags":"a b"}]}"#;
+        let posts = super::read_posts(wrapped)?.unwrap();
+        assert_eq!(Some("abcd"), posts[0].md5.as_deref());
+
+        let bare = r#"[{"id":2,"directory":"3779","image":"ef01.jpg","hash":"ef01","tags":"c","score":null}]"#;
+        let posts = super::read_posts(bare)?.unwrap();
+        let context = Context::new(&Settings::default(), "https://safebooru.org");
+        assert_eq!(Some("ef01"), posts[0].md5.as_deref());
+        assert_eq!(
+            "https://safebooru.org/images/3779/ef01.jpg",
+            posts[0].media_url(&context)
+        );
+
+        assert!(super::read_posts("")?.is_none());
+        assert!(super::read_posts(r#"{"@attributes":{"count":0}}"#)?.is_none());
+        Ok(())
+    }
+}
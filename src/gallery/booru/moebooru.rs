@@ -0,0 +1,243 @@
+//! Moebooru, which serves posts as json from /post.json.
+//!
+//! Changing /post.xml to /post.json is the only way to get json; the api doesn't respect the
+//! accept header. Apparently one may log in using a username and *hashed password,* passed as
+//! query parameters. The "hash" is the sha1 hash of the password and a salt, of a form that
+//! varies by site, e.g.
+//!
+//! choujin-steiner--<password>--
+//!
+//! ...which is the most insane fucking thing I've ever heard.
+//!
+//! Media urls are relative on some instances (e.g. bleachbooru, where preview and image links
+//! are the same except for "preview" and "image") and absolute on others.
+
+use serde::Deserialize;
+
+use crate::{
+    config::{Configuration, Key},
+    gallery::prelude::*,
+};
+
+use super::Context;
+
+pub static ROUTES: &[&str] = &["/post?tags=<tags>"];
+
+pub struct Auth {
+    username: String,
+    password_hash: String,
+}
+
+impl Auth {
+    /// Reads credentials from the config file, hashing the password with the site's salt, in
+    /// which `{}` stands for the password.
+    pub fn from_config(username: Key, password: Key, salt: &str) -> crate::Result<Self> {
+        let config = Configuration::init();
+        Ok(Self {
+            username: config.get_config(username)?.into(),
+            password_hash: apply_salt_and_hash(config.get_config(password)?, salt),
+        })
+    }
+}
+
+fn apply_salt_and_hash(password: &str, salt: &str) -> String {
+    let mut m = sha1_smol::Sha1::new();
+    m.update(salt.replace("{}", password).as_bytes());
+    m.digest().to_string()
+}
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+    base_url: &str,
+    auth: Option<Auth>,
+) -> crate::Result<(PagedGallery<MoebooruPager>, Option<String>)> {
+    let tags = super::read_tags(url)?;
+    let gallery = PagedGallery {
+        context: Arc::new(Context::new(settings, base_url)),
+        pager: MoebooruPager {
+            auth,
+            tags: tags.into(),
+            page: 1,
+            is_complete: false,
+        },
+        current: Page::Empty,
+        filter: settings.filter.clone(),
+    };
+
+    Ok((gallery, super::get_single_tag(tags).map(Into::into)))
+}
+
+pub struct MoebooruPager {
+    auth: Option<Auth>,
+    tags: String,
+    page: usize,
+    is_complete: bool,
+}
+
+impl Pager for MoebooruPager {
+    type Context = Context;
+
+    type Item = Image;
+
+    fn next_page(&mut self, context: &Self::Context) -> crate::Result<Page<Self::Item>> {
+        if self.is_complete {
+            return Ok(Page::Empty);
+        }
+
+        let mut url = format!(
+            "{}/post.json?limit=100&page={}&tags={}",
+            context.base_url, self.page, self.tags
+        );
+        if let Some(Auth {
+            username,
+            password_hash,
+        }) = &self.auth
+        {
+            url += &format!("&username={username}&password_hash={password_hash}");
+        }
+        self.page += 1;
+
+        let images: VecDeque<Image> = context.client.get(url).send_with_retry()?.json()?;
+        if !images.is_empty() {
+            Ok(Page::Items(images))
+        } else {
+            self.is_complete = true;
+            Ok(Page::Empty)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Image {
+    id: Option<i64>,
+    #[serde(default)]
+    tags: String,
+    author: Option<String>,
+    score: Option<i64>,
+    md5: Option<String>,
+    // file_size: i64,
+    file_url: String,
+    sample_url: Option<String>,
+    sample_width: Option<u32>,
+    sample_height: Option<u32>,
+    preview_url: Option<String>,
+    // preview_width and preview_height are what the browser is told to scale it to.
+    actual_preview_width: Option<u32>,
+    actual_preview_height: Option<u32>,
+    rating: Option<String>,
+    // status: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    created_at: Option<i64>,
+}
+
+impl Image {
+    fn variant(&self, context: &Context) -> Variant<String> {
+        let original = Variant::new(Kind::Original, context.absolute_url(&self.file_url))
+            .with_size(self.width, self.height);
+        super::choose(
+            context,
+            original,
+            [
+                super::variant(
+                    context,
+                    Kind::Sample,
+                    self.sample_url.as_deref(),
+                    self.sample_width,
+                    self.sample_height,
+                ),
+                super::variant(
+                    context,
+                    Kind::Preview,
+                    self.preview_url.as_deref(),
+                    self.actual_preview_width,
+                    self.actual_preview_height,
+                ),
+            ],
+        )
+    }
+}
+
+impl Downloadable for Image {
+    type Context = Context;
+
+    type Output = ResponseGalleryItem;
+
+    fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
+        let media = self.resolve(context)?;
+        Ok(context
+            .client
+            .get(media.url)
+            .send_with_retry()
+            .map(ResponseGalleryItem::new)?
+            .with_metadata(media.metadata))
+    }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
+        let variant = self.variant(context);
+        let metadata = Metadata {
+            id: self.id.map(|id| id.to_string()),
+            source: self
+                .id
+                .map(|id| format!("{}/post/show/{id}", context.base_url)),
+            tags: Metadata::split_tags(&self.tags),
+            rating: self.rating,
+            uploader: self.author,
+            width: variant.width,
+            height: variant.height,
+            md5: self.md5.filter(|_| variant.is_original()),
+            uploaded: self.created_at.and_then(Metadata::timestamp),
+            ..Default::default()
+        };
+        Ok(Media::new(variant.value).with_metadata(metadata))
+    }
+
+    fn source(&self, context: &Self::Context) -> String {
+        context.absolute_url(&self.file_url)
+    }
+
+    fn md5(&self, context: &Self::Context) -> Option<String> {
+        self.md5
+            .clone()
+            .filter(|_| self.variant(context).is_original())
+    }
+
+    fn post(&self, _: &Self::Context) -> Option<Post<'_>> {
+        Some(Post {
+            rating: self.rating.as_deref(),
+            width: self.width,
+            height: self.height,
+            score: self.score,
+            ..Post::with_tags(&self.tags).with_file_url(&self.file_url)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{gallery::Downloadable, Settings};
+
+    #[test]
+    fn source_is_the_post() -> crate::Result<()> {
+        let image: super::Image = serde_json::from_str(
+            r#"{"id":42,"source":"https://twitter.com/someone","file_url":"/data/image/ab.jpg"}"#,
+        )
+        .unwrap();
+        let context = super::Context::new(&Settings::default(), "https://bleachbooru.org");
+        let metadata = image.resolve(&context)?.metadata.unwrap();
+        assert_eq!(
+            Some("https://bleachbooru.org/post/show/42"),
+            metadata.source.as_deref()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn salts_and_hashes_password() {
+        assert_eq!(
+            "1fc0adf8544b5cb927ac1895f8e67c042e6e8dba",
+            super::apply_salt_and_hash("hunter2", "choujin-steiner--{}--")
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! Galleries with their types erased, for when the site isn't known until runtime.
+//!
+//! Every extractor produces its own gallery type, which is great until you need to decide
+//! which extractor to call based on a url. A [`BoxedGallery`] can hold any of them. Pending
+//! items carry a handle to their gallery's context, so the boxed gallery and its items need
+//! no context of their own.
+
+use std::{io::Write, sync::Arc};
+
+use crate::{metadata::Metadata, storage::NameContext};
+
+use super::{Downloadable, Gallery, GalleryItem, Media};
+
+pub struct BoxedGallery {
+    gallery: Box<dyn DynGallery>,
+    context: Arc<()>,
+}
+
+impl BoxedGallery {
+    pub fn new<T>(gallery: T) -> Self
+    where
+        T: Gallery + 'static,
+        T::Context: Send + Sync + 'static,
+        T::Pending: Send + 'static,
+        T::Item: 'static,
+    {
+        Self {
+            gallery: Box::new(gallery),
+            context: Arc::new(()),
+        }
+    }
+}
+
+impl Gallery for BoxedGallery {
+    type Context = ();
+    type Item = BoxedItem;
+    type Pending = BoxedPending;
+
+    fn next_pending(&mut self) -> Option<crate::Result<Self::Pending>> {
+        self.gallery.next_pending()
+    }
+
+    fn context(&self) -> &Arc<Self::Context> {
+        &self.context
+    }
+
+    fn advance_by(&mut self, n: usize) -> crate::Result<usize> {
+        self.gallery.advance_by(n)
+    }
+
+    fn metadata(&self) -> Option<&Metadata> {
+        self.gallery.metadata()
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        self.gallery.remaining()
+    }
+
+    fn buffered(&self) -> Option<usize> {
+        self.gallery.buffered()
+    }
+}
+
+pub struct BoxedPending(Box<dyn DynPending>);
+
+impl Downloadable for BoxedPending {
+    type Context = ();
+    type Output = BoxedItem;
+
+    fn download(self, _: &Self::Context) -> crate::Result<Self::Output> {
+        self.0.download()
+    }
+
+    fn resolve(self, _: &Self::Context) -> crate::Result<Media> {
+        self.0.resolve()
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        self.0.source()
+    }
+
+    fn md5(&self, _: &Self::Context) -> Option<String> {
+        self.0.md5()
+    }
+}
+
+pub struct BoxedItem(Box<dyn DynItem>);
+
+impl GalleryItem for BoxedItem {
+    fn context(&self) -> NameContext<'_> {
+        self.0.context()
+    }
+
+    fn validate(&mut self) -> crate::Result<()> {
+        self.0.validate()
+    }
+
+    fn write<W: Write + ?Sized>(self, mut writer: &mut W) -> crate::Result<u64> {
+        self.0.write(&mut writer)
+    }
+
+    fn metadata(&self) -> Option<&Metadata> {
+        self.0.metadata()
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.0.content_length()
+    }
+}
+
+trait DynGallery {
+    fn next_pending(&mut self) -> Option<crate::Result<BoxedPending>>;
+    fn advance_by(&mut self, n: usize) -> crate::Result<usize>;
+    fn metadata(&self) -> Option<&Metadata>;
+    fn remaining(&self) -> Option<usize>;
+    fn buffered(&self) -> Option<usize>;
+}
+
+impl<T> DynGallery for T
+where
+    T: Gallery,
+    T::Context: Send + Sync + 'static,
+    T::Pending: Send + 'static,
+    T::Item: 'static,
+{
+    fn next_pending(&mut self) -> Option<crate::Result<BoxedPending>> {
+        let pending = Gallery::next_pending(self)?;
+        let context = Gallery::context(self).clone();
+        Some(pending.map(|pending| BoxedPending(Box::new(WithContext { pending, context }))))
+    }
+
+    fn advance_by(&mut self, n: usize) -> crate::Result<usize> {
+        Gallery::advance_by(self, n)
+    }
+
+    fn metadata(&self) -> Option<&Metadata> {
+        Gallery::metadata(self)
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        Gallery::remaining(self)
+    }
+
+    fn buffered(&self) -> Option<usize> {
+        Gallery::buffered(self)
+    }
+}
+
+trait DynPending: Send {
+    fn download(self: Box<Self>) -> crate::Result<BoxedItem>;
+    fn resolve(self: Box<Self>) -> crate::Result<Media>;
+    fn source(&self) -> String;
+    fn md5(&self) -> Option<String>;
+}
+
+struct WithContext<T: Downloadable> {
+    pending: T,
+    context: Arc<T::Context>,
+}
+
+impl<T> DynPending for WithContext<T>
+where
+    T: Downloadable + Send,
+    T::Context: Send + Sync,
+    T::Output: 'static,
+{
+    fn download(self: Box<Self>) -> crate::Result<BoxedItem> {
+        let item = self.pending.download(&self.context)?;
+        Ok(BoxedItem(Box::new(item)))
+    }
+
+    fn resolve(self: Box<Self>) -> crate::Result<Media> {
+        self.pending.resolve(&self.context)
+    }
+
+    fn source(&self) -> String {
+        self.pending.source(&self.context)
+    }
+
+    fn md5(&self) -> Option<String> {
+        self.pending.md5(&self.context)
+    }
+}
+
+trait DynItem {
+    fn context(&self) -> NameContext<'_>;
+    fn validate(&mut self) -> crate::Result<()>;
+    fn write(self: Box<Self>, writer: &mut dyn Write) -> crate::Result<u64>;
+    fn metadata(&self) -> Option<&Metadata>;
+    fn content_length(&self) -> Option<u64>;
+}
+
+impl<T: GalleryItem> DynItem for T {
+    fn context(&self) -> NameContext<'_> {
+        GalleryItem::context(self)
+    }
+
+    fn validate(&mut self) -> crate::Result<()> {
+        GalleryItem::validate(self)
+    }
+
+    fn write(self: Box<Self>, writer: &mut dyn Write) -> crate::Result<u64> {
+        GalleryItem::write(*self, writer)
+    }
+
+    fn metadata(&self) -> Option<&Metadata> {
+        GalleryItem::metadata(self)
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        GalleryItem::content_length(self)
+    }
+}
@@ -3,17 +3,30 @@ use std::{collections::HashMap, ops::Not};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
-use crate::config::{Configuration, Key};
+use crate::{
+    config::{Configuration, Key},
+    cookies,
+    session::{self, SavedLogin},
+};
 
 use super::{prelude::*, Gallery};
 
-pub fn extract(url: &str) -> crate::Result<(EHentaiGallery, Option<String>)> {
+pub static SITE: Site = Site {
+    name: "E-Hentai",
+    hosts: &["e-hentai.org"],
+    routes: &["/g/<gallery id>/<token>/"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+/// Logins are saved under e-hentai's name; exhentai signs in through the same forum.
+pub(super) static LOGIN_KEY: &str = "e-hentai.org";
+
+pub fn extract(url: &str, settings: &Settings) -> crate::Result<(EHentaiGallery, Option<String>)> {
     // First thing first: we have to log in to get full-size images.
 
-    let config = Configuration::init();
-    let username = config.get_config(Key::EHentaiUser)?;
-    let password = config.get_config(Key::EHentaiPass)?;
-    let client = configure_client(username, password)?;
+    static SESSION: Session<(HashMap<String, String>, bool)> = Session::new();
+    let (login, restored) = SESSION.get_or_try_init(|| restore_or_log_in(settings))?;
+    let mut client = build_client(settings, login);
 
     // Next, hit the API for gallery metadata. This metadata is almost completely useless, but it
     // gives us the page count without a lot of rigamarole.
@@ -47,9 +60,18 @@ pub fn extract(url: &str) -> crate::Result<(EHentaiGallery, Option<String>)> {
 
     #[derive(Deserialize)]
     pub struct Gmetadatum {
+        gid: i64,
         title: String,
         title_jpn: String,
         filecount: String,
+        #[serde(default)]
+        uploader: String,
+        #[serde(default)]
+        posted: String,
+        #[serde(default)]
+        rating: String,
+        #[serde(default)]
+        tags: Vec<String>,
 
         // No clue what we're ever gonna do with this, but I want to keep it around....
         #[allow(unused)]
@@ -65,6 +87,25 @@ pub fn extract(url: &str) -> crate::Result<(EHentaiGallery, Option<String>)> {
                 .or_else(|| self.title_jpn.is_empty().not().then_some(&*self.title_jpn))
                 .map(|x| x.into())
         }
+
+        fn metadata(&self, url: &str) -> Metadata {
+            let non_empty = |s: &str| s.is_empty().not().then(|| s.to_owned());
+            Metadata {
+                id: Some(self.gid.to_string()),
+                source: Some(url.into()),
+                title: self.auto_name(),
+                tags: self.tags.clone(),
+                rating: non_empty(&self.rating),
+                artist: self
+                    .tags
+                    .iter()
+                    .find_map(|tag| tag.strip_prefix("artist:"))
+                    .map(Into::into),
+                uploader: non_empty(&self.uploader),
+                uploaded: self.posted.parse().ok().and_then(Metadata::timestamp),
+                ..Default::default()
+            }
+        }
     }
 
     let url_expr = Regex::new(r#"/g/(\d+)/(\w+)/?"#).unwrap();
@@ -76,7 +117,11 @@ pub fn extract(url: &str) -> crate::Result<(EHentaiGallery, Option<String>)> {
         })
         .ok_or_else(|| Error::Extraction(ExtractionFailure::Metadata, url.into()))?;
     let request = ApiRequest::new(gid, gtoken);
-    let response: ApiResponse = client.post(API_URL).json(&request).send()?.json()?;
+    let response: ApiResponse = client
+        .post(API_URL)
+        .json(&request)
+        .send_with_retry()?
+        .json()?;
     let meta = &response.gmetadata[0];
 
     // Are you fucking shitting me?
@@ -85,15 +130,28 @@ pub fn extract(url: &str) -> crate::Result<(EHentaiGallery, Option<String>)> {
         .parse()
         .map_err(|_| Error::Extraction(ExtractionFailure::Metadata, url.into()))?;
 
-    let page_content = client.get(url).send()?.text()?;
-    let image_page_pattern = Regex::new(r#"https://e-hentai.org/s/[^"]+"#).unwrap();
-    let queue: Page<_> = image_page_pattern
-        .captures_iter(&page_content)
-        .map(|s| EhentaiUrl(s.get(0).unwrap().as_str().into()))
-        .collect();
+    let read_image_pages = |client: &Client| -> crate::Result<Page<EhentaiUrl>> {
+        let page_content = client.get(url).send_with_retry()?.text()?;
+        let image_page_pattern = Regex::new(r#"https://e-hentai.org/s/[^"]+"#).unwrap();
+        Ok(image_page_pattern
+            .captures_iter(&page_content)
+            .map(|s| EhentaiUrl(s.get(0).unwrap().as_str().into()))
+            .collect())
+    };
+
+    // A saved login the site no longer honors gets us an empty page rather than an error, so
+    // that's our cue to sign in again and take another look.
+    let mut queue = read_image_pages(&client)?;
+    if queue.is_empty() && gallery_size > 0 && restored {
+        SESSION.reset();
+        SavedLogin::forget(LOGIN_KEY);
+        let (login, _) = SESSION.get_or_try_init(|| restore_or_log_in(settings))?;
+        client = build_client(settings, login);
+        queue = read_image_pages(&client)?;
+    }
 
     let gallery = EHentaiGallery {
-        context: Context::with_client(client),
+        context: Arc::new(Context::new(client, settings.quality)),
         pager: EhentaiPager {
             base_url: url.into(),
             page: 1,
@@ -101,6 +159,7 @@ pub fn extract(url: &str) -> crate::Result<(EHentaiGallery, Option<String>)> {
             total_count: gallery_size,
         },
         current: queue,
+        metadata: meta.metadata(url),
     };
 
     Ok((gallery, meta.auto_name()))
@@ -137,7 +196,7 @@ impl Pager for EhentaiPager {
 
         let url = format!("{}?p={}", self.base_url, self.page);
         self.page += 1;
-        let text = context.client.get(url).send()?.text()?;
+        let text = context.client.get(url).send_with_retry()?.text()?;
         let page: Page<_> = context
             .page_url_pattern
             .find_iter(&text)
@@ -150,40 +209,66 @@ impl Pager for EhentaiPager {
 
 pub struct Context {
     client: Client,
+    quality: Quality,
     page_url_pattern: Regex,
     full_size_pattern: Regex,
     thumbnail_pattern: Regex,
+    thumbnail_size_pattern: Regex,
 }
 
 impl Context {
-    fn with_client(client: Client) -> Self {
+    fn new(client: Client, quality: Quality) -> Self {
         Self {
             client,
+            quality,
             page_url_pattern: Regex::new(r#"https://e-hentai.org/s/[^"]+"#).unwrap(),
             thumbnail_pattern: Regex::new(r#"id="img" src="([^"]+)"#).unwrap(),
-            full_size_pattern: Regex::new(r#"<a href="([^"]+)">Download original"#).unwrap(),
+            thumbnail_size_pattern: Regex::new(r#":: (\d+) x (\d+) ::"#).unwrap(),
+            full_size_pattern: Regex::new(
+                r#"<a href="([^"]+)">Download original(?: (\d+) x (\d+))?"#,
+            )
+            .unwrap(),
         }
     }
 
     fn retrieve_image_url(&self, url: &str) -> crate::Result<String> {
         // There are two flavors of image: full size and standard. In the
-        // event there is no full-size image, fall back to standard.
-        let text = self.client.get(url).send()?.text()?;
-        self.extract_full_size(&text)
-            .or_else(|| self.extract_thumbnail(&text))
+        // event there is no full-size image, the standard one is the original.
+        // Full-size images count against the image quota, so any quality short
+        // of original settles for standard.
+        let text = self.client.get(url).send_with_retry()?.text()?;
+        let full_size = self.extract_full_size(&text);
+        let kind = match full_size {
+            Some(_) => Kind::Sample,
+            None => Kind::Original,
+        };
+        let variants = full_size
+            .into_iter()
+            .chain(self.extract_thumbnail(&text, kind))
+            .collect();
+        self.quality
+            .choose(variants)
+            .map(|variant| variant.value)
             .ok_or_else(|| Error::Extraction(ExtractionFailure::ImageUrl, url.into()))
     }
 
-    fn extract_full_size(&self, text: &str) -> Option<String> {
-        self.full_size_pattern
-            .captures(text)
-            .map(|captures| captures.get(1).unwrap().as_str().replace("&amp;", "&"))
+    fn extract_full_size(&self, text: &str) -> Option<Variant<String>> {
+        let captures = self.full_size_pattern.captures(text)?;
+        let url = captures.get(1).unwrap().as_str().replace("&amp;", "&");
+        let size = |idx| captures.get(idx)?.as_str().parse().ok();
+        Some(Variant::new(Kind::Original, url).with_size(size(2), size(3)))
     }
 
-    fn extract_thumbnail(&self, text: &str) -> Option<String> {
-        self.thumbnail_pattern
-            .captures(text)
-            .map(|captures| captures.get(1).unwrap().as_str().into())
+    fn extract_thumbnail(&self, text: &str, kind: Kind) -> Option<Variant<String>> {
+        let url = self
+            .thumbnail_pattern
+            .captures(text)?
+            .get(1)?
+            .as_str()
+            .into();
+        let size = self.thumbnail_size_pattern.captures(text);
+        let size = |idx| size.as_ref()?.get(idx)?.as_str().parse().ok();
+        Some(Variant::new(kind, url).with_size(size(1), size(2)))
     }
 }
 
@@ -196,27 +281,38 @@ impl Downloadable for EhentaiUrl {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
-        let url = context.retrieve_image_url(&self.0)?;
+        let media = self.resolve(context)?;
         Ok(context
             .client
-            .get(url)
-            .send()
+            .get(media.url)
+            .send_with_retry()
             .map(ResponseGalleryItem::new)?)
     }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
+        context.retrieve_image_url(&self.0).map(Media::new)
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        self.0.clone()
+    }
 }
 
 // This specialized gallery impl exists to improve efficiency
 // in skipping back pages for ehentai.
 pub struct EHentaiGallery {
-    context: Context,
+    context: Arc<Context>,
     pager: EhentaiPager,
     current: Page<EhentaiUrl>,
+    metadata: Metadata,
 }
 
 impl Gallery for EHentaiGallery {
+    type Context = Context;
     type Item = ResponseGalleryItem;
+    type Pending = EhentaiUrl;
 
-    fn next(&mut self) -> Option<crate::Result<Self::Item>> {
+    fn next_pending(&mut self) -> Option<crate::Result<Self::Pending>> {
         // Copied from PagedGallery implementation;
         // there might be a better way to share code here.
         if self.current.is_empty() {
@@ -227,8 +323,27 @@ impl Gallery for EHentaiGallery {
             };
         }
 
-        let item = self.current.pop()?;
-        Some(item.download(&self.context))
+        self.current.pop().map(Ok)
+    }
+
+    fn context(&self) -> &Arc<Self::Context> {
+        &self.context
+    }
+
+    fn metadata(&self) -> Option<&Metadata> {
+        Some(&self.metadata)
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        let unpaged = self
+            .pager
+            .total_count
+            .saturating_sub(self.pager.paged_count);
+        Some(unpaged + self.current.len())
+    }
+
+    fn buffered(&self) -> Option<usize> {
+        Some(self.current.len())
     }
 
     fn advance_by(&mut self, n: usize) -> crate::Result<usize> {
@@ -264,7 +379,29 @@ impl Gallery for EHentaiGallery {
     }
 }
 
-fn configure_client(username: &str, password: &str) -> crate::Result<Client> {
+/// Signs in, by way of a saved login where we have one; the flag is set when we do. Returns the
+/// login's cookies, which are all a client needs to be signed in.
+fn restore_or_log_in(settings: &Settings) -> crate::Result<(HashMap<String, String>, bool)> {
+    if let Some(login) = SavedLogin::load(LOGIN_KEY).filter(|login| !login.is_expired()) {
+        return Ok((login.cookies, true));
+    }
+
+    // A browser's cookies are as good as a login, and spare us the login form and its captcha.
+    if cookies::is_imported(settings, "e-hentai.org", "ipb_member_id") {
+        return Ok((HashMap::new(), false));
+    }
+
+    let config = Configuration::init();
+    let login = log_in(
+        settings,
+        config.get_config(Key::EHentaiUser)?,
+        config.get_config(Key::EHentaiPass)?,
+    )?;
+    login.save(LOGIN_KEY);
+    Ok((login.cookies, false))
+}
+
+fn log_in(settings: &Settings, username: &str, password: &str) -> crate::Result<SavedLogin> {
     // This struct looks ridiculous, but it represents the form post required to successfully
     // authenticate to e-hentai's back end. God knows what all this crap is for.
     #[derive(Serialize)]
@@ -293,46 +430,56 @@ fn configure_client(username: &str, password: &str) -> crate::Result<Client> {
         }
     }
 
-    let client = reqwest::blocking::Client::new();
+    let client = Client::builder(settings.retry).build()?;
     let response = client
         .post("https://forums.e-hentai.org/index.php?act=Login&CODE=01")
         .form(&Form::new(username, password))
-        .send()?;
+        .send_with_retry()?;
 
-    Ok(build_client(read_cookies(&response)))
+    // A failed login still succeeds as far as http is concerned; it just doesn't sign us in.
+    let (cookies, expires) = read_cookies(&response);
+    if !cookies.contains_key("ipb_member_id") {
+        return Err(Error::Authentication(SITE.name));
+    }
+
+    Ok(SavedLogin {
+        cookies,
+        expires,
+        ..Default::default()
+    })
 }
 
-fn build_client(cookies: HashMap<String, String>) -> Client {
-    use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, COOKIE, USER_AGENT};
-    use std::fmt::Write;
-
-    let builder = Client::builder();
-    let mut headers = HeaderMap::new();
-    let mut buffer = String::from("nw=1");
+fn build_client(settings: &Settings, cookies: HashMap<String, String>) -> Client {
+    use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
 
+    // Our login goes in alongside whatever cookies were imported, for the site as a whole.
+    let url = "https://e-hentai.org".parse().unwrap();
+    let jar = crate::cookies::jar(settings);
+    jar.add_cookie_str("nw=1; Domain=e-hentai.org", &url);
     for (key, value) in cookies {
-        let _ = write!(buffer, "; {}={}", key, value);
+        jar.add_cookie_str(&format!("{}={}; Domain=e-hentai.org", key, value), &url);
     }
 
+    let mut headers = HeaderMap::new();
     headers.insert(ACCEPT, HeaderValue::from_static("text/html"));
-    headers.insert(
-        USER_AGENT,
-        HeaderValue::from_static(super::prelude::USER_AGENT),
-    );
-    headers.insert(
-        COOKIE,
-        HeaderValue::from_str(&buffer).expect("Bad header value (cookies)"),
-    );
-
-    builder.default_headers(headers).build().unwrap()
+
+    Client::builder(settings.retry)
+        .user_agent(USER_AGENT)
+        .cookie_provider(jar)
+        .default_headers(headers)
+        .build()
+        .unwrap()
 }
 
-fn read_cookies(response: &Response) -> HashMap<String, String> {
+/// Reads the cookies set by a response, along with when the first of them expires.
+fn read_cookies(response: &Response) -> (HashMap<String, String>, Option<i64>) {
     // "set-cookie": "ipb_session_id=17f5a3fe9fde37b28d9f4584f67705bc; path=/; domain=.e-hentai.org",
     // "set-cookie": "ipb_coppa=0; path=/; domain=forums.e-hentai.org",
     // "set-cookie": "ipb_session_id=fa9c1ddfc3b9f425ad23a77364dc1677; path=/; domain=.e-hentai.org",
 
+    let now = chrono::Utc::now().timestamp();
     let mut map = HashMap::new();
+    let mut expires: Option<i64> = None;
     let header_pattern = Regex::new(r#"(\w+)=(\w+)"#).unwrap();
     let headers = response
         .headers()
@@ -347,7 +494,45 @@ fn read_cookies(response: &Response) -> HashMap<String, String> {
                 captures.get(2).unwrap().as_str().into(),
             );
         }
+        if let Some(expiry) = session::cookie_expiry(header, now) {
+            expires = Some(expires.map_or(expiry, |expires| expires.min(expiry)));
+        }
     }
 
-    map
+    (map, expires)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::quality::{Kind, Quality};
+
+    use super::{Client, Context};
+
+    #[test]
+    fn reads_both_flavors_of_image() {
+        let text = r#"<img id="img" src="https://abc.hath.network/h/ab/keystamp/1.jpg" />
+            <div>1.png :: 1280 x 1807 :: 297.8 KiB</div>
+            <a href="https://e-hentai.org/fullimg/1/2/3/1.png?a=1&amp;b=2">Download original 2480 x 3500 1.47 MiB source</a>"#;
+        let context = Context::new(
+            Client::builder(Default::default()).build().unwrap(),
+            Quality::Original,
+        );
+
+        let full_size = context.extract_full_size(text).unwrap();
+        assert_eq!(
+            "https://e-hentai.org/fullimg/1/2/3/1.png?a=1&b=2",
+            full_size.value
+        );
+        assert_eq!(
+            (Some(2480), Some(3500)),
+            (full_size.width, full_size.height)
+        );
+
+        let standard = context.extract_thumbnail(text, Kind::Sample).unwrap();
+        assert_eq!(
+            "https://abc.hath.network/h/ab/keystamp/1.jpg",
+            standard.value
+        );
+        assert_eq!((Some(1280), Some(1807)), (standard.width, standard.height));
+    }
 }
@@ -4,18 +4,29 @@ use regex::Regex;
 
 use super::{build_client, prelude::*};
 
-pub fn extract(url: &str) -> crate::Result<(PagedGallery<FappeningBookPager>, Option<String>)> {
-    let client = build_client();
+pub static SITE: Site = Site {
+    name: "FappeningBook",
+    hosts: &["fappeningbook.com"],
+    routes: &["/<model>/"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(PagedGallery<FappeningBookPager>, Option<String>)> {
+    let client = build_client(settings);
     let gallery_name = name_from_url(url);
 
-    let text = client.get(url).send()?.text()?;
+    let text = client.get(url).send_with_retry()?.text()?;
     let pages = get_pages(&text)?;
 
     Ok((
         PagedGallery {
             current: Page::Empty,
-            context: Context::new(client),
+            context: Arc::new(Context::new(client)),
             pager: FappeningBookPager::new(url, pages),
+            filter: settings.filter.clone(),
         },
         gallery_name.map(|name| name.into()),
     ))
@@ -75,7 +86,7 @@ impl Context {
     }
 
     fn fetch_page(&self, url: &str) -> crate::Result<Page<Image>> {
-        let text = self.client.get(url).send()?.text()?;
+        let text = self.client.get(url).send_with_retry()?.text()?;
         let links: VecDeque<_> = self
             .img_src_re
             .captures_iter(&text)
@@ -98,12 +109,21 @@ impl Downloadable for Image {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
+        let media = self.resolve(context)?;
         Ok(context
             .client
-            .get(self.0)
-            .send()
+            .get(media.url)
+            .send_with_retry()
             .map(ResponseGalleryItem::new)?)
     }
+
+    fn resolve(self, _: &Self::Context) -> crate::Result<Media> {
+        Ok(Media::new(self.0))
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        self.0.clone()
+    }
 }
 
 fn name_from_url(url: &str) -> Option<&str> {
@@ -1,16 +1,26 @@
 use super::prelude::*;
 
-pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<FngUrl>, Option<String>)> {
+pub static SITE: Site = Site {
+    name: "FitNakedGirls",
+    hosts: &["fitnakedgirls.com"],
+    routes: &["/<gallery>/"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(UnpagedGallery<FngUrl>, Option<String>)> {
     use scraper::{Html, Selector};
 
-    let client = Client::builder().user_agent(USER_AGENT).build().unwrap();
-    let content = client.get(url).send()?.text()?;
+    let client = client_builder(settings).build().unwrap();
+    let content = client.get(url).send_with_retry()?.text()?;
 
     let item_selector = Selector::parse("div.album img,div.album source").unwrap();
     let document = Html::parse_document(&content);
 
     let gallery = UnpagedGallery {
-        context: client,
+        context: Arc::new(client),
         items: document
             .select(&item_selector)
             .filter_map(|noderef| {
@@ -35,6 +45,18 @@ impl Downloadable for FngUrl {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
-        Ok(context.get(self.0).send().map(ResponseGalleryItem::new)?)
+        let media = self.resolve(context)?;
+        Ok(context
+            .get(media.url)
+            .send_with_retry()
+            .map(ResponseGalleryItem::new)?)
+    }
+
+    fn resolve(self, _: &Self::Context) -> crate::Result<Media> {
+        Ok(Media::new(self.0))
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        self.0.clone()
     }
 }
@@ -5,7 +5,17 @@ use serde::Deserialize;
 
 use crate::gallery::prelude::*;
 
-pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<FlistImage>, Option<String>)> {
+pub static SITE: Site = Site {
+    name: "F-List",
+    hosts: &["www.f-list.net"],
+    routes: &["/c/<character>"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(UnpagedGallery<FlistImage>, Option<String>)> {
     // This ought to be basically the easiest implementation yet. My compliments to FList,
     // despite they're not exactly my favorite bunch of people to deal with.
 
@@ -38,8 +48,8 @@ pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<FlistImage>, Option<S
         })
     });
 
-    let context = Context::new();
-    let page_content = context.client.get(url).send()?.text()?;
+    let context = Context::new(settings);
+    let page_content = context.client.get(url).send_with_retry()?.text()?;
     let character_id = read_character_id(url, &page_content)?;
     let inline = read_inlines(&page_content).unwrap_or_default();
     let links = read_links(&page_content).unwrap_or_default();
@@ -48,7 +58,7 @@ pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<FlistImage>, Option<S
         .client
         .post("https://www.f-list.net/json/profile-images.json")
         .form(&[("character_id", character_id)])
-        .send()?
+        .send_with_retry()?
         .json()?;
 
     let mut items = VecDeque::with_capacity(inline.len() + links.len() + profile.len());
@@ -57,7 +67,10 @@ pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<FlistImage>, Option<S
     items.extend(links.into_iter().map(FlistImage::Link));
 
     Ok((
-        UnpagedGallery { context, items },
+        UnpagedGallery {
+            context: Arc::new(context),
+            items,
+        },
         gallery_name.map(|name| name.to_string()),
     ))
 }
@@ -74,27 +87,37 @@ impl Downloadable for FlistImage {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
-        let response = match self {
+        let media = self.resolve(context)?;
+        let response = context.client.get(media.url).send_with_retry()?;
+        Ok(ResponseGalleryItem::new(response))
+    }
+
+    fn resolve(self, _: &Self::Context) -> crate::Result<Media> {
+        Ok(Media::new(self.url()))
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        self.url()
+    }
+}
+
+impl FlistImage {
+    fn url(&self) -> String {
+        match self {
             FlistImage::Inline(inline) => {
                 let d = &inline.hash[..2];
                 let e = &inline.hash[2..4];
-                let url = format!(
+                format!(
                     "https://static.f-list.net/images/charinline/{}/{}/{}.{}",
                     d, e, inline.hash, inline.extension
-                );
-                context.client.get(url).send()?
-            }
-            FlistImage::Profile(image) => {
-                let url = format!(
-                    "https://static.f-list.net/images/charimage/{}.{}",
-                    image.id, image.extension
-                );
-                context.client.get(url).send()?
+                )
             }
-            FlistImage::Link(url) => context.client.get(url).send()?,
-        };
-
-        Ok(ResponseGalleryItem::new(response))
+            FlistImage::Profile(image) => format!(
+                "https://static.f-list.net/images/charimage/{}.{}",
+                image.id, image.extension
+            ),
+            FlistImage::Link(url) => url.clone(),
+        }
     }
 }
 
@@ -103,13 +126,10 @@ pub struct Context {
 }
 
 impl Context {
-    fn new() -> Self {
-        use reqwest::{
-            cookie::Jar,
-            header::{HeaderMap, HeaderValue, ACCEPT},
-        };
+    fn new(settings: &Settings) -> Self {
+        use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
 
-        let cookie_store = Jar::default();
+        let cookie_store = crate::cookies::jar(settings);
         let url = "http://www.f-list.net".parse().unwrap();
         cookie_store.add_cookie_str("warning=1", &url);
 
@@ -117,9 +137,9 @@ impl Context {
         headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
 
         Self {
-            client: Client::builder()
+            client: Client::builder(settings.retry)
                 .user_agent(USER_AGENT)
-                .cookie_provider(Arc::new(cookie_store))
+                .cookie_provider(cookie_store)
                 .default_headers(headers)
                 .build()
                 .unwrap(),
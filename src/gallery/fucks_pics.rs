@@ -3,9 +3,19 @@ use scraper::{Html, Selector};
 
 use super::prelude::*;
 
-pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<Image>, Option<String>)> {
-    let client = super::build_client();
-    let text = client.get(url).send()?.text()?;
+pub static SITE: Site = Site {
+    name: "Fucks.pics",
+    hosts: &["fucks.pics"],
+    routes: &["/<gallery>/"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(UnpagedGallery<Image>, Option<String>)> {
+    let client = super::build_client(settings);
+    let text = client.get(url).send_with_retry()?.text()?;
     let gallery_base_url = get_gallery_base_url(url).ok_or_else(|| {
         Error::Extraction(
             ExtractionFailure::Metadata,
@@ -18,12 +28,12 @@ pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<Image>, Option<String
     let selector = Selector::parse("a").unwrap();
     let image_urls = document
         .select(&selector)
-        .filter_map(|e| {
+        .filter(|e| {
             e.value()
-                .attr("href")?
-                .starts_with(gallery_base_url)
-                .then(|| e.inner_html())
+                .attr("href")
+                .is_some_and(|href| href.starts_with(gallery_base_url))
         })
+        .map(|e| e.inner_html())
         .filter_map(|s| {
             url_expr
                 .captures(&s)?
@@ -33,7 +43,7 @@ pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<Image>, Option<String
 
     Ok((
         UnpagedGallery {
-            context: client,
+            context: Arc::new(client),
             items: image_urls.map(Image).collect(),
         },
         get_gallery_name(&document),
@@ -62,6 +72,17 @@ impl Downloadable for Image {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
-        Ok(ResponseGalleryItem::new(context.get(self.0).send()?))
+        let media = self.resolve(context)?;
+        Ok(ResponseGalleryItem::new(
+            context.get(media.url).send_with_retry()?,
+        ))
+    }
+
+    fn resolve(self, _: &Self::Context) -> crate::Result<Media> {
+        Ok(Media::new(self.0))
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        self.0.clone()
     }
 }
@@ -2,15 +2,24 @@ use regex::Regex;
 
 use super::prelude::*;
 
-pub fn extract(url: &str) -> crate::Result<(PagedGallery<GwmPager>, Option<String>)> {
+pub static SITE: Site = Site {
+    name: "GirlsWithMuscle",
+    hosts: &["www.girlswithmuscle.com"],
+    routes: &["/images/?name=<name>"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(PagedGallery<GwmPager>, Option<String>)> {
     use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
 
     let mut headers = HeaderMap::new();
     headers.insert(ACCEPT, HeaderValue::from_static("text/html"));
 
     let context = Context {
-        client: Client::builder()
-            .user_agent(USER_AGENT)
+        client: client_builder(settings)
             .default_headers(headers)
             .build()
             .unwrap(),
@@ -19,13 +28,14 @@ pub fn extract(url: &str) -> crate::Result<(PagedGallery<GwmPager>, Option<Strin
     };
 
     let gallery = PagedGallery {
-        context,
+        context: Arc::new(context),
         current: Page::Empty,
         pager: GwmPager {
             name: read_name(url)?.into(),
             page: 1,
             previous_items: VecDeque::new(),
         },
+        filter: settings.filter.clone(),
     };
 
     Ok((gallery, None))
@@ -39,12 +49,12 @@ pub struct Context {
 
 impl Context {
     fn get_page_content(&self, url: &str) -> crate::Result<String> {
-        Ok(self.client.get(url).send()?.text()?)
+        Ok(self.client.get(url).send_with_retry()?.text()?)
     }
 
     fn get_full_image_link(&self, id: &str) -> crate::Result<String> {
         let url = format!("https://www.girlswithmuscle.com/{}/", id);
-        let content = self.client.get(&url).send()?.text()?;
+        let content = self.client.get(&url).send_with_retry()?.text()?;
         let data_url = self
             .data_url_pattern
             .captures(&content)
@@ -103,13 +113,21 @@ impl Downloadable for Id {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
-        let url = context.get_full_image_link(&self.0)?;
+        let media = self.resolve(context)?;
         Ok(context
             .client
-            .get(url)
-            .send()
+            .get(media.url)
+            .send_with_retry()
             .map(ResponseGalleryItem::new)?)
     }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
+        context.get_full_image_link(&self.0).map(Media::new)
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        format!("https://www.girlswithmuscle.com/{}/", self.0)
+    }
 }
 
 fn read_name(url: &str) -> crate::Result<&str> {
@@ -5,12 +5,19 @@
 
 use crate::gallery::prelude::*;
 
-pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<Image>, Option<String>)> {
-    let client = Client::builder()
-        .user_agent(USER_AGENT)
-        .cookie_store(true)
-        .build()?;
-    let text = client.get(url).send()?.text()?;
+pub static SITE: Site = Site {
+    name: "HDPorn.pics",
+    hosts: &["hdporn.pics"],
+    routes: &["/<gallery>"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(UnpagedGallery<Image>, Option<String>)> {
+    let client = client_builder(settings).build()?;
+    let text = client.get(url).send_with_retry()?.text()?;
     let document = nipper::Document::from(&text);
 
     let links = document
@@ -21,7 +28,7 @@ pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<Image>, Option<String
 
     Ok((
         UnpagedGallery {
-            context: client,
+            context: Arc::new(client),
             items: links.collect(),
         },
         None,
@@ -36,6 +43,17 @@ impl Downloadable for Image {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
-        Ok(ResponseGalleryItem::new(context.get(self.0).send()?))
+        let media = self.resolve(context)?;
+        Ok(ResponseGalleryItem::new(
+            context.get(media.url).send_with_retry()?,
+        ))
+    }
+
+    fn resolve(self, _: &Self::Context) -> crate::Result<Media> {
+        Ok(Media::new(self.0))
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        self.0.clone()
     }
 }
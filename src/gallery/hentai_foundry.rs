@@ -5,30 +5,40 @@ use scraper::{Html, Selector};
 
 use super::prelude::*;
 
-pub fn extract(url: &str) -> crate::Result<(PagedGallery<HfPager>, Option<String>)> {
+pub static SITE: Site = Site {
+    name: "Hentai Foundry",
+    hosts: &["www.hentai-foundry.com"],
+    routes: &["/user/<user>", "/pictures/user/<user>"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(PagedGallery<HfPager>, Option<String>)> {
     let mut segments = url.split('/');
     let _ = segments.find(|&x| x == "user");
 
     if let Some(user) = segments.next() {
         let gallery_name = user.to_owned();
-        user_gallery(&format!(
-            "https://www.hentai-foundry.com/pictures/user/{}",
-            user
-        ))
+        user_gallery(
+            settings,
+            &format!("https://www.hentai-foundry.com/pictures/user/{}", user),
+        )
         .map(|gallery| (gallery, Some(gallery_name)))
     } else {
         Err(Error::Unsupported(UnsupportedError::Route, url.into()))
     }
 }
 
-fn user_gallery(url: &str) -> crate::Result<PagedGallery<HfPager>> {
+fn user_gallery(settings: &Settings, url: &str) -> crate::Result<PagedGallery<HfPager>> {
     // First, build the client and get initial content using the enterAgree=1 param.
     // This content contains nothing good. We're going to use it to grab a CSRF token
     // and submit a filter update. Kind of silly we need to do this every time.
-    let client = build_client();
+    let client = build_client(settings);
     let base_url = truncate_url(url)?;
     let agree = base_url.to_string() + "?enterAgree=1";
-    let content = client.get(agree).send()?.text()?;
+    let content = client.get(agree).send_with_retry()?.text()?;
 
     // Extract the csrf token from the initial content and update filter settings.
     // I'm a little worried about this sending so many requests so quickly. Might be
@@ -36,7 +46,7 @@ fn user_gallery(url: &str) -> crate::Result<PagedGallery<HfPager>> {
     // DOES work, so....
     let token = read_csrf_token(&content)?;
     update_filters(&client, token)?;
-    let content = client.get(url).send()?.text()?;
+    let content = client.get(url).send_with_retry()?.text()?;
 
     // For this gallery, we just go on and read the number of pages ahead of time.
     let pages = 2..=read_pages(&content).ok_or_else(|| {
@@ -47,13 +57,16 @@ fn user_gallery(url: &str) -> crate::Result<PagedGallery<HfPager>> {
     })?;
 
     let context = Context::with_client(client);
+    let links = context.read_links(&content);
     Ok(PagedGallery {
-        current: Page::Items(context.read_links(&content)),
         pager: HfPager {
             base_url: base_url.into(),
             pages,
+            page_size: links.len(),
         },
-        context,
+        current: Page::Items(links),
+        context: Arc::new(context),
+        filter: settings.filter.clone(),
     })
 }
 
@@ -87,6 +100,8 @@ impl Context {
 pub struct HfPager {
     base_url: String,
     pages: RangeInclusive<usize>,
+    /// The size of a full page, which we take to be the size of the first.
+    page_size: usize,
 }
 
 impl Pager for HfPager {
@@ -100,9 +115,13 @@ impl Pager for HfPager {
             None => return Ok(Page::Empty),
         };
         let url = format!("{}/page/{}", self.base_url, page);
-        let document = context.client.get(url).send()?.text()?;
+        let document = context.client.get(url).send_with_retry()?.text()?;
         Ok(Page::Items(context.read_links(&document)))
     }
+
+    fn remaining(&self) -> Option<usize> {
+        Some(self.pages.clone().count() * self.page_size)
+    }
 }
 
 pub struct HfUrl(String);
@@ -113,8 +132,17 @@ impl Downloadable for HfUrl {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
+        let media = self.resolve(context)?;
+        Ok(context
+            .client
+            .get(media.url)
+            .send_with_retry()
+            .map(ResponseGalleryItem::new)?)
+    }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
         let page = String::from("https://www.hentai-foundry.com") + &self.0;
-        let text = context.client.get(page).send()?.text()?;
+        let text = context.client.get(page).send_with_retry()?.text()?;
 
         fn extract_by_pattern<'a>(pattern: &Regex, text: &'a str) -> Option<&'a str> {
             Some(pattern.captures(text).map(|x| x.get(1).unwrap())?.as_str())
@@ -125,11 +153,11 @@ impl Downloadable for HfUrl {
             .map(|route| String::from("https://pictures.hentai-foundry.com/") + route)
             .ok_or(Error::Extraction(ExtractionFailure::ImageUrl, self.0))?;
 
-        Ok(context
-            .client
-            .get(url)
-            .send()
-            .map(ResponseGalleryItem::new)?)
+        Ok(Media::new(url))
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        String::from("https://www.hentai-foundry.com") + &self.0
     }
 }
 
@@ -224,7 +252,7 @@ fn update_filters(client: &Client, token: &str) -> crate::Result<()> {
     client
         .post("https://www.hentai-foundry.com/site/filters")
         .form(&filters)
-        .send()?;
+        .send_with_retry()?;
     Ok(())
 }
 
@@ -233,24 +261,34 @@ fn read_pages(content: &str) -> Option<usize> {
     pattern.captures(content)?.get(1)?.as_str().parse().ok()
 }
 
-fn build_client() -> Client {
-    Client::builder()
-        .user_agent(USER_AGENT)
-        .cookie_store(true)
-        .build()
-        .unwrap()
+fn build_client(settings: &Settings) -> Client {
+    client_builder(settings).build().unwrap()
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::Settings;
+
     #[test]
     fn can_read_profile_links() {
-        assert!(super::extract("https://www.hentai-foundry.com/user/AndavaNSFW").is_ok());
-        assert!(super::extract("https://www.hentai-foundry.com/user/AndavaNSFW/profile").is_ok());
+        assert!(super::extract(
+            "https://www.hentai-foundry.com/user/AndavaNSFW",
+            &Settings::default()
+        )
+        .is_ok());
+        assert!(super::extract(
+            "https://www.hentai-foundry.com/user/AndavaNSFW/profile",
+            &Settings::default()
+        )
+        .is_ok());
     }
 
     #[test]
     fn can_read_profile_gallery_links() {
-        assert!(super::extract("https://www.hentai-foundry.com/pictures/user/AndavaNSFW").is_ok());
+        assert!(super::extract(
+            "https://www.hentai-foundry.com/pictures/user/AndavaNSFW",
+            &Settings::default()
+        )
+        .is_ok());
     }
 }
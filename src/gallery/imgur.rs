@@ -37,23 +37,41 @@ impl Downloadable for ImageModel {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
-        let link = self.mp4.unwrap_or(self.link);
+        let media = self.resolve(context)?;
         Ok(context
             .client
-            .get(link)
-            .send()
+            .get(media.url)
+            .send_with_retry()
             .map(ResponseGalleryItem::new)?)
     }
+
+    fn resolve(self, _: &Self::Context) -> crate::Result<Media> {
+        Ok(Media::new(self.mp4.unwrap_or(self.link)))
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        self.link.clone()
+    }
 }
 
-pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<ImageModel>, Option<String>)> {
-    let context = Context::try_with_config()?;
+pub static SITE: Site = Site {
+    name: "Imgur",
+    hosts: &["imgur.com", "i.imgur.com"],
+    routes: &["/a/<album>", "/gallery/<album>", "/<image>"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(UnpagedGallery<ImageModel>, Option<String>)> {
+    let context = Context::try_with_config(settings)?;
 
     if url.contains("imgur.com/a/") {
         let images = query_album(&context, url)?;
         return Ok((
             UnpagedGallery {
-                context,
+                context: Arc::new(context),
                 items: images,
             },
             None,
@@ -64,7 +82,7 @@ pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<ImageModel>, Option<S
         let images = query_gallery(&context, url)?;
         return Ok((
             UnpagedGallery {
-                context,
+                context: Arc::new(context),
                 items: images,
             },
             None,
@@ -76,7 +94,7 @@ pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<ImageModel>, Option<S
     images.push_back(image);
     Ok((
         UnpagedGallery {
-            context,
+            context: Arc::new(context),
             items: images,
         },
         None,
@@ -88,7 +106,7 @@ pub struct Context {
 }
 
 impl Context {
-    fn try_with_config() -> crate::Result<Self> {
+    fn try_with_config(settings: &Settings) -> crate::Result<Self> {
         use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
 
         let config = Configuration::init();
@@ -102,8 +120,7 @@ impl Context {
         );
 
         Ok(Self {
-            client: Client::builder()
-                .user_agent(USER_AGENT)
+            client: client_builder(settings)
                 .default_headers(headers)
                 .build()
                 .unwrap(),
@@ -118,7 +135,7 @@ fn query_album(context: &Context, url: &str) -> crate::Result<VecDeque<ImageMode
             "https://api.imgur.com/3/album/{}/images",
             last_segment(url)?
         ))
-        .send()?
+        .send_with_retry()?
         .json()?;
     Ok(response.data)
 }
@@ -130,7 +147,7 @@ fn query_gallery(context: &Context, url: &str) -> crate::Result<VecDeque<ImageMo
             "https://api.imgur.com/3/gallery/album/{}",
             last_segment(url)?
         ))
-        .send()?
+        .send_with_retry()?
         .json()?;
     Ok(response.data.images)
 }
@@ -142,7 +159,7 @@ fn query_image(context: &Context, url: &str) -> crate::Result<ImageModel> {
             "https://api.imgur.com/3/image/{}",
             last_segment(url)?
         ))
-        .send()?
+        .send_with_retry()?
         .json()?;
     Ok(response.data)
 }
@@ -154,7 +171,8 @@ fn last_segment(address: &str) -> crate::Result<String> {
         .into_iter()
         .flatten()
         .last()
-        .map(|x| x.to_string())
+        // Direct links from i.imgur.com carry an extension the api knows nothing about.
+        .map(|x| x.split('.').next().unwrap_or(x).to_string())
         .ok_or_else(|| {
             Error::Extraction(
                 ExtractionFailure::Metadata,
@@ -171,4 +189,11 @@ mod tests {
         let expected = "gN55w";
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn last_segment_of_direct_link() {
+        let actual = super::last_segment("https://imgur.com/gN55w.jpg").unwrap();
+        let expected = "gN55w";
+        assert_eq!(actual, expected);
+    }
 }
@@ -20,15 +20,25 @@ use serde::{Deserialize, Deserializer};
 
 use super::prelude::*;
 
-pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<ImageToken>, Option<String>)> {
-    let client = Client::builder().user_agent(USER_AGENT).build().unwrap();
+pub static SITE: Site = Site {
+    name: "nhentai",
+    hosts: &["nhentai.net"],
+    routes: &["/g/<gallery id>"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(UnpagedGallery<ImageToken>, Option<String>)> {
+    let client = client_builder(settings).build().unwrap();
 
     // The gallery page serves no real purpose for us, because each of the image pages
     // includes a json packet which describes the book we're trying to download. Once we
     // have the gallery info, we'll store the media id and image formats for future use.
 
     let url = url.to_string() + "/1/";
-    let document = client.get(url).send()?.text()?;
+    let document = client.get(url).send_with_retry()?.text()?;
     let pattern = Regex::new(r#"JSON\.parse\("(.+?)"\)"#).unwrap();
     let packet = pattern
         .captures(&document)
@@ -52,17 +62,17 @@ pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<ImageToken>, Option<S
     // There's no real reason to use i32 over usize, but the documentation
     // suggested trying it out. Whatever.
     let tokens = (1i32..)
-        .zip(gallery_info.images.pages.into_iter())
+        .zip(gallery_info.images.pages)
         .map(|(idx, info)| ImageToken {
             idx,
             fmt: info.format,
         });
 
     let gallery = UnpagedGallery {
-        context: Context {
+        context: Arc::new(Context {
             client,
             media_id: gallery_info.media_id,
-        },
+        }),
         items: tokens.collect(),
     };
 
@@ -80,18 +90,25 @@ impl Downloadable for ImageToken {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
-        let ImageToken { idx, fmt } = self;
-        let url = format!(
-            "https://i.nhentai.net/galleries/{}/{}.{}",
-            context.media_id, idx, fmt
-        );
-
+        let media = self.resolve(context)?;
         Ok(context
             .client
-            .get(url)
-            .send()
+            .get(media.url)
+            .send_with_retry()
             .map(ResponseGalleryItem::new)?)
     }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
+        Ok(Media::new(self.source(context)))
+    }
+
+    fn source(&self, context: &Self::Context) -> String {
+        let ImageToken { idx, fmt } = self;
+        format!(
+            "https://i.nhentai.net/galleries/{}/{}.{}",
+            context.media_id, idx, fmt
+        )
+    }
 }
 
 pub struct Context {
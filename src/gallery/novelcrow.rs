@@ -2,13 +2,23 @@ use scraper::{Html, Selector};
 
 use crate::gallery::prelude::*;
 
-pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<ImageLink>, Option<String>)> {
-    let client = Client::builder().user_agent(USER_AGENT).build()?;
+pub static SITE: Site = Site {
+    name: "NovelCrow",
+    hosts: &["novelcrow.com"],
+    routes: &["/<comic>/<chapter>/"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(UnpagedGallery<ImageLink>, Option<String>)> {
+    let client = client_builder(settings).build()?;
 
     let title_s = Selector::parse("title").unwrap();
     let image_s = Selector::parse("div.reading-content img[data-src]").unwrap();
 
-    let text = client.get(url).send()?.text()?;
+    let text = client.get(url).send_with_retry()?.text()?;
     let document = Html::parse_fragment(&text);
 
     // This title business looks complicated but isn't.
@@ -32,7 +42,7 @@ pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<ImageLink>, Option<St
 
     Ok((
         UnpagedGallery {
-            context: client,
+            context: Arc::new(client),
             items: images,
         },
         title,
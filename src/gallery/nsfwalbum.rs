@@ -4,10 +4,20 @@ use super::prelude::*;
 
 // FIXME: This almost works, but it's actually downloading thumbnails instead of full-size images.
 
-pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<NsfwImageId>, Option<String>)> {
-    let client = Client::builder().user_agent(USER_AGENT).build().unwrap();
+pub static SITE: Site = Site {
+    name: "NSFWAlbum",
+    hosts: &["nsfwalbum.com"],
+    routes: &["/album/<album id>"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(UnpagedGallery<NsfwImageId>, Option<String>)> {
+    let client = client_builder(settings).build().unwrap();
     let pattern = Regex::new(r#"data-img-id="(\d+)""#).unwrap();
-    let content = client.get(url).send()?.text()?;
+    let content = client.get(url).send_with_retry()?.text()?;
     let images = pattern
         .captures_iter(&content)
         .filter_map(|x| x.get(1).map(|x| x.as_str().to_owned()))
@@ -15,7 +25,7 @@ pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<NsfwImageId>, Option<
 
     Ok((
         UnpagedGallery {
-            context: Context::with_client(client),
+            context: Arc::new(Context::with_client(client)),
             items: images.collect(),
         },
         None,
@@ -35,12 +45,11 @@ impl Context {
         }
     }
 
-    fn request_image(&self, id: &str) -> crate::Result<Response> {
+    fn image_url(&self, id: &str) -> crate::Result<String> {
         let url = format_stage_one_url(id);
-        let image_content = self.client.get(url).send()?.text()?;
+        let image_content = self.client.get(url).send_with_retry()?.text()?;
         let (giraffe, salt) = self.extract_params(&image_content)?;
-        let url = format_stage_two_url(id, giraffe, salt);
-        Ok(self.client.get(url).send()?)
+        Ok(format_stage_two_url(id, giraffe, salt))
     }
 
     fn extract_params<'a>(&self, image_content: &'a str) -> crate::Result<(&'a str, i32)> {
@@ -73,10 +82,25 @@ impl Downloadable for NsfwImageId {
     type Output = NamedGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
+        let Media {
+            url,
+            name,
+            metadata,
+        } = self.resolve(context)?;
+        let response = context.client.get(url).send_with_retry()?;
+        Ok(NamedGalleryItem::new(response, name.unwrap_or_default()).with_metadata(metadata))
+    }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
         let id = self.0;
-        context
-            .request_image(&id)
-            .map(|response| NamedGalleryItem::new(response, id + ".jpg"))
+        let url = context.image_url(&id)?;
+
+        // The extension is only a guess; it's corrected once we see the content.
+        Ok(Media::named(url, id + ".jpg"))
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        format_stage_one_url(&self.0)
     }
 }
 
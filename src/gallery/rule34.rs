@@ -1,19 +1,42 @@
-use std::borrow::Cow;
+//! Rule34.xxx, a Gelbooru fork.
+//!
+//! Posts are listed through the site's DAPI, which gives us everything needed to download a post
+//! in the listing itself. Should the API refuse us, we fall back to scraping the listing and each
+//! post's page, which costs a second request per post and breaks whenever the templates change.
+
+use std::{borrow::Cow, fmt::Display};
 
 use regex::Regex;
 use scraper::{Html, Selector};
 use serde::Deserialize;
 
-use super::{prelude::*, Gallery};
+use crate::filter::Filter;
+
+use super::{booru, booru::gelbooru, prelude::*, Gallery};
+
+/// Posts per page, both in the listing and as requested from the API.
+const RULE34_PAGE_SIZE: usize = 42; // NEVER CHANGE, GUYS!
 
-pub fn extract(url: &str) -> crate::Result<(Rule34Gallery, Option<String>)> {
+pub static SITE: Site = Site {
+    name: "Rule34.xxx",
+    hosts: &["rule34.xxx", "*.rule34.xxx"],
+    routes: &["/index.php?page=post&s=list&tags=<tags>"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(url: &str, settings: &Settings) -> crate::Result<(Rule34Gallery, Option<String>)> {
     let search = extract_search(url)?;
-    let pager = Rule34Pager { search, idx: 0 };
+    let pager = Rule34Pager {
+        search,
+        idx: 0,
+        use_listing: false,
+    };
 
     let gallery = Rule34Gallery {
-        context: Context::new(),
+        context: Arc::new(Context::new(settings)),
         pager,
         current: Page::Empty,
+        filter: settings.filter.clone(),
     };
 
     match get_single_tag(&gallery.pager.search).map(|tag| tag.to_owned()) {
@@ -24,6 +47,7 @@ pub fn extract(url: &str) -> crate::Result<(Rule34Gallery, Option<String>)> {
 
 pub struct Context {
     client: Client,
+    api: booru::Context,
     gallery_page_pattern: Regex,
     image_metadata_pattern: Regex,
     video_container_pattern: Regex,
@@ -31,10 +55,14 @@ pub struct Context {
 }
 
 impl Context {
-    fn new() -> Self {
+    fn new(settings: &Settings) -> Self {
         Self {
-            client: build_client(),
-            gallery_page_pattern: Regex::new(r#"index\.php\?page=post&s=view&id=(\d+)"#).unwrap(),
+            client: build_client(settings),
+            api: booru::Context::new(settings, "https://rule34.xxx"),
+            gallery_page_pattern: Regex::new(
+                r#"index\.php\?page=post&s=view&id=(\d+)"(?:[^>]*>\s*<img[^>]*?title="([^"]*)")?"#,
+            )
+            .unwrap(),
             image_metadata_pattern: Regex::new(r#"image = (\{.+\})"#).unwrap(),
 
             video_container_pattern: Regex::new("gelcomVideoContainer").unwrap(),
@@ -45,8 +73,10 @@ impl Context {
     fn get_gallery_page_links(&self, text: &str) -> Page<ImageId> {
         self.gallery_page_pattern
             .captures_iter(text)
-            .filter_map(|cx| cx.get(1))
-            .map(|cx| ImageId(cx.as_str().into()))
+            .filter_map(|cx| {
+                let title = cx.get(2).map(|title| title.as_str().to_owned());
+                Some(ImageId(cx.get(1)?.as_str().into(), title))
+            })
             .collect()
     }
 
@@ -93,38 +123,141 @@ impl Context {
     }
 }
 
-pub struct ImageId(String);
+/// A post, as read from the API or, failing that, the listing.
+pub enum Rule34Post {
+    Api(Box<gelbooru::Image>),
+    Listing(ImageId),
+}
+
+impl Downloadable for Rule34Post {
+    type Context = Context;
+
+    type Output = ResponseGalleryItem;
+
+    fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
+        let media = self.resolve(context)?;
+        Ok(context
+            .client
+            .get(media.url)
+            .send_with_retry()
+            .map(ResponseGalleryItem::new)?
+            .with_metadata(media.metadata))
+    }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
+        match self {
+            Rule34Post::Api(image) => (*image).resolve(&context.api),
+            Rule34Post::Listing(id) => id.resolve(context),
+        }
+    }
+
+    fn source(&self, context: &Self::Context) -> String {
+        match self {
+            Rule34Post::Api(image) => match image.id() {
+                Some(id) => post_url(id),
+                None => image.source(&context.api),
+            },
+            Rule34Post::Listing(id) => id.source(context),
+        }
+    }
+
+    fn md5(&self, context: &Self::Context) -> Option<String> {
+        match self {
+            Rule34Post::Api(image) => image.md5(&context.api),
+            Rule34Post::Listing(_) => None,
+        }
+    }
+
+    fn post(&self, context: &Self::Context) -> Option<Post<'_>> {
+        match self {
+            Rule34Post::Api(image) => image.post(&context.api),
+            Rule34Post::Listing(id) => id.post(context),
+        }
+    }
+}
+
+/// A post's id, along with the title of its thumbnail in the listing.
+pub struct ImageId(String, Option<String>);
 
 impl ImageId {
     fn url(&self) -> String {
-        format!(
-            "https://rule34.xxx/index.php?page=post&s=view&id={}",
-            self.0
-        )
+        post_url(&self.0)
     }
 }
 
+/// The page of a post, which is how we know it however it was listed.
+fn post_url(id: impl Display) -> String {
+    format!("https://rule34.xxx/index.php?page=post&s=view&id={id}")
+}
+
 impl Downloadable for ImageId {
     type Context = Context;
 
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
+        let media = self.resolve(context)?;
+        Ok(context
+            .client
+            .get(media.url)
+            .send_with_retry()
+            .map(ResponseGalleryItem::new)?
+            .with_metadata(media.metadata))
+    }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
         // 1. Grab gallery page.
         // 2. Get image metadata
         // 3. Generate download url.
         // 4. Profit.
 
-        let text = context.client.get(self.url()).send()?.text()?;
+        let text = context.client.get(self.url()).send_with_retry()?.text()?;
         let meta = context.get_image_metadata(&text)?;
-        let url = meta.url();
+        let metadata = Metadata {
+            id: Some(self.0.clone()),
+            source: Some(self.url()),
+            // The player's dimensions are reported as floats, for whatever reason.
+            width: meta.width.map(|width| width as u32),
+            height: meta.height.map(|height| height as u32),
+            ..Default::default()
+        };
+        Ok(Media::new(meta.url()).with_metadata(metadata))
+    }
 
-        Ok(context
-            .client
-            .get(&*url)
-            .send()
-            .map(ResponseGalleryItem::new)?)
+    fn source(&self, _: &Self::Context) -> String {
+        self.url()
+    }
+
+    fn post(&self, _: &Self::Context) -> Option<Post<'_>> {
+        self.1.as_deref().map(read_thumbnail_title)
+    }
+}
+
+/// Reads what a listing's thumbnail title says about its post: the post's tags, separated by
+/// spaces or commas, possibly along with its score and rating.
+pub(super) fn read_thumbnail_title(title: &str) -> Post<'_> {
+    let tokens: Box<dyn Iterator<Item = &str>> = if title.contains(',') {
+        Box::new(
+            title
+                .split(',')
+                .map(str::trim)
+                .filter(|token| !token.is_empty()),
+        )
+    } else {
+        Box::new(title.split_whitespace())
+    };
+
+    let mut post = Post::default();
+    for token in tokens {
+        if let Some(score) = token.strip_prefix("score:") {
+            post.score = score.parse().ok();
+        } else if let Some(rating) = token.strip_prefix("rating:") {
+            post.rating = Some(rating);
+        } else {
+            post.tags.push(token);
+        }
     }
+    post
 }
 
 /// Image metadata extracted from each image page
@@ -136,13 +269,17 @@ pub struct ImageMetadata {
     dir: i32,
     img: String,
     base_dir: String,
+    #[serde(default)]
+    width: Option<f64>,
+    #[serde(default)]
+    height: Option<f64>,
 
     #[serde(skip_deserializing)]
     video_url: Option<String>,
 }
 
 impl ImageMetadata {
-    fn url(&self) -> Cow<str> {
+    fn url(&self) -> Cow<'_, str> {
         self.video_url
             .as_deref()
             .map(|s| s.into())
@@ -155,9 +292,48 @@ impl ImageMetadata {
 pub struct Rule34Pager {
     search: String,
     idx: usize,
+    use_listing: bool,
 }
 
 impl Rule34Pager {
+    fn get_api_url(&self) -> String {
+        // The API counts pages where the listing counts posts; idx only ever moves by whole
+        // pages until the last one, so the two agree.
+        format!(
+            "https://rule34.xxx/index.php?page=dapi&s=post&q=index&json=1&limit={}&pid={}&tags={}",
+            RULE34_PAGE_SIZE,
+            self.idx / RULE34_PAGE_SIZE,
+            self.search
+        )
+    }
+
+    fn next_api_page(&mut self, context: &Context) -> crate::Result<Page<Rule34Post>> {
+        let text = context
+            .client
+            .get(self.get_api_url())
+            .send_with_retry()?
+            .text()?;
+        let posts = gelbooru::read_posts(&text)?.unwrap_or_default();
+        self.idx += posts.len();
+        Ok(posts
+            .into_iter()
+            .map(|image| Rule34Post::Api(Box::new(image)))
+            .collect())
+    }
+
+    fn next_listing_page(&mut self, context: &Context) -> crate::Result<Page<Rule34Post>> {
+        let text = context
+            .client
+            .get(self.get_url())
+            .send_with_retry()?
+            .text()?;
+        let mut links = context.get_gallery_page_links(&text);
+        self.idx += links.len();
+        Ok(std::iter::from_fn(|| links.pop())
+            .map(Rule34Post::Listing)
+            .collect())
+    }
+
     fn get_url(&self) -> String {
         match self.idx {
             // We don't want to give away that we're doing this via automation
@@ -176,13 +352,18 @@ impl Rule34Pager {
 impl Pager for Rule34Pager {
     type Context = Context;
 
-    type Item = ImageId;
+    type Item = Rule34Post;
 
     fn next_page(&mut self, context: &Self::Context) -> crate::Result<Page<Self::Item>> {
-        let text = context.client.get(self.get_url()).send()?.text()?;
-        let links = context.get_gallery_page_links(&text);
-        self.idx += links.len();
-        Ok(context.get_gallery_page_links(&text))
+        if !self.use_listing {
+            match self.next_api_page(context) {
+                Ok(page) => return Ok(page),
+                // The API wants credentials on some days and not on others. The listing
+                // doesn't, so we stick with it from here on.
+                Err(_) => self.use_listing = true,
+            }
+        }
+        self.next_listing_page(context)
     }
 }
 
@@ -190,31 +371,39 @@ impl Pager for Rule34Pager {
 // skip behavior for rule34. Sure wish the rust crew would go on and
 // merge specialization....
 pub struct Rule34Gallery {
-    context: Context,
+    context: Arc<Context>,
     pager: Rule34Pager,
-    current: Page<ImageId>,
+    current: Page<Rule34Post>,
+    filter: Filter,
 }
 
 impl Gallery for Rule34Gallery {
+    type Context = Context;
     type Item = ResponseGalleryItem;
+    type Pending = Rule34Post;
 
     // Copy/paste from standard gallery implementation
-    fn next(&mut self) -> Option<crate::Result<Self::Item>> {
+    fn next_pending(&mut self) -> Option<crate::Result<Self::Pending>> {
         if self.current.is_empty() {
-            self.current = match self.pager.next_page(&self.context) {
+            self.current = match self.filter.next_page(&mut self.pager, &self.context) {
                 Ok(page) if page.is_empty() => return None,
                 Ok(page) => page,
                 Err(e) => return Some(Err(e)),
             };
         }
 
-        let item = self.current.pop()?;
-        Some(item.download(&self.context))
+        self.current.pop().map(Ok)
     }
 
-    fn advance_by(&mut self, n: usize) -> crate::Result<usize> {
-        const RULE34_PAGE_SIZE: usize = 42; // NEVER CHANGE, GUYS!
+    fn context(&self) -> &Arc<Self::Context> {
+        &self.context
+    }
+
+    fn buffered(&self) -> Option<usize> {
+        Some(self.current.len())
+    }
 
+    fn advance_by(&mut self, n: usize) -> crate::Result<usize> {
         let mut skipped = 0;
         let mut skip_remaining = n;
 
@@ -223,13 +412,13 @@ impl Gallery for Rule34Gallery {
             skipped = advance_pages * RULE34_PAGE_SIZE;
             skip_remaining -= skipped;
             self.pager.idx += skipped;
-            self.current = self.pager.next_page(&self.context)?;
+            self.current = self.filter.next_page(&mut self.pager, &self.context)?;
         }
 
         // Copied from PagedGallery impl
         loop {
             if self.current.is_empty() {
-                self.current = self.pager.next_page(&self.context)?;
+                self.current = self.filter.next_page(&mut self.pager, &self.context)?;
             }
 
             if self.current.len() > skip_remaining {
@@ -259,22 +448,19 @@ fn extract_search(url: &str) -> crate::Result<String> {
         })
 }
 
-fn build_client() -> Client {
-    use std::sync::Arc;
-
-    use reqwest::{cookie::Jar, Url};
+fn build_client(settings: &Settings) -> Client {
+    use reqwest::Url;
 
     let url: Url = "https://rule34.xxx".parse().unwrap();
-    let jar = Jar::default();
+    let jar = crate::cookies::jar(settings);
     jar.add_cookie_str("gdpr=1", &url);
     jar.add_cookie_str("gdpr-disable-ga=1", &url);
     jar.add_cookie_str("resize-notification=1", &url);
     jar.add_cookie_str("resize-original=1", &url);
 
-    Client::builder()
+    Client::builder(settings.retry)
         .user_agent(USER_AGENT)
-        .cookie_store(true)
-        .cookie_provider(Arc::new(jar))
+        .cookie_provider(jar)
         .build()
         .unwrap()
 }
@@ -283,3 +469,62 @@ fn get_single_tag(tags: &str) -> Option<&str> {
     let mut tags = tags.trim_matches('+').split('+');
     tags.next().filter(|_| tags.next().is_none())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gallery::{booru::gelbooru, Downloadable},
+        Settings,
+    };
+
+    use super::{Context, ImageId, Rule34Post};
+
+    #[test]
+    fn can_read_listing() {
+        let text = r#"<span id="s101" class="thumb"><a id="p101" href="index.php?page=post&s=view&id=101" >
+            <img src="thumbnail_101.jpg" alt="1girl sweat" border="0" title=" 1girl sweat  score:12 rating:Explicit" class="preview"/></a></span>
+            <a href="index.php?page=post&s=view&id=102">next</a>"#;
+
+        let context = Context::new(&Settings::default());
+        let mut page = context.get_gallery_page_links(text);
+        let first = page.pop().unwrap();
+        let post = first.post(&context).unwrap();
+        assert_eq!("101", first.0);
+        assert_eq!(vec!["1girl", "sweat"], post.tags);
+        assert_eq!(Some(12), post.score);
+        assert_eq!(Some("Explicit"), post.rating);
+
+        let second = page.pop().unwrap();
+        assert_eq!("102", second.0);
+        assert!(second.post(&context).is_none());
+    }
+
+    #[test]
+    fn can_read_api_posts() -> crate::Result<()> {
+        let text = r#"[{"id":9001,"directory":"4182","image":"c0ffee.mp4","hash":"c0ffee","width":1920,"height":1080,"file_url":"https://api-cdn.rule34.xxx/images/4182/c0ffee.mp4","tags":"1girl animated","rating":"explicit","score":40,"owner":"someone"}]"#;
+
+        let context = Context::new(&Settings::default());
+        let mut posts = gelbooru::read_posts(text)?.unwrap();
+        let post = Rule34Post::Api(Box::new(posts.pop_front().unwrap()));
+        assert_eq!(Some("c0ffee"), post.md5(&context).as_deref());
+
+        // The same post, as scraped, must be recognized as the same post.
+        let listed = Rule34Post::Listing(ImageId("9001".into(), None));
+        assert_eq!(listed.source(&context), post.source(&context));
+        assert_eq!(Some(40), post.post(&context).unwrap().score);
+
+        let media = post.resolve(&context)?;
+        assert_eq!(
+            "https://api-cdn.rule34.xxx/images/4182/c0ffee.mp4",
+            media.url
+        );
+        assert_eq!(
+            Some("https://rule34.xxx/index.php?page=post&s=view&id=9001"),
+            media
+                .metadata
+                .and_then(|metadata| metadata.source)
+                .as_deref()
+        );
+        Ok(())
+    }
+}
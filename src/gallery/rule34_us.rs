@@ -1,14 +1,25 @@
 use regex::Regex;
 
-use super::{prelude::*, Gallery};
-
-pub fn extract(url: &str) -> crate::Result<(PagedGallery<Rule34Pager>, Option<String>)> {
+use super::prelude::*;
+
+pub static SITE: Site = Site {
+    name: "Rule34.us",
+    hosts: &["rule34.us"],
+    routes: &["/index.php?r=posts/index&q=<tags>"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(PagedGallery<Rule34Pager>, Option<String>)> {
     let query = get_query(url)?;
     let pager = Rule34Pager::new(query);
     let gallery = PagedGallery {
-        context: Context::new(),
+        context: Arc::new(Context::new(settings)),
         pager,
         current: Page::Empty,
+        filter: settings.filter.clone(),
     };
 
     match get_single_tag(&gallery.pager.query).map(|x| x.to_owned()) {
@@ -23,9 +34,9 @@ pub struct Context {
 }
 
 impl Context {
-    fn new() -> Self {
+    fn new(settings: &Settings) -> Self {
         Self {
-            client: super::build_client(),
+            client: super::build_client(settings),
             image_url_expr: Regex::new(
                 r#"a href="([^"]+/images/[^"]+)"|img src="([^"]+/images/[^"]+)""#,
             )
@@ -34,35 +45,6 @@ impl Context {
     }
 }
 
-pub struct Rule34Gallery {
-    inner: PagedGallery<Rule34Pager>,
-}
-
-impl Gallery for Rule34Gallery {
-    type Item = ResponseGalleryItem;
-
-    fn next(&mut self) -> Option<crate::Result<Self::Item>> {
-        self.inner.next()
-    }
-
-    fn advance_by(&mut self, n: usize) -> crate::Result<usize> {
-        const PAGE_SIZE: usize = 42;
-
-        let mut skipped = 0;
-        let mut skip_remaining = 0;
-
-        let advance_pages = n / PAGE_SIZE;
-        if advance_pages > 0 {
-            skipped = advance_pages * PAGE_SIZE;
-            skip_remaining -= skipped;
-            self.inner.pager.page += skipped;
-            self.inner.current = self.inner.pager.next_page(&self.inner.context)?;
-        }
-
-        self.inner.default_advance_by(skipped, skip_remaining)
-    }
-}
-
 pub struct Rule34Pager {
     query: String,
     page: usize,
@@ -77,7 +59,10 @@ impl Rule34Pager {
             query: query.into(),
             page: 0,
             is_complete: false,
-            gallery_item_id_expr: Regex::new(r#"<a id="(\d+)""#).unwrap(),
+            gallery_item_id_expr: Regex::new(
+                r#"<a id="(\d+)"(?:[^>]*>\s*<img[^>]*?title="([^"]*)")?"#,
+            )
+            .unwrap(),
         }
     }
 
@@ -88,6 +73,16 @@ impl Rule34Pager {
         let page = &self.page;
         format!("https://rule34.us/index.php?r=posts/index&q={query}&page={page}")
     }
+
+    fn read_listing(&self, text: &str) -> Page<GalleryItemId> {
+        self.gallery_item_id_expr
+            .captures_iter(text)
+            .filter_map(|cx| {
+                let title = cx.get(2).map(|title| title.as_str().to_owned());
+                Some(GalleryItemId(cx.get(1)?.as_str().into(), title))
+            })
+            .collect()
+    }
 }
 
 impl Pager for Rule34Pager {
@@ -102,23 +97,18 @@ impl Pager for Rule34Pager {
 
         let request = self.format_request();
         self.page += 1;
-        let text = context.client.get(request).send()?.text()?;
+        let text = context.client.get(request).send_with_retry()?.text()?;
 
         // https://rule34.us/index.php?r=posts/view&id=4597827
         // https://rule34.us/index.php?r=posts/view&id=4597826
         // https://rule34.us/index.php?r=posts/view&id=4545892
 
-        let identifiers = self
-            .gallery_item_id_expr
-            .captures_iter(&text)
-            .filter_map(|cx| cx.get(1))
-            .map(|cx| GalleryItemId(cx.as_str().into()));
-
-        Ok(identifiers.collect())
+        Ok(self.read_listing(&text))
     }
 }
 
-pub struct GalleryItemId(String);
+/// A post's id, along with the title of its thumbnail in the listing, which lists its tags.
+pub struct GalleryItemId(String, Option<String>);
 
 impl GalleryItemId {
     fn page_url(&self) -> String {
@@ -133,7 +123,20 @@ impl Downloadable for GalleryItemId {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
-        let text = context.client.get(self.page_url()).send()?.text()?;
+        let media = self.resolve(context)?;
+        Ok(context
+            .client
+            .get(media.url)
+            .send_with_retry()
+            .map(ResponseGalleryItem::new)?)
+    }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
+        let text = context
+            .client
+            .get(self.page_url())
+            .send_with_retry()?
+            .text()?;
         let url = context
             .image_url_expr
             .captures_iter(&text)
@@ -147,11 +150,15 @@ impl Downloadable for GalleryItemId {
             })?
             .as_str();
 
-        Ok(context
-            .client
-            .get(url)
-            .send()
-            .map(ResponseGalleryItem::new)?)
+        Ok(Media::new(url))
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        self.page_url()
+    }
+
+    fn post(&self, _: &Self::Context) -> Option<Post<'_>> {
+        self.1.as_deref().map(super::rule34::read_thumbnail_title)
     }
 }
 
@@ -173,6 +180,23 @@ fn get_single_tag(tags: &str) -> Option<&str> {
 
 #[cfg(test)]
 mod tests {
+    use crate::{gallery::Downloadable, Settings};
+
+    #[test]
+    fn reads_tags_from_listing() {
+        let text = r#"<div class="thumbail-container"><div><a id="4597827" href="https://rule34.us/index.php?r=posts/view&amp;id=4597827">
+            <img src="https://img2.rule34.us/thumbnails/ab/cd/thumbnail_abcd.jpg" title="korra, sweat, the legend of korra" class="" /></a></div>"#;
+
+        let pager = super::Rule34Pager::new("korra");
+        let context = super::Context::new(&Settings::default());
+        let item = pager.read_listing(text).pop().unwrap();
+        assert_eq!("4597827", item.0);
+        assert_eq!(
+            vec!["korra", "sweat", "the legend of korra"],
+            item.post(&context).unwrap().tags
+        );
+    }
+
     #[test]
     fn get_query() {
         let url = "https://rule34.us/index.php?r=posts/index&q=korra+sweat+";
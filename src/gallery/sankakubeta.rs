@@ -1,15 +1,18 @@
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     config::{Configuration, Key},
     gallery::prelude::*,
+    session::SavedLogin,
     tags::Tags,
 };
 
 #[derive(Clone, Debug, Serialize)]
-struct LoginRequest<'a> {
-    login: &'a str,
-    password: &'a str,
+#[serde(untagged)]
+enum TokenRequest<'a> {
+    Login { login: &'a str, password: &'a str },
+    Refresh { refresh_token: &'a str },
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -17,7 +20,7 @@ struct LoginResponse {
     // success: bool,
     // token_type: String,
     access_token: String,
-    // refresh_token: String,
+    refresh_token: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -34,19 +37,49 @@ struct PageResponseMetadata {
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Image {
-    // id: i64,
-    // rating: String,
+    id: i64,
+    rating: Option<String>,
     file_url: String,
-    // width: i32,
-    // height: i32,
+    width: Option<u32>,
+    height: Option<u32>,
+    sample_url: Option<String>,
+    sample_width: Option<u32>,
+    sample_height: Option<u32>,
+    preview_url: Option<String>,
+    preview_width: Option<u32>,
+    preview_height: Option<u32>,
     // file_size: i32,
     // file_type: String,
-    // md5: String,
+    md5: Option<String>,
+    total_score: Option<i64>,
+    author: Option<Author>,
+    #[serde(default)]
+    tags: Vec<Tag>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Author {
+    name: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Tag {
+    name: String,
 }
 
 // https://beta.sankakucomplex.com/?tags=slave%20sweat%20welts
 
-pub fn extract(url: &str) -> crate::Result<(PagedGallery<SankakuPager>, Option<String>)> {
+pub static SITE: Site = Site {
+    name: "Sankaku Complex",
+    hosts: &["beta.sankakucomplex.com"],
+    routes: &["/?tags=<tags>"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(PagedGallery<SankakuPager>, Option<String>)> {
     // I doubt we'll see hashes at the end of these urls, but who knows?
     let url = url.trim_end_matches('#');
     let tags = Tags::try_from_url(url, "%20").ok_or_else(|| {
@@ -57,42 +90,50 @@ pub fn extract(url: &str) -> crate::Result<(PagedGallery<SankakuPager>, Option<S
     })?;
 
     // We need to sign in to get the goodies.
-    let config = Configuration::init();
-    let username = config.get_config(Key::SankakuUser)?;
-    let password = config.get_config(Key::SankakuPass)?;
-    let client = build_client();
-
-    // This process pulls both the access and refresh token from the login response,
-    // but according to my research the access token will last something like 48 hours.
-    // In other words, we really have no need of the refresh token.
-    let LoginResponse { access_token, .. } = client
-        .post("https://capi-v2.sankakucomplex.com/auth/token")
-        .json(&LoginRequest {
-            login: username,
-            password,
-        })
-        .send()?
-        .json()?;
+    static SESSION: Session<(String, bool)> = Session::new();
+    let (token, restored) = SESSION.get_or_try_init(|| restore_or_log_in(settings))?;
+    let mut context = Context {
+        client: build_client(settings),
+        token,
+        quality: settings.quality,
+    };
+
+    // The first page tells us whether a saved token is still any good. If it isn't, we renew it
+    // and start over.
+    let mut pager = SankakuPager {
+        tags,
+        next: None,
+        has_started: false,
+    };
+    let mut current = match pager.next_page(&context) {
+        Err(Error::Authentication(_)) if restored => {
+            SESSION.reset();
+            let saved = SavedLogin::load(SITE.hosts[0]).unwrap_or_default();
+            (context.token, _) = SESSION.get_or_try_init(|| {
+                renew(settings, saved.refresh_token.as_deref()).map(|token| (token, false))
+            })?;
+            pager.has_started = false;
+            pager.next_page(&context)?
+        }
+        page => page?,
+    };
+    settings.filter.filter_page(&mut current, &context);
 
     let gallery = PagedGallery {
-        context: Context {
-            client,
-            token: access_token,
-        },
-        pager: SankakuPager {
-            tags,
-            next: None,
-            has_started: false,
-        },
-        current: Page::Empty,
+        context: Arc::new(context),
+        pager,
+        current,
+        filter: settings.filter.clone(),
     };
 
     Ok((gallery, None))
 }
 
+#[derive(Clone)]
 pub struct Context {
     client: Client,
     token: String,
+    quality: Quality,
 }
 
 pub struct SankakuPager {
@@ -124,32 +165,164 @@ impl Pager for SankakuPager {
             None => return Ok(Page::Empty),
         };
 
-        let PageResponse { meta, data } = context
+        let response = context
             .client
             .get(url)
             .bearer_auth(&context.token)
-            .send()?
-            .json()?;
+            .send_with_retry()?;
+
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Err(Error::Authentication(SITE.name));
+        }
+
+        let PageResponse { meta, data } = response.json()?;
 
         self.next = meta.next;
         Ok(Page::Items(data))
     }
 }
 
+impl Image {
+    fn variant(&self, quality: Quality) -> Variant<String> {
+        let original =
+            Variant::new(Kind::Original, self.file_url.clone()).with_size(self.width, self.height);
+        let others = [
+            (
+                Kind::Sample,
+                &self.sample_url,
+                self.sample_width,
+                self.sample_height,
+            ),
+            (
+                Kind::Preview,
+                &self.preview_url,
+                self.preview_width,
+                self.preview_height,
+            ),
+        ];
+        let variants = std::iter::once(original)
+            .chain(others.into_iter().filter_map(|(kind, url, width, height)| {
+                let url = url.as_deref().filter(|url| !url.is_empty())?;
+                Some(Variant::new(kind, url.to_owned()).with_size(width, height))
+            }))
+            .collect();
+        quality.choose(variants).unwrap()
+    }
+}
+
 impl Downloadable for Image {
     type Context = Context;
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
+        let media = self.resolve(context)?;
         Ok(context
             .client
-            .get(self.file_url)
-            .send()
-            .map(ResponseGalleryItem::new)?)
+            .get(media.url)
+            .send_with_retry()
+            .map(ResponseGalleryItem::new)?
+            .with_metadata(media.metadata))
+    }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
+        let variant = self.variant(context.quality);
+        let metadata = Metadata {
+            id: Some(self.id.to_string()),
+            source: Some(self.source(context)),
+            tags: self.tags.into_iter().map(|tag| tag.name).collect(),
+            rating: self.rating,
+            uploader: self.author.map(|author| author.name),
+            width: variant.width,
+            height: variant.height,
+            md5: self.md5.filter(|_| variant.is_original()),
+            ..Default::default()
+        };
+        Ok(Media::new(variant.value).with_metadata(metadata))
     }
+
+    fn source(&self, _: &Self::Context) -> String {
+        // File urls are signed and expire, so they're no good for this.
+        format!("https://beta.sankakucomplex.com/post/show/{}", self.id)
+    }
+
+    fn md5(&self, context: &Self::Context) -> Option<String> {
+        self.md5
+            .clone()
+            .filter(|_| self.variant(context.quality).is_original())
+    }
+
+    fn post(&self, _: &Self::Context) -> Option<Post<'_>> {
+        Some(Post {
+            tags: self.tags.iter().map(|tag| &*tag.name).collect(),
+            rating: self.rating.as_deref(),
+            width: self.width,
+            height: self.height,
+            score: self.total_score,
+            ..Post::default().with_file_url(&self.file_url)
+        })
+    }
+}
+
+/// Signs in, by way of a saved token where we have one; the flag is set when we do. Returns the
+/// access token.
+fn restore_or_log_in(settings: &Settings) -> crate::Result<(String, bool)> {
+    let saved = SavedLogin::load(SITE.hosts[0]).unwrap_or_default();
+    match saved.access_token {
+        Some(token) if !saved.is_expired() => Ok((token, true)),
+        _ => renew(settings, saved.refresh_token.as_deref()).map(|token| (token, false)),
+    }
+}
+
+/// Gets a fresh access token, using the refresh token if we have one and logging in if that
+/// doesn't work out.
+fn renew(settings: &Settings, refresh_token: Option<&str>) -> crate::Result<String> {
+    if let Some(refresh_token) = refresh_token {
+        if let Ok(token) = request_token(settings, &TokenRequest::Refresh { refresh_token }) {
+            return Ok(token);
+        }
+    }
+
+    let config = Configuration::init();
+    request_token(
+        settings,
+        &TokenRequest::Login {
+            login: config.get_config(Key::SankakuUser)?,
+            password: config.get_config(Key::SankakuPass)?,
+        },
+    )
+}
+
+fn request_token(settings: &Settings, request: &TokenRequest) -> crate::Result<String> {
+    // According to my research the access token will last something like 48 hours, but the
+    // login response doesn't say, so we play it safe and renew a day in.
+    const ACCESS_TOKEN_LIFETIME: i64 = 24 * 60 * 60;
+
+    let response = build_client(settings)
+        .post("https://capi-v2.sankakucomplex.com/auth/token")
+        .json(request)
+        .send_with_retry()?;
+
+    if !response.status().is_success() {
+        return Err(Error::Authentication(SITE.name));
+    }
+
+    let LoginResponse {
+        access_token,
+        refresh_token,
+    } = response.json()?;
+
+    SavedLogin {
+        access_token: Some(access_token.clone()),
+        refresh_token,
+        ..Default::default()
+    }
+    .expires_in(ACCESS_TOKEN_LIFETIME)
+    .save(SITE.hosts[0]);
+
+    Ok(access_token)
 }
 
-fn build_client() -> Client {
+fn build_client(settings: &Settings) -> Client {
     use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
 
     let mut headers = HeaderMap::new();
@@ -158,9 +331,7 @@ fn build_client() -> Client {
         HeaderValue::from_static("application/vnd.sankaku.api+json;v=2"),
     );
 
-    Client::builder()
-        .user_agent(USER_AGENT)
-        .cookie_store(true)
+    client_builder(settings)
         .default_headers(headers)
         .build()
         .unwrap()
@@ -1,42 +1,206 @@
 //! Gallery implementation for scrolller.com
 //!
-//! I'm really only trying to cover individual images at the moment. The way this idiocy works is
-//! that they have a GraphQL API that returns a pile of garbage if you just ask nicely based on the
-//! URL. GraphQL itself is a fucking retarded API, because I mean... seriously? But it kinda
-//! works...
+//! Covers individual posts as well as subreddit (/r/<name>) and user (/u/<name>) feeds. The way
+//! this idiocy works is that they have a GraphQL API that returns a pile of garbage if you just
+//! ask nicely based on the URL. GraphQL itself is a fucking retarded API, because I mean...
+//! seriously? But it kinda works...
+//!
+//! Feeds are paged by an opaque iterator handed back with each page, and can be narrowed to
+//! pictures, videos or albums with the site's own `?filter=` parameter. Albums are listed as a
+//! single post, so each is fetched again to get at its children.
 
 // Test url: https://scrolller.com/whitney-johns-ewhhmc5wuo
 
+use serde::de::DeserializeOwned;
+use url::Url;
+
 use crate::gallery::prelude::*;
 
-use self::data::{Query, Response};
+use self::data::{FeedResponse, PostResponse, Query, SubredditPost};
 
 static API_URL: &str = "https://api.scrolller.com/api/v2/graphql";
 
 static QUERY: &str = "query SubredditPostQuery( $url: String! ) { getSubredditPost(url: $url) { \
-    id url title fullLengthSource gfycatSource redgifsSource mediaSources { url width height \
-        isOptimized } } }";
+    id url title fullLengthSource gfycatSource redgifsSource albumUrl mediaSources { url width \
+        height isOptimized } albumContent { mediaSources { url width height isOptimized } } } }";
 
-pub fn extract(url: &str) -> crate::Result<(UnpagedGallery<Image>, Option<String>)> {
-    let query = Query::from_url(url);
-    let client = Client::builder().user_agent(USER_AGENT).build()?;
-    let response: Response = client
-        .post(API_URL)
-        .json(&query)
-        .header("accept", "application/json")
-        .send()?
-        .json()?;
+static FEED_QUERY: &str = "query SubredditQuery( $url: String! $filter: SubredditPostFilter \
+    $iterator: String ) { getSubreddit(url: $url) { children(limit: 50 iterator: $iterator \
+        filter: $filter) { iterator items { id url title fullLengthSource gfycatSource \
+            redgifsSource albumUrl mediaSources { url width height isOptimized } } } } }";
+
+pub static SITE: Site = Site {
+    name: "Scrolller",
+    hosts: &["scrolller.com"],
+    routes: &["/<gallery>", "/r/<subreddit>", "/u/<user>"],
+    extractor: |url, settings| match read_feed(url) {
+        Ok(Some(_)) => boxed(url, settings, extract_feed),
+        Ok(None) => boxed(url, settings, extract),
+        Err(e) => Err(e),
+    },
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(UnpagedGallery<Image>, Option<String>)> {
+    let client = client_builder(settings).build()?;
+    let response: PostResponse = query(&client, &Query::post(url))?;
+    let items = response
+        .data
+        .get_subreddit_post
+        .images(settings.quality)
+        .collect();
 
     Ok((
         UnpagedGallery {
-            context: client,
-            items: response.urls().map(Image).collect(),
+            context: Arc::new(client),
+            items,
         },
         None,
     ))
 }
 
-pub struct Image(String);
+pub fn extract_feed(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(PagedGallery<ScrolllerPager>, Option<String>)> {
+    let feed = read_feed(url)?.ok_or_else(|| {
+        Error::Unsupported(
+            UnsupportedError::Route,
+            String::from("Scrolller feeds are /r/<subreddit> or /u/<user>"),
+        )
+    })?;
+
+    let gallery = PagedGallery {
+        context: Arc::new(client_builder(settings).build()?),
+        pager: ScrolllerPager {
+            path: feed.path,
+            filter: feed.filter,
+            iterator: None,
+            is_complete: false,
+            quality: settings.quality,
+        },
+        current: Page::Empty,
+        filter: settings.filter.clone(),
+    };
+
+    Ok((gallery, Some(feed.name)))
+}
+
+struct Feed {
+    /// The feed's path, which is what the API wants in place of a url.
+    path: String,
+    name: String,
+    filter: Option<&'static str>,
+}
+
+/// Reads a subreddit or user feed from a url, if that's what it is.
+fn read_feed(url: &str) -> crate::Result<Option<Feed>> {
+    let url = Url::parse(url)?;
+    let mut segments = url.path_segments().into_iter().flatten();
+    let (kind, name) = match (segments.next(), segments.next()) {
+        (Some(kind @ ("r" | "u")), Some(name)) if !name.is_empty() => (kind, name),
+        _ => return Ok(None),
+    };
+
+    let filter = match url.query_pairs().find(|(key, _)| key == "filter") {
+        None => None,
+        Some((_, filter)) => Some(match &*filter {
+            "pictures" => "PICTURE",
+            "videos" => "VIDEO",
+            "albums" => "ALBUM",
+            _ => {
+                return Err(Error::Unsupported(
+                    UnsupportedError::Route,
+                    String::from("Scrolller filters are pictures, videos or albums"),
+                ))
+            }
+        }),
+    };
+
+    Ok(Some(Feed {
+        path: format!("/{kind}/{name}"),
+        name: name.into(),
+        filter,
+    }))
+}
+
+fn query<T: DeserializeOwned>(client: &Client, query: &Query) -> crate::Result<T> {
+    use reqwest::header::{HeaderValue, ACCEPT};
+
+    Ok(client
+        .post(API_URL)
+        .json(query)
+        .header(ACCEPT, HeaderValue::from_static("application/json"))
+        .send_with_retry()?
+        .json()?)
+}
+
+pub struct ScrolllerPager {
+    path: String,
+    filter: Option<&'static str>,
+    iterator: Option<String>,
+    is_complete: bool,
+    quality: Quality,
+}
+
+impl Pager for ScrolllerPager {
+    type Context = Client;
+
+    type Item = Image;
+
+    fn next_page(&mut self, context: &Self::Context) -> crate::Result<Page<Self::Item>> {
+        // Posts with nothing we can download don't make for the end of the feed.
+        loop {
+            if self.is_complete {
+                return Ok(Page::Empty);
+            }
+
+            let request = Query::feed(&self.path, self.filter, self.iterator.as_deref());
+            let response: FeedResponse = query(context, &request)?;
+            let children = match response.data.get_subreddit {
+                Some(subreddit) => subreddit.children,
+                None => {
+                    self.is_complete = true;
+                    return Ok(Page::Empty);
+                }
+            };
+
+            // The page is built before we move on, so that an album we fail to fetch leaves
+            // this page to be read again rather than skipped.
+            let is_last = children.iterator.is_none() || children.items.is_empty();
+            let mut page = VecDeque::new();
+            for post in children.items {
+                page.extend(expand_album(context, post)?.images(self.quality));
+            }
+
+            self.is_complete = is_last;
+            self.iterator = children.iterator;
+            if !page.is_empty() {
+                return Ok(Page::Items(page));
+            }
+        }
+    }
+}
+
+/// Fetches an album's children, which its listing in a feed leaves out.
+fn expand_album(client: &Client, post: SubredditPost) -> crate::Result<SubredditPost> {
+    match post.album_path() {
+        Some(path) => {
+            let response: PostResponse = query(client, &Query::post(path))?;
+            Ok(response.data.get_subreddit_post)
+        }
+        None => Ok(post),
+    }
+}
+
+pub struct Image {
+    url: String,
+    /// The post, along with the image's place in it where it's one of an album's.
+    source: String,
+    metadata: Metadata,
+}
 
 impl Downloadable for Image {
     type Context = Client;
@@ -44,60 +208,129 @@ impl Downloadable for Image {
     type Output = ResponseGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
-        Ok(ResponseGalleryItem::new(context.get(self.0).send()?))
+        let media = self.resolve(context)?;
+        Ok(
+            ResponseGalleryItem::new(context.get(media.url).send_with_retry()?)
+                .with_metadata(media.metadata),
+        )
+    }
+
+    fn resolve(self, _: &Self::Context) -> crate::Result<Media> {
+        Ok(Media::new(self.url).with_metadata(self.metadata))
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        self.source.clone()
     }
 }
 
 mod data {
     use serde::{Deserialize, Serialize};
 
-    use super::QUERY;
+    use crate::{
+        metadata::Metadata,
+        quality::{Kind, Quality, Variant},
+    };
+
+    use super::{Image, FEED_QUERY, QUERY};
 
-    #[derive(Serialize, Deserialize)]
+    #[derive(Serialize)]
     pub struct Query<'a> {
         query: &'static str,
         variables: Variables<'a>,
     }
 
-    #[derive(Serialize, Deserialize)]
+    #[derive(Serialize)]
     pub struct Variables<'a> {
         url: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        filter: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        iterator: Option<&'a str>,
     }
 
     impl<'a> Query<'a> {
-        pub fn from_url(url: &'a str) -> Self {
+        pub fn post(url: &'a str) -> Self {
             Self {
                 query: QUERY,
                 variables: Variables {
                     url: url.strip_prefix("https://scrolller.com").unwrap_or(url),
+                    filter: None,
+                    iterator: None,
+                },
+            }
+        }
+
+        pub fn feed(path: &'a str, filter: Option<&'a str>, iterator: Option<&'a str>) -> Self {
+            Self {
+                query: FEED_QUERY,
+                variables: Variables {
+                    url: path,
+                    filter,
+                    iterator,
                 },
             }
         }
     }
 
-    #[derive(Serialize, Deserialize)]
-    pub struct Response {
-        data: Data,
+    #[derive(Deserialize)]
+    pub struct PostResponse {
+        pub data: PostData,
     }
 
-    #[derive(Serialize, Deserialize)]
-    pub struct Data {
+    #[derive(Deserialize)]
+    pub struct PostData {
         #[serde(rename = "getSubredditPost")]
-        get_subreddit_post: GetSubredditPost,
+        pub get_subreddit_post: SubredditPost,
+    }
+
+    #[derive(Deserialize)]
+    pub struct FeedResponse {
+        pub data: FeedData,
+    }
+
+    #[derive(Deserialize)]
+    pub struct FeedData {
+        #[serde(rename = "getSubreddit")]
+        pub get_subreddit: Option<Subreddit>,
     }
 
-    #[derive(Serialize, Deserialize)]
+    #[derive(Deserialize)]
+    pub struct Subreddit {
+        pub children: Children,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Children {
+        pub iterator: Option<String>,
+        #[serde(default)]
+        pub items: Vec<SubredditPost>,
+    }
+
+    #[derive(Deserialize)]
     #[serde(rename_all = "camelCase")]
-    pub struct GetSubredditPost {
+    pub struct SubredditPost {
         id: i64,
+        url: Option<String>,
         title: Option<String>,
         full_length_source: Option<String>,
         gfycat_source: Option<String>,
         redgifs_source: Option<String>,
+        album_url: Option<String>,
+        #[serde(default)]
         media_sources: Vec<MediaSource>,
+        album_content: Option<Vec<AlbumItem>>,
     }
 
-    #[derive(Serialize, Deserialize)]
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct AlbumItem {
+        #[serde(default)]
+        media_sources: Vec<MediaSource>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
     pub struct MediaSource {
         url: String,
         width: Option<i64>,
@@ -106,68 +339,194 @@ mod data {
         is_optimized: bool,
     }
 
-    impl Response {
-        pub fn urls(&self) -> impl Iterator<Item = String> + '_ {
-            struct RepsonseIter<'a> {
-                idx: u8,
-                source: &'a Response,
-            }
+    impl SubredditPost {
+        /// The path of an album whose children we've yet to see.
+        pub fn album_path(&self) -> Option<&str> {
+            let url = self.url.as_deref()?;
+            self.album_url
+                .as_ref()
+                .filter(|_| self.album_content.is_none())
+                .map(|_| url)
+        }
 
-            impl<'a> RepsonseIter<'a> {
-                fn new(response: &'a Response) -> Self {
-                    RepsonseIter {
-                        idx: 0,
-                        source: response,
-                    }
-                }
+        pub fn metadata(&self) -> Metadata {
+            Metadata {
+                id: Some(self.id.to_string()),
+                source: self
+                    .url
+                    .as_deref()
+                    .map(|url| format!("https://scrolller.com{url}")),
+                title: self.title.clone(),
+                ..Default::default()
             }
+        }
 
-            impl<'a> Iterator for RepsonseIter<'a> {
-                type Item = String;
-
-                fn next(&mut self) -> Option<Self::Item> {
-                    if self.idx == 3 {
-                        return None;
-                    }
-
-                    self.idx += 1;
-
-                    match self.idx {
-                        1 => self
-                            .source
-                            .data
-                            .get_subreddit_post
-                            .full_length_source
-                            .as_deref()
-                            .map(|s| s.into()),
-
-                        2 => self
-                            .source
-                            .data
-                            .get_subreddit_post
-                            .gfycat_source
-                            .as_deref()
-                            .map(|s| s.into()),
-
-                        3 => self
-                            .source
-                            .data
-                            .get_subreddit_post
-                            .redgifs_source
-                            .as_deref()
-                            .map(|s| s.into()),
-
-                        _ => unreachable!("We have an early return for this."),
-                    }
-                }
-            }
+        /// What to download for the post: one image, or one for each child of an album.
+        pub fn images(&self, quality: Quality) -> impl Iterator<Item = Image> + '_ {
+            let variants = match &self.album_content {
+                Some(children) if !children.is_empty() => children
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, child)| {
+                        (Some(idx + 1), media_variants(&child.media_sources, false))
+                    })
+                    .collect(),
+                _ => vec![(None, self.variants())],
+            };
 
-            self.data
-                .get_subreddit_post
-                .media_sources
+            variants.into_iter().filter_map(move |(child, variants)| {
+                let variant = quality.choose(variants)?;
+
+                // Whichever variant we chose, it's the same post (or member of an album).
+                let post = self.metadata().source;
+                let source = match (post, child) {
+                    (Some(post), Some(child)) => format!("{post}#{child}"),
+                    (Some(post), None) => post,
+                    (None, _) => variant.value.clone(),
+                };
+
+                Some(Image {
+                    url: variant.value,
+                    source,
+                    metadata: Metadata {
+                        width: variant.width,
+                        height: variant.height,
+                        ..self.metadata()
+                    },
+                })
+            })
+        }
+
+        /// Every form in which the post is served, best first.
+        pub fn variants(&self) -> Vec<Variant<String>> {
+            let mut variants: Vec<_> = self
+                .full_length_source
                 .iter()
-                .map(|s| s.url.clone())
-                .chain(RepsonseIter::new(self))
+                .map(|url| Variant::new(Kind::Original, url.clone()))
+                .collect();
+            let has_original = !variants.is_empty();
+            variants.extend(media_variants(&self.media_sources, has_original));
+
+            // The post's copies elsewhere are a last resort.
+            if variants.is_empty() {
+                variants.extend(
+                    [&self.gfycat_source, &self.redgifs_source]
+                        .into_iter()
+                        .flatten()
+                        .map(|url| Variant::new(Kind::Original, url.clone())),
+                );
+            }
+            variants
         }
     }
+
+    /// Media sources differ only in size and format, so the largest counts as the original
+    /// (unless there's one already) and the smallest as the preview.
+    fn media_variants(sources: &[MediaSource], has_original: bool) -> Vec<Variant<String>> {
+        let mut sources: Vec<_> = sources.iter().collect();
+        sources.sort_by_key(|source| {
+            let area = source.width.unwrap_or_default() * source.height.unwrap_or_default();
+            (std::cmp::Reverse(area), source.is_optimized)
+        });
+
+        let count = sources.len();
+        sources
+            .into_iter()
+            .enumerate()
+            .map(|(idx, source)| {
+                let kind = match idx {
+                    0 if !has_original => Kind::Original,
+                    idx if idx > 0 && idx + 1 == count => Kind::Preview,
+                    _ => Kind::Sample,
+                };
+                let size = |size: Option<i64>| size.and_then(|size| size.try_into().ok());
+                Variant::new(kind, source.url.clone())
+                    .with_size(size(source.width), size(source.height))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::quality::{Kind, Quality};
+
+    use super::data::{FeedResponse, PostResponse};
+
+    #[test]
+    fn lists_each_size_once() {
+        let text = r#"{"data":{"getSubredditPost":{"id":1,"title":"t","fullLengthSource":null,"gfycatSource":null,"redgifsSource":"https://redgifs.com/watch/a","mediaSources":[
+            {"url":"https://images.scrolller.com/a-320.jpg","width":320,"height":480,"isOptimized":false},
+            {"url":"https://images.scrolller.com/a.webp","width":1080,"height":1620,"isOptimized":true},
+            {"url":"https://images.scrolller.com/a.jpg","width":1080,"height":1620,"isOptimized":false},
+            {"url":"https://images.scrolller.com/a-640.jpg","width":640,"height":960,"isOptimized":false}]}}}"#;
+        let response: PostResponse = serde_json::from_str(text).unwrap();
+        let post = response.data.get_subreddit_post;
+
+        let variants = post.variants();
+        let kinds: Vec<_> = variants.iter().map(|variant| variant.kind).collect();
+        assert_eq!(
+            vec![Kind::Original, Kind::Sample, Kind::Sample, Kind::Preview],
+            kinds
+        );
+
+        let chosen = |quality: Quality| quality.choose(post.variants()).unwrap().value;
+        assert_eq!(
+            "https://images.scrolller.com/a.jpg",
+            chosen(Quality::Original)
+        );
+        assert_eq!(
+            "https://images.scrolller.com/a-640.jpg",
+            chosen(Quality::BestUnder(1000))
+        );
+        assert_eq!(
+            "https://images.scrolller.com/a-320.jpg",
+            chosen(Quality::Preview)
+        );
+    }
+
+    #[test]
+    fn expands_albums() {
+        let text = r#"{"data":{"getSubredditPost":{"id":2,"url":"/an-album-abc123","title":"t","albumUrl":"/an-album-abc123","mediaSources":[],"albumContent":[
+            {"mediaSources":[{"url":"https://images.scrolller.com/b.jpg","width":800,"height":600}]},
+            {"mediaSources":[{"url":"https://images.scrolller.com/c.jpg","width":800,"height":600}]}]}}}"#;
+        let response: PostResponse = serde_json::from_str(text).unwrap();
+        let post = response.data.get_subreddit_post;
+
+        assert!(post.album_path().is_none());
+        let images: Vec<_> = post.images(Quality::Original).collect();
+        assert_eq!(2, images.len());
+        assert_eq!("https://images.scrolller.com/c.jpg", images[1].url);
+        assert_eq!("https://scrolller.com/an-album-abc123#2", images[1].source);
+        assert_eq!(
+            Some("https://scrolller.com/an-album-abc123"),
+            images[1].metadata.source.as_deref()
+        );
+    }
+
+    #[test]
+    fn reads_feed_page() {
+        let text = r#"{"data":{"getSubreddit":{"children":{"iterator":"abc","items":[
+            {"id":3,"url":"/an-album-def456","title":"t","albumUrl":"/an-album-def456","mediaSources":[]},
+            {"id":4,"url":"/a-picture-ghi789","title":"t","albumUrl":null,"mediaSources":[{"url":"https://images.scrolller.com/d.jpg","width":800,"height":600}]}]}}}}"#;
+        let response: FeedResponse = serde_json::from_str(text).unwrap();
+        let children = response.data.get_subreddit.unwrap().children;
+
+        assert_eq!(Some("abc"), children.iterator.as_deref());
+        assert_eq!(Some("/an-album-def456"), children.items[0].album_path());
+        assert_eq!(None, children.items[1].album_path());
+        assert_eq!(1, children.items[1].images(Quality::Original).count());
+    }
+
+    #[test]
+    fn reads_feed_from_url() -> crate::Result<()> {
+        let feed = super::read_feed("https://scrolller.com/r/Fitness?filter=pictures")?.unwrap();
+        assert_eq!("/r/Fitness", feed.path);
+        assert_eq!("Fitness", feed.name);
+        assert_eq!(Some("PICTURE"), feed.filter);
+
+        assert!(super::read_feed("https://scrolller.com/whitney-johns-ewhhmc5wuo")?.is_none());
+        assert!(super::read_feed("https://scrolller.com/u/someone?filter=gifs").is_err());
+        Ok(())
+    }
 }
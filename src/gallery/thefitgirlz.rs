@@ -3,20 +3,31 @@ use regex::Regex;
 
 use super::prelude::*;
 
-pub fn extract(url: &str) -> crate::Result<(PagedGallery<FgPager>, Option<String>)> {
+pub static SITE: Site = Site {
+    name: "TheFitGirlz",
+    hosts: &["thefitgirlz.com"],
+    routes: &["/gallery/<model>/"],
+    extractor: |url, settings| boxed(url, settings, extract),
+};
+
+pub fn extract(
+    url: &str,
+    settings: &Settings,
+) -> crate::Result<(PagedGallery<FgPager>, Option<String>)> {
     let model = extract_model_name(url)?;
     let gallery = PagedGallery {
-        context: Context {
-            client: Client::builder().user_agent(USER_AGENT).build().unwrap(),
+        context: Arc::new(Context {
+            client: client_builder(settings).build().unwrap(),
             image_meta_selector: Matcher::new("meta").unwrap(),
             image_name_pattern: Regex::new(r"wp-content/uploads/(\d+)/(\d+)/(.+)").unwrap(),
-        },
+        }),
         pager: FgPager {
             is_complete: false,
             offset: 0,
             model: model.clone(),
         },
         current: Page::Empty,
+        filter: settings.filter.clone(),
     };
 
     Ok((gallery, Some(model)))
@@ -84,7 +95,7 @@ impl Pager for FgPager {
 // this throw an error, or will it send back a response devoid of links?
 // Option 2 is fine. Option 1 not so much.
 fn download_page(url: &str, client: &Client) -> crate::Result<String> {
-    Ok(client.get(url).send()?.text()?)
+    Ok(client.get(url).send_with_retry()?.text()?)
 }
 
 pub struct Url(String);
@@ -94,8 +105,18 @@ impl Downloadable for Url {
     type Output = NamedGalleryItem;
 
     fn download(self, context: &Self::Context) -> crate::Result<Self::Output> {
+        let Media {
+            url,
+            name,
+            metadata,
+        } = self.resolve(context)?;
+        let response = context.client.get(url).send_with_retry()?;
+        Ok(NamedGalleryItem::new(response, name.unwrap_or_default()).with_metadata(metadata))
+    }
+
+    fn resolve(self, context: &Self::Context) -> crate::Result<Media> {
         // Step one: get the image url from the gallery page
-        let content = context.client.get(self.0).send()?.text()?;
+        let content = context.client.get(self.0).send_with_retry()?.text()?;
         let document = Document::from(&content);
         let url = document
             .select_matcher(&context.image_meta_selector)
@@ -133,11 +154,11 @@ impl Downloadable for Url {
         let month = captures.get(2).unwrap().as_str();
         let file = captures.get(3).unwrap().as_str();
 
-        let response = context.client.get(&*url).send()?;
-        Ok(NamedGalleryItem::new(
-            response,
-            format!("{}-{}-{}", year, month, file),
-        ))
+        Ok(Media::named(&*url, format!("{}-{}-{}", year, month, file)))
+    }
+
+    fn source(&self, _: &Self::Context) -> String {
+        self.0.clone()
     }
 }
 
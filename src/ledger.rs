@@ -0,0 +1,127 @@
+//! A record of every file downloaded into a directory.
+//!
+//! File names make for a lousy record of what we've already downloaded: sites reorder their
+//! results, and counter-based names shift around along with them. The ledger instead remembers
+//! where each file came from, one json object per line, in a file kept alongside the downloads.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+static LEDGER_FILE_NAME: &str = ".imgrab.ledger";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Entry {
+    /// The post or page the file belongs to; see [`crate::gallery::Downloadable::source`].
+    pub source: String,
+    /// The url the file was actually downloaded from.
+    pub url: String,
+    /// The file name, relative to the ledger's directory. A duplicate that was skipped in favor
+    /// of a copy elsewhere in the library points at that copy instead.
+    pub file: String,
+    pub size: u64,
+    pub timestamp: String,
+}
+
+impl Entry {
+    pub fn new(
+        source: impl Into<String>,
+        url: impl Into<String>,
+        file: impl Into<String>,
+        size: u64,
+    ) -> Self {
+        Self {
+            source: source.into(),
+            url: url.into(),
+            file: file.into(),
+            size,
+            timestamp: chrono::Local::now().to_rfc3339(),
+        }
+    }
+}
+
+pub struct Ledger {
+    sources: HashMap<String, Entry>,
+    files: HashSet<String>,
+    writer: Mutex<File>,
+}
+
+impl Ledger {
+    /// Opens the ledger for a directory, creating it if need be.
+    ///
+    /// Lookups only ever see what was in the ledger when it was opened; entries recorded during
+    /// this run are written to disk but are not visible until the next one.
+    pub fn open(directory: &Path) -> io::Result<Self> {
+        let path = directory.join(LEDGER_FILE_NAME);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut sources = HashMap::new();
+        let mut files = HashSet::new();
+        for entry in read_entries(&text) {
+            files.insert(entry.file.clone());
+            sources.insert(entry.source.clone(), entry);
+        }
+
+        let mut writer = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        // A crash may have left us with half a line; don't tack the next entry onto it.
+        if !text.is_empty() && !text.ends_with('\n') {
+            writer.write_all(b"\n")?;
+        }
+
+        Ok(Self {
+            sources,
+            files,
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Finds a previous download by source.
+    pub fn get(&self, source: &str) -> Option<&Entry> {
+        self.sources.get(source)
+    }
+
+    /// Returns true if the ledger accounts for the named file.
+    pub fn contains_file(&self, file: &str) -> bool {
+        self.files.contains(file)
+    }
+
+    pub fn record(&self, entry: &Entry) -> io::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        self.writer.lock().unwrap().write_all(line.as_bytes())
+    }
+}
+
+fn read_entries(text: &str) -> impl Iterator<Item = Entry> + '_ {
+    // Anything we can't read (e.g. a line cut short by a crash) is simply ignored.
+    text.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    static CONTENT: &str = "{\"source\":\"https://rule34.xxx/index.php?page=post&s=view&id=1\",\
+                            \"url\":\"https://us.rule34.xxx//images/1/a.png\",\"file\":\"a.png\",\
+                            \"size\":1024,\"timestamp\":\"2023-02-26T12:00:00-06:00\"}\n\
+                            \n\
+                            {\"source\":\"https://rule34.xxx/index.php?page=post&s=vi";
+
+    #[test]
+    fn can_read_entries() {
+        let entries: Vec<_> = super::read_entries(CONTENT).collect();
+        assert_eq!(1, entries.len());
+        assert_eq!("a.png", entries[0].file);
+        assert_eq!(1024, entries[0].size);
+    }
+}
@@ -0,0 +1,51 @@
+//! Extractors for image galleries.
+//!
+//! [`resolve`] turns a url into a [`BoxedGallery`], which yields [`Downloadable`] items one at
+//! a time. Downloading an item produces a [`GalleryItem`] that can be named by way of a
+//! [`StorageProvider`] and written wherever you like. The `imgrab` binary is one such consumer.
+
+pub mod config;
+pub mod cookies;
+pub mod error;
+pub mod filter;
+pub mod gallery;
+pub mod ledger;
+pub mod library;
+pub mod metadata;
+pub mod quality;
+pub mod queue;
+pub mod retry;
+pub mod settings;
+pub mod site;
+pub mod storage;
+
+mod client;
+mod session;
+mod tags;
+
+use url::Url;
+
+pub use crate::{
+    error::Error,
+    gallery::{BoxedGallery, Downloadable, Gallery, GalleryItem, Pager},
+    metadata::Metadata,
+    settings::Settings,
+    site::Site,
+    storage::{NameContext, StorageProvider},
+};
+
+use crate::error::UnsupportedError;
+
+pub type Result<T, E = error::Error> = std::result::Result<T, E>;
+
+/// Resolves a url to its gallery, along with a name for the gallery if the site provides one.
+pub fn resolve(url: &str, settings: &Settings) -> Result<(BoxedGallery, Option<String>)> {
+    let parsed_url = Url::parse(url)?;
+    let host = parsed_url
+        .domain()
+        .ok_or_else(|| Error::Unsupported(UnsupportedError::Route, url.into()))?;
+
+    Site::find(host)
+        .ok_or_else(|| Error::Unsupported(UnsupportedError::Domain, host.into()))?
+        .extract(url, settings)
+}
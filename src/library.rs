@@ -0,0 +1,193 @@
+//! A content index shared by every gallery under a library root.
+//!
+//! The ledger knows what's been downloaded into one directory, by source, but the same image
+//! tends to turn up on several sites under several names. The library instead remembers files
+//! by the sha1 of their content, along with the md5 where the site published one, in an index
+//! kept at the library root. Only files downloaded while the index is in use are known to it.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use sha1_smol::Sha1;
+
+static INDEX_FILE_NAME: &str = ".imgrab.hashes";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Record {
+    pub sha1: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    /// The file's path, relative to the library root where possible.
+    pub path: PathBuf,
+    pub timestamp: String,
+}
+
+pub struct Library {
+    root: PathBuf,
+    index: Mutex<Index>,
+}
+
+struct Index {
+    sha1: HashMap<String, PathBuf>,
+    md5: HashMap<String, PathBuf>,
+    writer: File,
+}
+
+impl Library {
+    /// Opens the index for a library root, creating it if need be.
+    ///
+    /// Unlike the ledger, the index sees what's recorded during this run, so that duplicates
+    /// within a single gallery are caught too.
+    pub fn open(root: &Path) -> io::Result<Self> {
+        let root = root.canonicalize()?;
+        let path = root.join(INDEX_FILE_NAME);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut writer = OpenOptions::new().create(true).append(true).open(&path)?;
+        if !text.is_empty() && !text.ends_with('\n') {
+            writer.write_all(b"\n")?;
+        }
+
+        let mut index = Index {
+            sha1: HashMap::new(),
+            md5: HashMap::new(),
+            writer,
+        };
+
+        for record in read_records(&text) {
+            if let Some(md5) = record.md5 {
+                index.md5.insert(md5, record.path.clone());
+            }
+            index.sha1.insert(record.sha1, record.path);
+        }
+
+        Ok(Self {
+            root,
+            index: Mutex::new(index),
+        })
+    }
+
+    /// Finds a file in the library with the given md5.
+    pub fn find_md5(&self, md5: &str) -> Option<PathBuf> {
+        let index = self.index.lock().unwrap();
+        self.existing(index.md5.get(&md5.to_ascii_lowercase())?)
+    }
+
+    /// Records a newly-written file, unless the library already holds the same content
+    /// elsewhere, in which case the path to the existing copy is returned instead.
+    pub fn claim(&self, sha1: &str, md5: Option<&str>, path: &Path) -> io::Result<Option<PathBuf>> {
+        let path = path.canonicalize()?;
+        let mut index = self.index.lock().unwrap();
+
+        let existing = index
+            .sha1
+            .get(sha1)
+            .and_then(|existing| self.existing(existing))
+            .filter(|existing| *existing != path);
+        if existing.is_some() {
+            return Ok(existing);
+        }
+
+        let record = Record {
+            sha1: sha1.into(),
+            md5: md5.map(str::to_ascii_lowercase),
+            path: path.strip_prefix(&self.root).unwrap_or(&path).into(),
+            timestamp: chrono::Local::now().to_rfc3339(),
+        };
+
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        index.writer.write_all(line.as_bytes())?;
+
+        if let Some(md5) = record.md5 {
+            index.md5.insert(md5, record.path.clone());
+        }
+        index.sha1.insert(record.sha1, record.path);
+        Ok(None)
+    }
+
+    /// Resolves a recorded path, provided the file is still there.
+    fn existing(&self, path: &Path) -> Option<PathBuf> {
+        let path = self.root.join(path);
+        path.is_file().then_some(path)
+    }
+}
+
+/// A writer that hashes everything written through it.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha1,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha1::new(),
+        }
+    }
+
+    /// The sha1 of everything written so far, as hex.
+    pub fn digest(&self) -> String {
+        self.hasher.digest().to_string()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.hasher.update(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn read_records(text: &str) -> impl Iterator<Item = Record> + '_ {
+    text.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::HashingWriter;
+
+    #[test]
+    fn hashing_writer() {
+        let mut writer = HashingWriter::new(Vec::new());
+        writer.write_all(b"The quick brown fox ").unwrap();
+        writer.write_all(b"jumps over the lazy dog").unwrap();
+        assert_eq!("2fd4e1c67a2d28fced849ee1bb76e7391b93eb12", writer.digest());
+        assert_eq!(43, writer.into_inner().len());
+    }
+
+    #[test]
+    fn can_read_records() {
+        let text = "{\"sha1\":\"abc\",\"md5\":\"def\",\"path\":\"a/1.png\",\
+                    \"timestamp\":\"2023-02-26T12:00:00-06:00\"}\n\
+                    {\"sha1\":\"ghi\",\"path\":\"b/2.png\",\"timestamp\":\"2023-02-26T12:00:00-06:00\"}\n\
+                    {\"sha1\":";
+        let records: Vec<_> = super::read_records(text).collect();
+        assert_eq!(2, records.len());
+        assert_eq!(Some("def"), records[0].md5.as_deref());
+        assert_eq!(None, records[1].md5);
+    }
+}
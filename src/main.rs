@@ -1,151 +1,985 @@
-mod config;
-mod error;
+mod batch;
+mod events;
+mod failures;
 mod format;
-mod gallery;
 mod options;
-mod storage;
-mod tags;
+mod progress;
+mod sequence;
+mod sidecar;
 mod waiter;
 
 use std::{
+    borrow::Cow,
     collections::HashSet,
     env,
     fs::{self, File},
+    io::{self, Write},
+    ops::AddAssign,
     path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
 };
 
-use error::{Error, UnsupportedError};
+use events::{Event, SkipReason};
+use failures::{Exit, Failure};
 use fmtsize::{Conventional, FmtSize};
 use format::DurationFormat;
-use gallery::Gallery;
-use options::Opt;
-use url::Url;
-
-use crate::gallery::GalleryItem;
-
-pub type Result<T, E = error::Error> = std::result::Result<T, E>;
-
-fn main() {
-    if let Err(e) = run(&Opt::parse()) {
-        eprintln!("{}", e);
-    }
-}
-
-fn run(opt: &Opt) -> crate::Result<()> {
-    use gallery::*;
-
-    let parsed_url = Url::parse(opt.url())?;
-    let domain = parsed_url
-        .domain()
-        .ok_or_else(|| Error::Unsupported(UnsupportedError::Route, opt.url().into()))?;
-
-    match domain {
-        "beta.sankakucomplex.com" => download(opt, sankakubeta::extract),
-        "bitchesgirls.com" => download(opt, bitchesgirls::extract),
-        "bleachbooru.org" => download(opt, bleachbooru::extract),
-        "booru.allthefallen.moe" => download(opt, atfbooru::extract),
-        "e-hentai.org" => download(opt, ehentai::extract),
-        "exhentai.org" => download(opt, exhentai::extract),
-        "fappeningbook.com" => download(opt, fappeningbook::extract),
-        "fitnakedgirls.com" => download(opt, fitnakedgirls::extract),
-        "fucks.pics" => download(opt, fucks_pics::extract),
-        "gelbooru.com" => download(opt, gelbooru::extract),
-        "hdporn.pics" => download(opt, hdporn::extract),
-        "imgur.com" => download(opt, imgur::extract),
-        "nhentai.net" => download(opt, nhentai::extract),
-        "novelcrow.com" => download(opt, novelcrow::extract),
-        "nsfwalbum.com" => download(opt, nsfwalbum::extract),
-        "rule34.us" => download(opt, rule34_us::extract),
-        "rule34.xxx" => download(opt, rule34::extract),
-        "scrolller.com" => download(opt, scrolller::extract),
-        "thefitgirlz.com" => download(opt, thefitgirlz::extract),
-        "www.beautymuscle.net" => download(opt, beautymuscle::extract),
-        "www.f-list.net" => download(opt, flist::extract),
-        "www.girlswithmuscle.com" => download(opt, girlswithmuscle::extract),
-        "www.hentai-foundry.com" => download(opt, hentai_foundry::extract),
-
-        other => Err(Error::Unsupported(UnsupportedError::Domain, other.into())),
-    }
-}
-
-fn download<T: Gallery>(
-    opt: &Opt,
-    extractor: impl Fn(&str) -> crate::Result<(T, Option<String>)>,
-) -> crate::Result<()> {
+use imgrab::{
+    config,
+    ledger::{Entry, Ledger},
+    library::{HashingWriter, Library},
+    queue::{QueuedItem, RetryQueue},
+    site::Site,
+    storage::{self, NameContext},
+    Downloadable, Error, Gallery, GalleryItem, Metadata, Result, Settings, StorageProvider,
+};
+use options::{DuplicateAction, Opt};
+use progress::Progress;
+use sequence::Sequencer;
+
+fn main() -> ExitCode {
+    let opt = Opt::parse();
+
+    match run(&opt) {
+        Ok(exit) => exit.into(),
+        Err(e) => {
+            if opt.json {
+                let url = Some(opt.url()).filter(|url| !url.is_empty());
+                println!("{}", Event::error(url, &e));
+            } else {
+                eprintln!("{}", e);
+            }
+            Exit::for_error(&e).into()
+        }
+    }
+}
+
+fn run(opt: &Opt) -> Result<Exit> {
+    if opt.list_sites {
+        list_sites();
+        return Ok(Exit::Success);
+    }
+
+    let settings = opt.settings(&config::Configuration::init())?;
     let start_time = chrono::Local::now();
+    let summary = match opt.input.as_deref() {
+        Some(input) => grab_batch(opt, &settings, input)?,
+        None => grab(opt, &settings).inspect_err(|e| {
+            // A url that fails outright is still worth listing for another try.
+            if let Some(path) = &opt.failures {
+                let _ = failures::write_list(path, &[Failure::gallery(&opt.entry(), e)]);
+            }
+        })?,
+    };
+
+    if let Some(path) = &opt.failures {
+        failures::write_list(path, &summary.failed)?;
+    }
+
+    // A dry run has nothing worth summarizing.
+    if !opt.dry_run {
+        let elapsed = chrono::Local::now().signed_duration_since(start_time);
+        if opt.json {
+            println!("{}", summary.event(elapsed));
+        } else {
+            summary.print(elapsed);
+        }
+    }
+
+    Ok(Exit::for_failures(summary.files, &summary.failed))
+}
+
+fn grab(opt: &Opt, settings: &Settings) -> Result<Summary> {
+    let (gallery, gallery_name) = imgrab::resolve(opt.url(), settings)?;
+    if opt.dry_run {
+        dry_run(opt, gallery, gallery_name)?;
+        Ok(Summary::default())
+    } else {
+        download(opt, gallery, gallery_name)
+    }
+}
+
+/// Grabs each url in a list, carrying on past any that fail.
+fn grab_batch(opt: &Opt, settings: &Settings, input: &str) -> Result<Summary> {
+    let entries = batch::read(input)?;
+    let mut results = Vec::with_capacity(entries.len());
+
+    for entry in &entries {
+        if !opt.json {
+            println!("{}", entry.url);
+        }
+
+        let result = grab(&opt.for_entry(entry), settings);
+        match &result {
+            Err(e) if opt.json => println!("{}", Event::error(Some(&entry.url), e)),
+            Err(e) => eprintln!("{}", e),
+            Ok(_) => (),
+        }
+        results.push(result);
+    }
+
+    let mut total = Summary::default();
+    if opt.dry_run {
+        return Ok(total);
+    }
+
+    // Under --json, each gallery's events have already said how it went.
+    if !opt.json {
+        println!();
+    }
+
+    for (entry, result) in entries.iter().zip(results) {
+        match result {
+            Ok(summary) => {
+                if !opt.json {
+                    println!(
+                        "{}: {} files ({}), {} failed",
+                        entry.url,
+                        summary.files,
+                        summary.bytes.fmt_size(Conventional),
+                        summary.failed.len(),
+                    );
+                }
+                total += summary;
+            }
+
+            Err(e) => {
+                if !opt.json {
+                    println!("{}: {}", entry.url, e);
+                }
+                total.failed.push(Failure::gallery(entry, &e));
+            }
+        }
+    }
+
+    Ok(total)
+}
+
+/// The outcome of a download, or of a batch of them.
+#[derive(Debug, Default)]
+struct Summary {
+    files: usize,
+    bytes: u64,
+    failed: Vec<Failure>,
+    duplicates: usize,
+    partial_files: usize,
+}
+
+impl AddAssign for Summary {
+    fn add_assign(&mut self, other: Self) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+        self.failed.extend(other.failed);
+        self.duplicates += other.duplicates;
+        self.partial_files += other.partial_files;
+    }
+}
+
+impl Summary {
+    fn event(&self, elapsed: chrono::Duration) -> Event<'_> {
+        Event::Summary {
+            files: self.files,
+            bytes: self.bytes,
+            failures: self.failed.len(),
+            failed: &self.failed,
+            duplicates: self.duplicates,
+            partial_files: self.partial_files,
+            elapsed: elapsed.num_milliseconds() as f64 / 1000.0,
+        }
+    }
+
+    fn print(&self, elapsed: chrono::Duration) {
+        println!(
+            "\n{} files ({})",
+            self.files,
+            self.bytes.fmt_size(Conventional)
+        );
+
+        if !self.failed.is_empty() {
+            println!("{} failed", self.failed.len());
+            for failure in &self.failed {
+                match failure.index {
+                    Some(index) => println!("    {} {}: {}", index, failure.url, failure.message),
+                    None => println!("    {}: {}", failure.url, failure.message),
+                }
+            }
+        }
+
+        if self.duplicates > 0 {
+            println!("{} duplicates found in library", self.duplicates);
+        }
+
+        println!("{} elapsed", elapsed.into_formatter());
+
+        if self.partial_files > 0 {
+            println!(
+                "{} partial files from earlier runs removed",
+                self.partial_files
+            );
+        }
+    }
+}
+
+/// The name of the site serving a url.
+fn site_name(url: &str) -> Option<&'static str> {
+    let url = url::Url::parse(url).ok()?;
+    Site::find(url.host_str()?).map(|site| site.name)
+}
 
-    let (mut gallery, gallery_name) = extractor(opt.url())?;
+fn list_sites() {
+    for site in Site::all() {
+        println!("{} ({})", site.name, site.hosts.join(", "));
+        for route in site.routes {
+            println!("    https://{}{}", site.canonical_host(), route);
+        }
+    }
+}
 
+/// Lists the media a download would fetch and where it would go, without fetching any of it.
+fn dry_run<T: Gallery>(opt: &Opt, mut gallery: T, gallery_name: Option<String>) -> Result<()> {
     if let Some(skip) = opt.skip {
         gallery.advance_by(skip)?;
     }
 
     let current_dir = env::current_dir()?;
-    let canonical_base_dir = current_dir.canonicalize()?;
-    let overwrite = opt.overwrite();
+    let path = opt.storage_path(&current_dir, gallery_name.as_deref())?;
+    let mut storage = opt.storage(path, gallery_name.as_deref(), site_name(opt.url()));
     let waiter = opt
         .wait()
         .map(waiter::Waiter::from_option)
         .unwrap_or_default();
 
-    let mut storage =
-        opt.storage_provider(&current_dir, gallery_name.as_ref().map(AsRef::as_ref))?;
+    let context = gallery.context().clone();
+    let idx_offset = opt.skip.unwrap_or_default();
+    let mut idx = 0;
+
+    while let Some(item) = gallery.next_pending() {
+        idx += 1;
+        let n = idx + idx_offset;
+        let resolved = item.and_then(|item| {
+            let source = item.source(&context);
+            waiter.wait();
+            Ok((source, item.resolve(&context)?))
+        });
+
+        match resolved {
+            Ok((source, media)) => {
+                let path = storage.create_path(media.context());
+                let path = path.strip_prefix(&current_dir).unwrap_or(&path);
+                if opt.json {
+                    let event = Event::Item {
+                        index: n,
+                        source: &source,
+                        url: &media.url,
+                        path,
+                        metadata: media.metadata.as_ref(),
+                    };
+                    println!("{}", event);
+                } else {
+                    println!("{}\t{}\t{}", n, media.url, path.display());
+                }
+            }
+
+            Err(e) if opt.json => println!("{}", Event::failed(n, &e)),
+            Err(e) => eprintln!("{} Warning: {}", n, e),
+        }
+
+        if is_complete(idx, opt.take) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn download<T>(opt: &Opt, mut gallery: T, gallery_name: Option<String>) -> Result<Summary>
+where
+    T: Gallery,
+    T::Context: Sync,
+    T::Pending: Send,
+{
+    let started = chrono::Local::now();
+    let current_dir = env::current_dir()?;
+    let storage =
+        opt.storage_provider(&current_dir, gallery_name.as_deref(), site_name(opt.url()))?;
+    let directory = storage.path().to_owned();
+
+    // A retry scans from the start, since the gallery may have shifted since the failures were
+    // queued; the scan stops once every queued item has turned up.
+    let queue = RetryQueue::open(storage.path())?;
+    let (skip, retries) = if opt.retry_failed {
+        let retries: HashSet<_> = queue
+            .items()
+            .iter()
+            .map(|item| item.source.clone())
+            .collect();
+        if retries.is_empty() {
+            if !opt.json {
+                println!("Nothing to retry in {}", directory.display());
+            }
+            return Ok(Summary::default());
+        }
+        (None, Some(retries))
+    } else {
+        (opt.skip, None)
+    };
+
+    if let Some(skip) = skip {
+        gallery.advance_by(skip)?;
+    }
+
+    let partial_files = storage::remove_partial_files(storage.path())?;
     let existing_files = read_existing_files(storage.path())?;
+    let ledger = Ledger::open(storage.path())?;
+    let library = opt
+        .library(&config::Configuration::init())
+        .map(|root| Library::open(&root))
+        .transpose()?;
 
-    let mut count = 0;
-    let mut bytes_written = 0;
-    let idx_offset = opt.skip.unwrap_or_default();
+    if opt.json {
+        let event = Event::Gallery {
+            url: opt.url(),
+            site: site_name(opt.url()),
+            name: gallery_name.as_deref(),
+            directory: directory.strip_prefix(&current_dir).unwrap_or(&directory),
+            total: expected_total(0, gallery.remaining(), opt.take),
+        };
+        println!("{}", event);
+    }
+
+    let downloader = Downloader {
+        canonical_base_dir: current_dir.canonicalize()?,
+        existing_files,
+        ledger,
+        idx_offset: skip.unwrap_or_default(),
+        overwrite: opt.overwrite(),
+        take_new: opt.take_new,
+        write_metadata: opt.write_metadata,
+        entry: opt.entry(),
+        json: opt.json,
+        library,
+        duplicates: opt.duplicates,
+        retries,
+        seen: Mutex::new(HashSet::new()),
+        queued: Mutex::new(Vec::new()),
+        waiter: opt
+            .wait()
+            .map(waiter::Waiter::from_option)
+            .unwrap_or_default(),
+        storage: Sequencer::new(storage),
+        progress: Progress::new(!opt.json),
+        is_stopped: AtomicBool::new(false),
+        count: AtomicUsize::new(0),
+        failed: Mutex::new(Vec::new()),
+        duplicate_count: AtomicUsize::new(0),
+        bytes_written: AtomicU64::new(0),
+        error: Mutex::new(None),
+    };
 
-    while let Some(item) = gallery.next() {
-        let idx = count + idx_offset;
-        waiter.wait();
-
-        match item {
-            Ok(item) => {
-                let path = storage.create_path(item.context());
-                if !overwrite && existing_files.contains(&path) {
-                    if let Ok(file_path) = shorten_path(&canonical_base_dir, &path) {
-                        // We have just found an existing file. If we've been asked to stop
-                        // downloading after finding an existing file, we won't bother printing
-                        // the name of the file.
-
-                        if opt.take_new {
-                            break;
+    let context = gallery.context().clone();
+    let context = &*context;
+    let jobs = opt.jobs();
+
+    if jobs > 1 {
+        let (tx, rx) = mpsc::sync_channel(jobs);
+        let rx = Mutex::new(rx);
+
+        thread::scope(|scope| {
+            for _ in 0..jobs {
+                scope.spawn(|| loop {
+                    // Don't hang on to the receiver while downloading.
+                    let job = rx.lock().unwrap().recv();
+                    match job {
+                        Ok((idx, item)) => downloader.process(idx, item, context),
+                        Err(_) => break,
+                    }
+                });
+            }
+
+            downloader.dispatch(&mut gallery, opt.take, move |idx, item| {
+                tx.send((idx, item)).is_ok()
+            });
+        });
+    } else {
+        downloader.dispatch(&mut gallery, opt.take, |idx, item| {
+            downloader.process(idx, item, context);
+            true
+        });
+    }
+
+    downloader.progress.clear();
+    queue.save(
+        &downloader.seen.into_inner().unwrap(),
+        downloader.queued.into_inner().unwrap(),
+    )?;
+
+    if let Some(e) = downloader.error.into_inner().unwrap() {
+        return Err(e);
+    }
+
+    // Failures are listed in gallery order, whatever order they happened in.
+    let mut failed = downloader.failed.into_inner().unwrap();
+    failed.sort_by_key(|failure| failure.index);
+    let summary = Summary {
+        files: downloader.count.into_inner(),
+        bytes: downloader.bytes_written.into_inner(),
+        failed,
+        duplicates: downloader.duplicate_count.into_inner(),
+        partial_files,
+    };
+
+    if opt.write_metadata {
+        sidecar::write_gallery(
+            &directory,
+            &sidecar::GalleryRecord {
+                url: opt.url(),
+                site: site_name(opt.url()),
+                name: gallery_name.as_deref(),
+                started: started.to_rfc3339(),
+                finished: chrono::Local::now().to_rfc3339(),
+                files: summary.files,
+                bytes: summary.bytes,
+                failures: summary.failed.len(),
+                metadata: gallery.metadata(),
+            },
+        )?;
+    }
+
+    Ok(summary)
+}
+
+/// Download state shared between all workers.
+struct Downloader {
+    canonical_base_dir: PathBuf,
+    existing_files: HashSet<PathBuf>,
+    ledger: Ledger,
+    idx_offset: usize,
+    overwrite: bool,
+    take_new: bool,
+    write_metadata: bool,
+    /// How this gallery would be listed, should any of it need another try.
+    entry: batch::Entry,
+    json: bool,
+    library: Option<Library>,
+    duplicates: DuplicateAction,
+    /// Under --retry-failed, the sources of the items to retry; everything else is passed over.
+    retries: Option<HashSet<String>>,
+    /// Sources of the items dealt with this run, one way or another.
+    seen: Mutex<HashSet<String>>,
+    queued: Mutex<Vec<QueuedItem>>,
+    waiter: waiter::Waiter,
+    storage: Sequencer<StorageProvider>,
+    progress: Progress,
+    is_stopped: AtomicBool,
+    count: AtomicUsize,
+    failed: Mutex<Vec<Failure>>,
+    duplicate_count: AtomicUsize,
+    bytes_written: AtomicU64,
+    error: Mutex<Option<Error>>,
+}
+
+enum Slot {
+    Stopped,
+    Existing(PathBuf),
+    /// An existing file the ledger doesn't know about, e.g. from before we kept a ledger.
+    Unrecorded(PathBuf),
+    New(PathBuf),
+}
+
+impl Downloader {
+    /// Pulls pending items from the gallery and hands them off, in order, to `send`.
+    fn dispatch<T: Gallery>(
+        &self,
+        gallery: &mut T,
+        take: Option<usize>,
+        mut send: impl FnMut(usize, Result<T::Pending>) -> bool,
+    ) {
+        let mut idx = 0;
+        let mut pages = 0;
+        let mut retries_left = self.retries.as_ref().map(HashSet::len);
+        while !self.is_stopped() {
+            self.progress
+                .set_total(expected_total(idx, gallery.remaining(), take));
+            let fetches_page = gallery.buffered() == Some(0);
+            let item = match gallery.next_pending() {
+                Some(item) => item,
+                None => break,
+            };
+
+            if fetches_page && item.is_ok() {
+                pages += 1;
+                self.emit(Event::Page {
+                    page: pages,
+                    items: gallery.buffered().unwrap_or_default() + 1,
+                });
+            }
+
+            let is_retry = match (&self.retries, &item) {
+                (Some(retries), Ok(item)) => retries.contains(&item.source(gallery.context())),
+                _ => false,
+            };
+
+            if !send(idx, item) {
+                break;
+            }
+
+            idx += 1;
+            if is_complete(idx, take) {
+                break;
+            }
+
+            // Once every item to be retried has been found, there's no need to look further.
+            if let Some(left) = &mut retries_left {
+                *left = left.saturating_sub(is_retry as usize);
+                if *left == 0 {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn process<T: Downloadable>(&self, idx: usize, item: Result<T>, context: &T::Context) {
+        if let Err(e) = self.try_process(idx, item, context) {
+            self.stop();
+            self.error.lock().unwrap().get_or_insert(e);
+        }
+        self.progress.item_done();
+    }
+
+    fn try_process<T: Downloadable>(
+        &self,
+        idx: usize,
+        item: Result<T>,
+        context: &T::Context,
+    ) -> Result<()> {
+        // Every index has to take its turn, even if there's nothing left to do with it.
+        if self.is_stopped() {
+            self.storage.with_turn(idx, |_| ());
+            return Ok(());
+        }
+
+        let item = match item {
+            Ok(item) => item,
+            Err(e) => return self.fail(idx, None, None, e),
+        };
+
+        // Items are recognized by where they came from rather than by the name we would give
+        // them, which means we can skip them without downloading anything.
+        let source = item.source(context);
+        if self
+            .retries
+            .as_ref()
+            .is_some_and(|retries| !retries.contains(&source))
+        {
+            self.storage.with_turn(idx, |storage| storage.skip());
+            return Ok(());
+        }
+        self.seen.lock().unwrap().insert(source.clone());
+        if let Some(entry) = self.ledger.get(&source).filter(|_| !self.overwrite) {
+            let slot = self.storage.with_turn(idx, |storage| {
+                if self.is_stopped() {
+                    return Slot::Stopped;
+                }
+
+                storage.skip();
+                self.existing(storage.path().join(&entry.file))
+            });
+
+            if let Slot::Existing(path) = slot {
+                self.print_existing(idx, &path);
+                self.count.fetch_add(1, Ordering::SeqCst);
+            }
+            return Ok(());
+        }
+
+        // Where the site tells us the md5 of its media, we can recognize content we already
+        // have without downloading it again.
+        let md5 = self.library.as_ref().and_then(|_| item.md5(context));
+        let duplicate = md5
+            .as_deref()
+            .and_then(|md5| self.library.as_ref()?.find_md5(md5));
+        if let Some(existing) = duplicate {
+            match self.duplicates {
+                DuplicateAction::Skip => return self.skip_duplicate(idx, &existing),
+                DuplicateAction::Link => return self.link_duplicate(idx, item, context, &existing),
+                // We'll hear about it again once it's downloaded.
+                DuplicateAction::Report => (),
+            }
+        }
+
+        self.waiter.wait();
+        let mut item = match item.download(context) {
+            Ok(item) => item,
+            Err(e) => return self.fail(idx, Some(&source), None, e),
+        };
+
+        if let Err(e) = item.validate() {
+            return self.fail(idx, Some(&source), Some(&item.context()), e);
+        }
+
+        let slot = self
+            .storage
+            .with_turn(idx, |storage| self.slot(storage, item.context()));
+
+        self.finish(idx, source, slot, item, md5.as_deref())
+    }
+
+    /// Picks a path for a new item, or else finds that we already have it.
+    fn slot(&self, storage: &mut StorageProvider, context: NameContext) -> Slot {
+        if self.is_stopped() {
+            return Slot::Stopped;
+        }
+
+        let path = storage.create_path(context);
+        if self.overwrite || !self.existing_files.contains(&path) {
+            return Slot::New(path);
+        }
+
+        // The ledger has never heard of this file, so we assume it's ours from an earlier
+        // run. Otherwise, it belongs to some other item and we need a different name.
+        if !self.ledger.contains_file(&file_name(&path)) {
+            return match self.existing(path) {
+                Slot::Existing(path) => Slot::Unrecorded(path),
+                slot => slot,
+            };
+        }
+
+        Slot::New(storage::free_path(&path, |path| {
+            self.existing_files.contains(path) || path.exists()
+        }))
+    }
+
+    fn finish(
+        &self,
+        idx: usize,
+        source: String,
+        slot: Slot,
+        item: impl GalleryItem,
+        md5: Option<&str>,
+    ) -> Result<()> {
+        let url = item.context().url().to_owned();
+        match slot {
+            Slot::Stopped => return Ok(()),
+            Slot::Existing(path) => self.print_existing(idx, &path),
+            Slot::Unrecorded(path) => self.record_unrecorded(idx, source, url, &path)?,
+            Slot::New(path) => {
+                let metadata = self.write_metadata.then(|| item.metadata().cloned());
+                let name = item.context().name().map(Cow::into_owned);
+                let (bytes, sha1) = match write_file(&path, item, &self.progress)? {
+                    Ok(written) => written,
+                    Err(e) => {
+                        if !self.is_stopped() {
+                            let media = NameContext::new(&url, name.map(Cow::Owned));
+                            self.report_failure(idx, Some(&source), Some(&media), e);
                         }
+                        return Ok(());
+                    }
+                };
+
+                let existing = match &self.library {
+                    Some(library) => library.claim(&sha1, md5, &path)?,
+                    None => None,
+                };
 
-                        println!(
-                            "{} {} has already been downloaded",
-                            idx + 1,
-                            file_path.display()
-                        );
+                if let Some(existing) = existing {
+                    match self.duplicates {
+                        DuplicateAction::Skip => {
+                            fs::remove_file(&path)?;
+                            self.print_skipped(idx, &existing);
+                            self.duplicate_count.fetch_add(1, Ordering::SeqCst);
+
+                            // The next run should find the copy we kept rather than fetch the
+                            // item all over again.
+                            let file = existing.strip_prefix(path.parent().unwrap_or(&path));
+                            let file = file.unwrap_or(&existing).to_string_lossy();
+                            self.ledger.record(&Entry::new(source, url, file, bytes))?;
+                            return Ok(());
+                        }
+                        DuplicateAction::Link => {
+                            link_file(&existing, &path)?;
+                            self.print_linked(idx, &path, &url, &source, &existing);
+                        }
+                        DuplicateAction::Report => {
+                            self.print_saved(idx, &path, &url, &source, bytes, Some(&existing));
+                        }
                     }
+                    self.duplicate_count.fetch_add(1, Ordering::SeqCst);
                 } else {
-                    let mut target = File::create(&path)?;
-                    bytes_written += item.write(&mut target)?;
-                    if let Ok(file_path) = shorten_path(&canonical_base_dir, &path) {
-                        println!("{} {}", idx + 1, file_path.display());
-                    }
+                    self.print_saved(idx, &path, &url, &source, bytes, None);
+                }
+
+                if let Some(metadata) = metadata {
+                    self.write_sidecar(&path, &url, &source, metadata)?;
                 }
+
+                self.ledger
+                    .record(&Entry::new(source, url, file_name(&path), bytes))?;
+                self.bytes_written.fetch_add(bytes, Ordering::SeqCst);
             }
+        }
+
+        self.count.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn record_unrecorded(
+        &self,
+        idx: usize,
+        source: String,
+        url: String,
+        path: &Path,
+    ) -> Result<()> {
+        let size = fs::metadata(path)?.len();
+        self.ledger
+            .record(&Entry::new(source, url, file_name(path), size))?;
+        self.print_existing(idx, path);
+        Ok(())
+    }
 
-            Err(e) => eprintln!("{} Warning: {}", idx + 1, e),
+    fn write_sidecar(
+        &self,
+        path: &Path,
+        url: &str,
+        source: &str,
+        metadata: Option<Metadata>,
+    ) -> Result<()> {
+        let mut metadata = metadata.unwrap_or_default();
+        metadata.source.get_or_insert_with(|| source.into());
+        sidecar::write_item(
+            path,
+            &sidecar::ItemRecord {
+                url,
+                file: &file_name(path),
+                metadata: &metadata,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn skip_duplicate(&self, idx: usize, existing: &Path) -> Result<()> {
+        let is_stopped = self.storage.with_turn(idx, |storage| {
+            storage.skip();
+            self.is_stopped()
+        });
+
+        if !is_stopped {
+            self.print_skipped(idx, existing);
+            self.duplicate_count.fetch_add(1, Ordering::SeqCst);
         }
+        Ok(())
+    }
 
-        count += 1;
-        if is_complete(count, opt.take) {
-            break;
+    fn print_skipped(&self, idx: usize, existing: &Path) {
+        if let Ok(existing) = shorten_path(&self.canonical_base_dir, existing) {
+            if self.json {
+                self.emit(Event::Skipped {
+                    index: idx + self.idx_offset + 1,
+                    reason: SkipReason::Duplicate,
+                    path: &existing,
+                });
+            } else {
+                self.progress.println(format_args!(
+                    "{} duplicate of {} skipped",
+                    idx + self.idx_offset + 1,
+                    existing.display()
+                ));
+            }
         }
     }
 
-    let elapsed = chrono::Local::now().signed_duration_since(start_time);
-    println!(
-        "\n{} files ({})\n{} elapsed",
-        count,
-        bytes_written.fmt_size(Conventional),
-        elapsed.into_formatter(),
-    );
+    /// Links an item we already have in the library, without downloading it.
+    fn link_duplicate<T: Downloadable>(
+        &self,
+        idx: usize,
+        item: T,
+        context: &T::Context,
+        existing: &Path,
+    ) -> Result<()> {
+        let source = item.source(context);
+        let media = match item.resolve(context) {
+            Ok(media) => media,
+            Err(e) => return self.fail(idx, Some(&source), None, e),
+        };
 
-    Ok(())
+        let slot = self
+            .storage
+            .with_turn(idx, |storage| self.slot(storage, media.context()));
+
+        match slot {
+            Slot::Stopped => return Ok(()),
+            Slot::Existing(path) => self.print_existing(idx, &path),
+            Slot::Unrecorded(path) => self.record_unrecorded(idx, source, media.url, &path)?,
+            Slot::New(path) => {
+                link_file(existing, &path)?;
+                self.print_linked(idx, &path, &media.url, &source, existing);
+                self.duplicate_count.fetch_add(1, Ordering::SeqCst);
+
+                if self.write_metadata {
+                    self.write_sidecar(&path, &media.url, &source, media.metadata)?;
+                }
+
+                let size = fs::metadata(&path)?.len();
+                self.ledger
+                    .record(&Entry::new(source, media.url, file_name(&path), size))?;
+            }
+        }
+
+        self.count.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn print_saved(
+        &self,
+        idx: usize,
+        path: &Path,
+        url: &str,
+        source: &str,
+        bytes: u64,
+        duplicate_of: Option<&Path>,
+    ) {
+        let path = match shorten_path(&self.canonical_base_dir, path) {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+        let duplicate_of =
+            duplicate_of.and_then(|existing| shorten_path(&self.canonical_base_dir, existing).ok());
+
+        let index = idx + self.idx_offset + 1;
+        if self.json {
+            self.emit(Event::Saved {
+                index,
+                path: &path,
+                url,
+                source,
+                bytes,
+                duplicate_of: duplicate_of.as_deref(),
+            });
+        } else if let Some(existing) = duplicate_of {
+            self.progress.println(format_args!(
+                "{} {} duplicates {}",
+                index,
+                path.display(),
+                existing.display()
+            ));
+        } else {
+            self.progress
+                .println(format_args!("{} {}", index, path.display()));
+        }
+    }
+
+    fn print_linked(&self, idx: usize, path: &Path, url: &str, source: &str, existing: &Path) {
+        let path = shorten_path(&self.canonical_base_dir, path);
+        let existing = shorten_path(&self.canonical_base_dir, existing);
+        if let (Ok(path), Ok(existing)) = (path, existing) {
+            let index = idx + self.idx_offset + 1;
+            if self.json {
+                self.emit(Event::Linked {
+                    index,
+                    path: &path,
+                    url,
+                    source,
+                    existing: &existing,
+                });
+            } else {
+                self.progress.println(format_args!(
+                    "{} {} linked to {}",
+                    index,
+                    path.display(),
+                    existing.display()
+                ));
+            }
+        }
+    }
+
+    /// Reports a failed item, queueing it for another try if we know enough to find it again.
+    fn fail(
+        &self,
+        idx: usize,
+        source: Option<&str>,
+        media: Option<&NameContext>,
+        e: Error,
+    ) -> Result<()> {
+        if self.storage.with_turn(idx, |_| !self.is_stopped()) {
+            self.report_failure(idx, source, media, e);
+        }
+        Ok(())
+    }
+
+    /// Reports a failed item and queues it for a retry, once its turn has been taken.
+    fn report_failure(
+        &self,
+        idx: usize,
+        source: Option<&str>,
+        media: Option<&NameContext>,
+        e: Error,
+    ) {
+        let index = idx + self.idx_offset + 1;
+        if self.json {
+            self.emit(Event::failed(index, &e));
+        } else {
+            self.progress
+                .eprintln(format_args!("{} Warning: {}", index, e));
+        }
+
+        if let Some(source) = source {
+            let mut queued = QueuedItem::new(index, source, &e);
+            if let Some(media) = media {
+                queued = queued.with_media(media);
+            }
+            self.queued.lock().unwrap().push(queued);
+        }
+
+        let failure = Failure::new(&self.entry, Some(index), source, &e);
+        self.failed.lock().unwrap().push(failure);
+    }
+
+    fn existing(&self, path: PathBuf) -> Slot {
+        // If we've been asked to stop downloading after finding an existing file, we won't
+        // bother printing the name of the file, and nothing after this point will be written.
+        if self.take_new {
+            self.stop();
+            Slot::Stopped
+        } else {
+            Slot::Existing(path)
+        }
+    }
+
+    fn print_existing(&self, idx: usize, path: &Path) {
+        if let Ok(file_path) = shorten_path(&self.canonical_base_dir, path) {
+            if self.json {
+                self.emit(Event::Skipped {
+                    index: idx + self.idx_offset + 1,
+                    reason: SkipReason::Existing,
+                    path: &file_path,
+                });
+            } else {
+                self.progress.println(format_args!(
+                    "{} {} has already been downloaded",
+                    idx + self.idx_offset + 1,
+                    file_path.display()
+                ));
+            }
+        }
+    }
+
+    /// Prints an event, provided we're printing events at all.
+    fn emit(&self, event: Event) {
+        if self.json {
+            self.progress.println(event);
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.is_stopped.load(Ordering::SeqCst)
+    }
+
+    fn stop(&self) {
+        self.is_stopped.store(true, Ordering::SeqCst);
+    }
 }
 
 fn read_existing_files(path: impl AsRef<Path>) -> Result<HashSet<PathBuf>> {
@@ -154,6 +988,104 @@ fn read_existing_files(path: impl AsRef<Path>) -> Result<HashSet<PathBuf>> {
         .collect())
 }
 
+/// Writes an item by way of a partial file, so that a failed download never leaves behind
+/// something that looks like a finished one. Returns the size and sha1 of what was written.
+///
+/// Trouble with the file itself is returned as the outer error, while trouble reading the item
+/// (a dropped connection, say) is returned as the inner one, since only the latter is worth
+/// trying again.
+fn write_file(
+    path: &Path,
+    item: impl GalleryItem,
+    progress: &Progress,
+) -> Result<Result<(u64, String)>> {
+    let partial = storage::partial_path(path);
+    let length = item.content_length();
+    let mut file = FileWriter::new(File::create(&partial)?);
+    let mut target = HashingWriter::new(progress.writer(&mut file, length));
+
+    let written = item
+        .write(&mut target)
+        .map(|bytes| (bytes, target.digest()));
+    drop(target);
+    let FileWriter { file, error } = file;
+    drop(file);
+
+    match (written, error) {
+        (Ok(written), _) => {
+            fs::rename(&partial, path)?;
+            Ok(Ok(written))
+        }
+
+        (Err(e), error) => {
+            let _ = fs::remove_file(&partial);
+            match error {
+                Some(error) => Err(error.into()),
+                None => Ok(Err(e)),
+            }
+        }
+    }
+}
+
+/// Remembers the first error in writing to a file, which would otherwise reach us only by way
+/// of whatever was reading the item.
+struct FileWriter {
+    file: File,
+    error: Option<io::Error>,
+}
+
+impl FileWriter {
+    fn new(file: File) -> Self {
+        Self { file, error: None }
+    }
+
+    fn check<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        if let Err(e) = &result {
+            self.error
+                .get_or_insert_with(|| io::Error::new(e.kind(), e.to_string()));
+        }
+        result
+    }
+}
+
+impl Write for FileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.file.write(buf);
+        self.check(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.file.flush();
+        self.check(result)
+    }
+}
+
+/// Replaces a file with a hard link to another, by way of a partial file.
+fn link_file(original: &Path, path: &Path) -> Result<()> {
+    let partial = storage::partial_path(path);
+    fs::hard_link(original, &partial)?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// The number of items we expect to dispatch, given how many have been so far.
+fn expected_total(
+    dispatched: usize,
+    remaining: Option<usize>,
+    take: Option<usize>,
+) -> Option<usize> {
+    match (remaining.map(|remaining| dispatched + remaining), take) {
+        (Some(total), Some(take)) => Some(total.min(take)),
+        (total, take) => total.or(take),
+    }
+}
+
 fn is_complete(count: usize, take: Option<usize>) -> bool {
     take.map(|take| take == count).unwrap_or_default()
 }
@@ -0,0 +1,52 @@
+//! What a site has to say about the things we download from it.
+//!
+//! Plenty of sites hand us tags, ratings and the like along with each image. None of it is
+//! needed to download anything, but it's worth keeping for anyone who wants to, e.g., search
+//! their archive by tag later on.
+
+use serde::Serialize;
+
+/// A record describing an item or a gallery. Anything the site doesn't provide is left out.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Metadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The page the item (or gallery) is published on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    /// The account that posted the item, which is not necessarily its artist.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploader: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    /// When the item was uploaded, as an rfc 3339 timestamp.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploaded: Option<String>,
+}
+
+impl Metadata {
+    /// Splits a space-separated tag string, as served by most boorus.
+    pub fn split_tags(tags: &str) -> Vec<String> {
+        tags.split_whitespace().map(Into::into).collect()
+    }
+
+    /// Formats a unix timestamp for the `uploaded` field.
+    pub fn timestamp(seconds: i64) -> Option<String> {
+        use chrono::{TimeZone, Utc};
+        Utc.timestamp_opt(seconds, 0)
+            .single()
+            .map(|time| time.to_rfc3339())
+    }
+}
@@ -1,8 +1,19 @@
-use std::{borrow::Cow, io, path::PathBuf};
+use std::{borrow::Cow, collections::HashMap, fs, io, path::PathBuf, time::Duration};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
-use crate::storage::StorageProvider;
+use imgrab::{
+    config::{Configuration, Key},
+    cookies::CookieFile,
+    filter::Filter,
+    quality::Quality,
+    retry::RetryPolicy,
+    settings::Settings,
+    site::Site,
+    storage::{make_safe_name, GalleryFields, NameTemplate, StorageProvider},
+};
+
+use crate::{batch::Entry, failures::EXIT_CODES};
 
 #[derive(Clone, Copy, Debug, Default)]
 pub enum WaitOption {
@@ -11,22 +22,45 @@ pub enum WaitOption {
     Specified(f64),
 }
 
+/// What to do with a file whose content is already somewhere in the library.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum DuplicateAction {
+    /// Don't keep the file.
+    #[default]
+    Skip,
+    /// Replace the file with a hard link to the existing copy.
+    Link,
+    /// Keep the file, but say so.
+    Report,
+}
+
 /// A program for downloading image galleries.
 ///
 /// It's best not to pass in your username and password. Instead, feel free to include that in
 /// a .env file when the program is compiled.
 #[derive(Clone, Debug, Parser)]
-#[clap(version)]
+#[clap(version, after_help = EXIT_CODES)]
 pub struct Opt {
     /// The target url.
-    url: String,
+    #[clap(required_unless_present_any = ["list_sites", "input"], conflicts_with = "input")]
+    url: Option<String>,
 
     /// A directory for new files.
     directory: Option<String>,
 
     /// A base name to be used in naming downloaded files.
     #[clap(short, long = "name")]
-    name_override: Option<String>,
+    pub name_override: Option<String>,
+
+    /// Name files after a template instead.
+    ///
+    /// Placeholders are {index} (zero-padded to 3 digits, or n digits as {index:n}), {id},
+    /// {name} and {ext} (the original file name and extension), {gallery}, {site}, {date} (the
+    /// upload date where the site provides one, or else today) and {md5}; {{ and }} stand for
+    /// literal braces. Placeholders the site can't fill come out empty. If the template doesn't
+    /// use {ext}, the original extension is added to the end. Takes precedence over --name.
+    #[clap(long = "name-template", value_name = "TEMPLATE")]
+    name_template: Option<NameTemplate>,
 
     /// Auto-derive name
     ///
@@ -61,6 +95,158 @@ pub struct Opt {
     /// this is a reliable way of getting new images, and for others it may not be.
     #[clap(short = 'T', long = "take-new")]
     pub take_new: bool,
+
+    /// Retry only the items that failed last time.
+    ///
+    /// Items that fail are queued in .imgrab.retry in the target directory. With this option,
+    /// each of them is found again in the gallery (reading pages as needed, but downloading
+    /// nothing else) and downloaded with the gallery's own session. Items that fail again stay
+    /// queued.
+    #[clap(long = "retry-failed", conflicts_with_all = ["skip", "take", "take_new"])]
+    pub retry_failed: bool,
+
+    /// Download n images at a time.
+    ///
+    /// Pages are still read one at a time and files are still named in gallery order; only the
+    /// downloads themselves run in parallel. Any cooldown applies across all downloads.
+    #[clap(short, long)]
+    jobs: Option<usize>,
+
+    /// Retry failed requests up to n times.
+    ///
+    /// Connection failures, rate limits and server errors are retried with exponential backoff,
+    /// honoring Retry-After where the server provides it. Defaults to the `retries` setting in
+    /// ~/.imgrab.conf, or else 3.
+    #[clap(long)]
+    retries: Option<u32>,
+
+    /// Wait n seconds before the first retry.
+    ///
+    /// The delay doubles with each further attempt. Defaults to the `retry_delay` setting in
+    /// ~/.imgrab.conf, or else 1.
+    #[clap(long = "retry-delay", value_parser = parse_delay)]
+    retry_delay: Option<Duration>,
+
+    /// Send a site cookies from a Netscape-format cookies.txt file. May be given once per site.
+    ///
+    /// Export these from a browser that's signed in to a site imgrab can't sign in to itself,
+    /// and name the site as --list-sites does, e.g. --cookies E-Hentai=cookies.txt. The file
+    /// goes only to that site, and each cookie only to the domain it belongs to, alongside any
+    /// login imgrab makes on its own; an imported e-hentai or exhentai login spares us signing
+    /// in at all. Adds to the `cookies.<site>` settings in ~/.imgrab.conf.
+    #[clap(long, value_name = "SITE=FILE", value_parser = parse_site_file)]
+    cookies: Vec<(String, PathBuf)>,
+
+    /// Which of a post's variants to download: original, sample, preview or best-under=SIZE.
+    ///
+    /// Where a site serves a post in more than one size, sample takes the site's scaled-down
+    /// copy, preview its thumbnail, and best-under=SIZE the largest variant no more than SIZE
+    /// pixels on either side. Sites serving a single variant ignore this, and any variant a site
+    /// lacks falls back to the nearest it has. On e-hentai and exhentai, anything short of
+    /// original spares the image quota. Defaults to the `quality` setting in ~/.imgrab.conf,
+    /// or else original.
+    #[clap(long, value_name = "QUALITY")]
+    quality: Option<Quality>,
+
+    /// Read urls from a file, or from stdin given -.
+    ///
+    /// Each line holds a url, optionally followed by a directory and a base name (as -n NAME)
+    /// for that url alone. Blank lines and lines starting with # are ignored. Every other
+    /// option applies to each url in turn.
+    #[clap(short, long, value_name = "FILE")]
+    pub input: Option<String>,
+
+    /// Download only posts with this tag.
+    ///
+    /// May be given more than once; posts must have every such tag. This and the options below
+    /// filter posts by what a booru's listing says about them, so posts turned away are never
+    /// downloaded. Sites that list nothing but links aren't filtered.
+    #[clap(long = "with-tag", value_name = "TAG")]
+    with_tags: Vec<String>,
+
+    /// Skip posts with this tag.
+    ///
+    /// May be given more than once. Tags in the `blacklist` setting in ~/.imgrab.conf (separated
+    /// by spaces) are always skipped.
+    #[clap(long = "without-tag", value_name = "TAG")]
+    without_tags: Vec<String>,
+
+    /// Download only posts with this rating.
+    ///
+    /// May be given more than once. A single letter stands for any rating starting with it, so
+    /// that e matches explicit.
+    #[clap(long = "rating", value_name = "RATING")]
+    ratings: Vec<String>,
+
+    /// Download only files of this type, by extension.
+    ///
+    /// May be given more than once.
+    #[clap(long = "file-type", value_name = "EXT")]
+    file_types: Vec<String>,
+
+    /// Skip posts narrower than n pixels.
+    #[clap(long = "min-width", value_name = "N")]
+    min_width: Option<u32>,
+
+    /// Skip posts shorter than n pixels.
+    #[clap(long = "min-height", value_name = "N")]
+    min_height: Option<u32>,
+
+    /// Skip posts scored below n.
+    #[clap(long = "min-score", value_name = "N", allow_negative_numbers = true)]
+    min_score: Option<i64>,
+
+    /// List what would be downloaded without downloading it.
+    ///
+    /// Each item is resolved to its media url and the path it would be saved to. Some sites
+    /// need a request per item to find the media, but the media itself is never requested.
+    #[clap(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Print json lines instead of text.
+    ///
+    /// Each line is an object whose "event" field is one of gallery, page, saved, linked,
+    /// skipped, failed, error or summary; --dry-run prints item events instead. Failures carry
+    /// a "kind": authentication, configuration, extraction, io, network, rejected, unsupported,
+    /// url or other.
+    /// Fields may be added to events, but existing fields keep their meaning. No progress is
+    /// shown.
+    #[clap(long)]
+    pub json: bool,
+
+    /// Write a json sidecar next to each new file.
+    ///
+    /// The sidecar is named for the file with .json appended and holds the media url, the
+    /// source page and whatever the site says about the item: id, title, tags, rating, artist,
+    /// uploader, dimensions, md5 and upload date. A record of the run as a whole is written to
+    /// .imgrab.gallery.json in the target directory.
+    #[clap(long = "write-metadata")]
+    pub write_metadata: bool,
+
+    /// Look for duplicate content across a library directory.
+    ///
+    /// Every file downloaded into the library is indexed by its sha1, so the same image is
+    /// recognized however it's named and whichever site it came from. Where a site publishes an
+    /// md5 for its media, duplicates are caught before they're downloaded. Defaults to the
+    /// `library` setting in ~/.imgrab.conf; without either, no index is kept.
+    #[clap(long, value_name = "DIR")]
+    library: Option<PathBuf>,
+
+    /// What to do with duplicates found in the library.
+    #[clap(long, value_enum, default_value_t)]
+    pub duplicates: DuplicateAction,
+
+    /// Write a list of whatever failed to a file.
+    ///
+    /// The list is in the form read by --input: each gallery with failures gets a line of its
+    /// own, preceded by comments naming the items that failed and why. Since items already
+    /// downloaded are skipped, the list can be fed back in to retry just the failures.
+    #[clap(long, value_name = "FILE")]
+    pub failures: Option<PathBuf>,
+
+    /// List supported sites and the urls they accept.
+    #[clap(long = "list-sites")]
+    pub list_sites: bool,
 }
 
 impl Opt {
@@ -69,7 +255,26 @@ impl Opt {
     }
 
     pub fn url(&self) -> &str {
-        &self.url
+        self.url.as_deref().unwrap_or_default()
+    }
+
+    /// The batch entry which would download this url to the same place, under the same name.
+    pub fn entry(&self) -> Entry {
+        Entry {
+            url: self.url().into(),
+            directory: self.directory.clone(),
+            name: self.name_override.clone(),
+        }
+    }
+
+    /// Options for one entry of a batch, which may override the directory and base name.
+    pub fn for_entry(&self, entry: &Entry) -> Self {
+        Self {
+            url: Some(entry.url.clone()),
+            directory: entry.directory.clone().or_else(|| self.directory.clone()),
+            name_override: entry.name.clone().or_else(|| self.name_override.clone()),
+            ..self.clone()
+        }
     }
 
     pub fn wait(&self) -> Option<WaitOption> {
@@ -81,34 +286,142 @@ impl Opt {
         self.overwrite
     }
 
+    pub fn jobs(&self) -> usize {
+        self.jobs.unwrap_or(1).max(1)
+    }
+
+    pub fn library(&self, config: &Configuration) -> Option<PathBuf> {
+        self.library
+            .clone()
+            .or_else(|| config.get_config(Key::Library).ok().map(PathBuf::from))
+    }
+
+    /// The cookies.txt file for each site, whether given here or in the config file.
+    pub fn cookies(&self, config: &Configuration) -> Vec<(String, PathBuf)> {
+        let mut files: Vec<_> = config
+            .cookie_files()
+            .filter(|&(site, _)| {
+                !self
+                    .cookies
+                    .iter()
+                    .any(|(given, _)| given.eq_ignore_ascii_case(site))
+            })
+            .map(|(site, path)| (site.to_owned(), PathBuf::from(path)))
+            .collect();
+        files.extend(self.cookies.iter().cloned());
+        files
+    }
+
+    pub fn quality(&self, config: &Configuration) -> Quality {
+        self.quality
+            .or_else(|| config.get_config(Key::Quality).ok()?.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn filter(&self, config: &Configuration) -> Filter {
+        let blacklist = config
+            .get_config(Key::Blacklist)
+            .map(|tags| tags.split_whitespace().map(String::from).collect())
+            .unwrap_or_else(|_| Vec::new());
+
+        Filter {
+            include: self.with_tags.clone(),
+            exclude: [&self.without_tags[..], &blacklist[..]].concat(),
+            ratings: self.ratings.clone(),
+            file_types: self.file_types.clone(),
+            min_width: self.min_width,
+            min_height: self.min_height,
+            min_score: self.min_score,
+        }
+    }
+
+    /// Everything the library needs to know to go about resolving and reading a gallery.
+    pub fn settings(&self, config: &Configuration) -> io::Result<Settings> {
+        let mut cookies = HashMap::new();
+        for (site, path) in self.cookies(config) {
+            cookies.insert(site, CookieFile::read(&path)?);
+        }
+
+        Ok(Settings {
+            cookies,
+            retry: self.retry_policy(config),
+            filter: self.filter(config),
+            quality: self.quality(config),
+        })
+    }
+
+    pub fn retry_policy(&self, config: &Configuration) -> RetryPolicy {
+        let mut policy = RetryPolicy::default();
+
+        if let Some(retries) = self
+            .retries
+            .or_else(|| config.get_config(Key::Retries).ok()?.parse().ok())
+        {
+            policy.retries = retries;
+        }
+
+        // A config value that makes no sense as a delay is ignored, like any other.
+        if let Some(delay) = self
+            .retry_delay
+            .or_else(|| parse_delay(config.get_config(Key::RetryDelay).ok()?).ok())
+        {
+            policy.delay = delay;
+        }
+
+        policy
+    }
+
     pub fn storage_provider(
         &self,
         current_dir: impl Into<PathBuf>,
         gallery_name: Option<&str>,
-    ) -> crate::Result<StorageProvider> {
-        use std::fs;
+        site: Option<&str>,
+    ) -> imgrab::Result<StorageProvider> {
+        let path = self.storage_path(current_dir, gallery_name)?;
+        if !path.exists() {
+            fs::create_dir(&path)?;
+        }
 
-        let Opt {
-            directory,
-            name_override,
-            ..
-        } = self;
+        Ok(self.storage(path, gallery_name, site))
+    }
 
+    /// Names files in a given directory, without creating anything.
+    pub fn storage(
+        &self,
+        path: PathBuf,
+        gallery_name: Option<&str>,
+        site: Option<&str>,
+    ) -> StorageProvider {
+        let storage = StorageProvider::new(path, self.name_override.clone());
+        match &self.name_template {
+            Some(template) => storage.with_template(
+                template.clone(),
+                GalleryFields {
+                    gallery: gallery_name.map(Into::into),
+                    site: site.map(Into::into),
+                },
+            ),
+            None => storage,
+        }
+    }
+
+    /// Works out where files are to be stored, without creating anything.
+    pub fn storage_path(
+        &self,
+        current_dir: impl Into<PathBuf>,
+        gallery_name: Option<&str>,
+    ) -> imgrab::Result<PathBuf> {
         let directory = if self.auto_name {
             gallery_name
                 .map(make_safe_name)
-                .or_else(|| directory.as_deref().map(Cow::from))
+                .or_else(|| self.directory.as_deref().map(Cow::from))
         } else {
-            directory.as_deref().map(Cow::from)
+            self.directory.as_deref().map(Cow::from)
         };
 
         // It is an error for the user to request an auto name and for us to have no name to use.
         if self.auto_name && directory.is_none() {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "auto name not available; use name override",
-            )
-            .into());
+            return Err(io::Error::other("auto name not available; use name override").into());
         }
 
         let mut current_dir = current_dir.into();
@@ -121,7 +434,6 @@ impl Opt {
                 Ok(path) => path,
                 _ => {
                     current_dir.push(&*path);
-                    fs::create_dir(&current_dir)?;
                     current_dir
                 }
             },
@@ -129,64 +441,26 @@ impl Opt {
             None => current_dir,
         };
 
-        Ok(StorageProvider::new(path, name_override.clone()))
+        Ok(path)
     }
 }
 
-fn make_safe_name(name: &str) -> Cow<str> {
-    // Replace opening and closing parens
-    let name = name.replace("%28", "(").replace("%29", ")");
-
-    for (idx, u) in name.bytes().enumerate() {
-        if is_illegal_char(u) {
-            return Cow::from(build_filtered_string(
-                &name[..idx],
-                &name[idx + 1..],
-                u == b'%',
-            ));
-        }
+/// Reads a delay in seconds, where anything below zero means no delay at all.
+fn parse_delay(s: &str) -> Result<Duration, String> {
+    let seconds: f64 = s.parse().map_err(|_| format!("{s} is not a number"))?;
+    if !seconds.is_finite() {
+        return Err(format!("{s} is not a finite number of seconds"));
     }
-
-    Cow::from(name)
+    Duration::try_from_secs_f64(seconds.max(0.0)).map_err(|_| format!("{s} seconds is too long"))
 }
 
-fn build_filtered_string(head: &str, tail: &str, mut skip_numerals: bool) -> String {
-    let mut has_invalid_char = false;
-    let mut buf = String::with_capacity(head.len() + tail.len() + 1);
-
-    buf.push_str(head);
-    buf.push('_');
-
-    for u in tail.bytes() {
-        if is_illegal_char(u) {
-            if u == b'%' {
-                skip_numerals = true;
-            }
-
-            if !has_invalid_char {
-                has_invalid_char = true;
-                buf.push('_');
-            }
-        } else {
-            if skip_numerals && u.is_ascii_digit() {
-                continue;
-            }
-
-            buf.push(u as char);
-            if has_invalid_char {
-                has_invalid_char = false;
-            }
-
-            if skip_numerals {
-                skip_numerals = false;
-            }
-        }
+/// Reads a site's name and a file for it, separated by an equals sign.
+fn parse_site_file(s: &str) -> Result<(String, PathBuf), String> {
+    let (site, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("{s} is not of the form SITE=FILE"))?;
+    if !Site::all().any(|known| known.name.eq_ignore_ascii_case(site)) {
+        return Err(format!("{site} is not a supported site; see --list-sites"));
     }
-
-    buf
-}
-
-fn is_illegal_char(u: u8) -> bool {
-    !u.is_ascii()
-        || !matches!(u, b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b' ' | b'-' | b'(' | b')')
+    Ok((site.to_owned(), PathBuf::from(path)))
 }
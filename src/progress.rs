@@ -0,0 +1,254 @@
+//! A status line for downloads in progress.
+//!
+//! On a terminal, the last line of output shows how many items are done (out of how many, if the
+//! gallery knows), the progress of the files being written, throughput and an estimate of the
+//! time remaining. Everything else printed during a download has to go through [`Progress`] so
+//! that the status line can get out of its way. When stdout isn't a terminal, there's no status
+//! line and output is just as it would be without one.
+
+use std::{
+    fmt::Display,
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use fmtsize::{Conventional, FmtSize};
+
+use crate::format::DurationFormat;
+
+/// How often the status line may be redrawn.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct Progress {
+    status: Option<Mutex<Status>>,
+}
+
+struct Status {
+    started: Instant,
+    last_drawn: Option<Instant>,
+    done: usize,
+    total: Option<usize>,
+    bytes: u64,
+    transfers: Vec<Transfer>,
+    next_id: usize,
+}
+
+struct Transfer {
+    id: usize,
+    written: u64,
+    length: Option<u64>,
+}
+
+impl Progress {
+    /// Shows a status line if asked to and stdout is a terminal.
+    pub fn new(enabled: bool) -> Self {
+        let status = (enabled && io::stdout().is_terminal()).then(|| {
+            Mutex::new(Status {
+                started: Instant::now(),
+                last_drawn: None,
+                done: 0,
+                total: None,
+                bytes: 0,
+                transfers: Vec::new(),
+                next_id: 0,
+            })
+        });
+
+        Self { status }
+    }
+
+    pub fn println(&self, line: impl Display) {
+        self.interrupt(|| println!("{}", line));
+    }
+
+    pub fn eprintln(&self, line: impl Display) {
+        self.interrupt(|| eprintln!("{}", line));
+    }
+
+    /// Sets the number of items expected, where it's known.
+    pub fn set_total(&self, total: Option<usize>) {
+        self.update(false, |status| status.total = total);
+    }
+
+    /// Counts an item as finished with, however it went.
+    pub fn item_done(&self) {
+        self.update(true, |status| status.done += 1);
+    }
+
+    /// Wraps the writer for a file of (maybe) known length, so that its progress is shown.
+    pub fn writer<W: Write>(&self, inner: W, length: Option<u64>) -> ProgressWriter<'_, W> {
+        let mut id = 0;
+        self.update(false, |status| {
+            id = status.next_id;
+            status.next_id += 1;
+            status.transfers.push(Transfer {
+                id,
+                written: 0,
+                length,
+            });
+        });
+
+        ProgressWriter {
+            progress: self,
+            id,
+            inner,
+        }
+    }
+
+    /// Removes the status line for good.
+    pub fn clear(&self) {
+        if let Some(status) = &self.status {
+            let mut status = status.lock().unwrap();
+            if status.last_drawn.take().is_some() {
+                erase();
+            }
+        }
+    }
+
+    fn interrupt(&self, print: impl FnOnce()) {
+        let status = match &self.status {
+            Some(status) => status,
+            None => return print(),
+        };
+
+        let mut status = status.lock().unwrap();
+        if status.last_drawn.is_some() {
+            erase();
+        }
+        print();
+        status.draw();
+    }
+
+    fn update(&self, force: bool, f: impl FnOnce(&mut Status)) {
+        if let Some(status) = &self.status {
+            let mut status = status.lock().unwrap();
+            f(&mut status);
+
+            let is_due = status
+                .last_drawn
+                .map(|drawn| drawn.elapsed() >= REDRAW_INTERVAL)
+                .unwrap_or(true);
+            if force || is_due {
+                status.draw();
+            }
+        }
+    }
+}
+
+impl Status {
+    fn draw(&mut self) {
+        print!("\r\x1b[2K{}", self.render());
+        let _ = io::stdout().flush();
+        self.last_drawn = Some(Instant::now());
+    }
+
+    fn render(&self) -> String {
+        let mut line = match self.total {
+            Some(total) => format!("[{}/{}]", self.done, total.max(self.done)),
+            None => format!("[{}]", self.done),
+        };
+
+        let written: u64 = self.transfers.iter().map(|transfer| transfer.written).sum();
+        let length: Option<u64> = self.transfers.iter().map(|transfer| transfer.length).sum();
+        if !self.transfers.is_empty() {
+            line += &format!(" {}", written.fmt_size(Conventional));
+            if let Some(length) = length.filter(|&length| length > 0) {
+                line += &format!(
+                    " of {} ({}%)",
+                    length.fmt_size(Conventional),
+                    written.min(length) * 100 / length
+                );
+            }
+        }
+
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = self.bytes as f64 / elapsed.max(f64::EPSILON);
+        line += &format!(" {}/s", (rate as u64).fmt_size(Conventional));
+
+        // With a known total, we can guess at the whole download. Otherwise, we can at least
+        // guess at the files in progress.
+        let eta = match (self.total, length) {
+            (Some(total), _) if self.done > 0 => {
+                Some(elapsed / self.done as f64 * total.saturating_sub(self.done) as f64)
+            }
+            (_, Some(length)) if rate > 0.0 && !self.transfers.is_empty() => {
+                Some(length.saturating_sub(written) as f64 / rate)
+            }
+            _ => None,
+        };
+
+        if let Some(eta) = eta {
+            let eta = chrono::Duration::seconds(eta.round() as i64);
+            line += &format!(" eta {}", eta.into_formatter());
+        }
+
+        line
+    }
+}
+
+fn erase() {
+    print!("\r\x1b[2K");
+}
+
+pub struct ProgressWriter<'a, W> {
+    progress: &'a Progress,
+    id: usize,
+    inner: W,
+}
+
+impl<W: Write> Write for ProgressWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        let id = self.id;
+        self.progress.update(false, |status| {
+            status.bytes += len as u64;
+            if let Some(transfer) = status.transfers.iter_mut().find(|t| t.id == id) {
+                transfer.written += len as u64;
+            }
+        });
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W> Drop for ProgressWriter<'_, W> {
+    fn drop(&mut self) {
+        let id = self.id;
+        self.progress.update(false, |status| {
+            status.transfers.retain(|transfer| transfer.id != id);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Status, Transfer};
+
+    #[test]
+    fn render() {
+        let status = Status {
+            started: Instant::now() - Duration::from_secs(10),
+            last_drawn: None,
+            done: 2,
+            total: Some(10),
+            bytes: 10 * 1024 * 1024,
+            transfers: vec![Transfer {
+                id: 0,
+                written: 1024 * 1024,
+                length: Some(4 * 1024 * 1024),
+            }],
+            next_id: 1,
+        };
+
+        let line = status.render();
+        assert!(line.starts_with("[2/10] "), "{}", line);
+        assert!(line.contains("(25%)"), "{}", line);
+        assert!(line.ends_with(" eta 0+00:40"), "{}", line);
+    }
+}
@@ -0,0 +1,182 @@
+//! Choosing which of a post's variants to download.
+//!
+//! Many sites serve each post in more than one size: the original upload, a sample scaled down
+//! for viewing in the browser, and a thumbnail. An extractor lists the variants it knows of as
+//! [`Variant`]s, best first, and the [`Quality`] in the gallery's
+//! [`Settings`](crate::Settings) picks one. Where a site offers no such choice, the extractor
+//! needn't ask.
+
+use std::{fmt, str::FromStr};
+
+/// Which variant of a post to download.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Quality {
+    /// The original upload.
+    #[default]
+    Original,
+    /// The site's scaled-down sample, or the original where there is none.
+    Sample,
+    /// The thumbnail, or the smallest variant where there is none.
+    Preview,
+    /// The largest variant no more than this many pixels on either side, or the smallest where
+    /// none is that small.
+    BestUnder(u32),
+}
+
+impl Quality {
+    /// Picks one of a post's variants, which must be listed best first.
+    pub fn choose<T>(self, variants: Vec<Variant<T>>) -> Option<Variant<T>> {
+        let mut variants = variants.into_iter();
+        match self {
+            Quality::Original => variants.next(),
+            Quality::Sample => {
+                let original = variants.next()?;
+                variants
+                    .find(|variant| variant.kind == Kind::Sample)
+                    .or(Some(original))
+            }
+            Quality::Preview => {
+                let mut smallest = None;
+                for variant in variants {
+                    if variant.kind == Kind::Preview {
+                        return Some(variant);
+                    }
+                    smallest = Some(variant);
+                }
+                smallest
+            }
+            Quality::BestUnder(limit) => {
+                let mut smallest = None;
+                for variant in variants {
+                    if variant.fits(limit) {
+                        return Some(variant);
+                    }
+                    smallest = Some(variant);
+                }
+                smallest
+            }
+        }
+    }
+}
+
+impl FromStr for Quality {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "original" => Ok(Quality::Original),
+            "sample" => Ok(Quality::Sample),
+            "preview" => Ok(Quality::Preview),
+            _ => s
+                .strip_prefix("best-under=")
+                .and_then(|size| size.parse().ok())
+                .map(Quality::BestUnder)
+                .ok_or_else(|| {
+                    format!("expected original, sample, preview or best-under=SIZE, not {s}")
+                }),
+        }
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quality::Original => f.write_str("original"),
+            Quality::Sample => f.write_str("sample"),
+            Quality::Preview => f.write_str("preview"),
+            Quality::BestUnder(size) => write!(f, "best-under={size}"),
+        }
+    }
+}
+
+/// What a site says a variant is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Original,
+    Sample,
+    Preview,
+}
+
+/// One of the forms in which a site serves a post, usually its url.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variant<T> {
+    pub kind: Kind,
+    pub value: T,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl<T> Variant<T> {
+    pub fn new(kind: Kind, value: T) -> Self {
+        Self {
+            kind,
+            value,
+            width: None,
+            height: None,
+        }
+    }
+
+    pub fn with_size(mut self, width: Option<u32>, height: Option<u32>) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn is_original(&self) -> bool {
+        self.kind == Kind::Original
+    }
+
+    /// A variant of unknown size is assumed not to fit.
+    fn fits(&self, limit: u32) -> bool {
+        match (self.width, self.height) {
+            (Some(width), Some(height)) => width <= limit && height <= limit,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Kind, Quality, Variant};
+
+    fn variants() -> Vec<Variant<&'static str>> {
+        vec![
+            Variant::new(Kind::Original, "original").with_size(Some(4000), Some(3000)),
+            Variant::new(Kind::Sample, "sample").with_size(Some(1600), Some(1200)),
+            Variant::new(Kind::Preview, "preview"),
+        ]
+    }
+
+    fn choose(quality: &str, variants: Vec<Variant<&'static str>>) -> &'static str {
+        let quality: Quality = quality.parse().unwrap();
+        quality.choose(variants).unwrap().value
+    }
+
+    #[test]
+    fn parses_quality() {
+        for quality in ["original", "sample", "preview", "best-under=2000"] {
+            assert_eq!(quality, quality.parse::<Quality>().unwrap().to_string());
+        }
+        assert!("best-under=large".parse::<Quality>().is_err());
+        assert!("huge".parse::<Quality>().is_err());
+    }
+
+    #[test]
+    fn chooses_variant() {
+        assert_eq!("original", choose("original", variants()));
+        assert_eq!("sample", choose("sample", variants()));
+        assert_eq!("preview", choose("preview", variants()));
+        assert_eq!("sample", choose("best-under=2000", variants()));
+        assert_eq!("original", choose("best-under=4000", variants()));
+        assert_eq!("preview", choose("best-under=1000", variants()));
+    }
+
+    #[test]
+    fn falls_back_where_variants_are_missing() {
+        let originals = || vec![Variant::new(Kind::Original, "original")];
+        assert_eq!("original", choose("sample", originals()));
+        assert_eq!("original", choose("preview", originals()));
+        assert_eq!("original", choose("best-under=1000", originals()));
+        assert!(Quality::Sample.choose(Vec::<Variant<()>>::new()).is_none());
+    }
+}
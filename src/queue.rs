@@ -0,0 +1,158 @@
+//! Items that failed to download, kept for another try.
+//!
+//! A failed item is otherwise lost unless the whole gallery is downloaded again. The queue
+//! keeps each one in a file alongside the downloads, one json object per line, so that a later
+//! run can retry just those items. Media urls tend to expire, so items are found again in their
+//! gallery by source; the url and name recorded here are for the benefit of the reader.
+
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{storage::NameContext, Error};
+
+static QUEUE_FILE_NAME: &str = ".imgrab.retry";
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct QueuedItem {
+    /// The item's one-based position in its gallery.
+    pub index: usize,
+    /// The post or page the item belongs to; see [`crate::gallery::Downloadable::source`].
+    pub source: String,
+    /// The media url, if we got as far as finding it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// The name the item would have been saved under, before any counter or template.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub error: String,
+    pub timestamp: String,
+}
+
+impl QueuedItem {
+    pub fn new(index: usize, source: impl Into<String>, error: &Error) -> Self {
+        Self {
+            index,
+            source: source.into(),
+            url: None,
+            name: None,
+            error: error.to_string(),
+            timestamp: chrono::Local::now().to_rfc3339(),
+        }
+    }
+
+    /// Records what we learned of the media before it failed.
+    pub fn with_media(mut self, context: &NameContext) -> Self {
+        self.url = Some(context.url().into());
+        self.name = context.name().map(Into::into);
+        self
+    }
+}
+
+pub struct RetryQueue {
+    path: PathBuf,
+    items: Vec<QueuedItem>,
+}
+
+impl RetryQueue {
+    /// Reads the queue for a directory; a directory without one has nothing queued.
+    pub fn open(directory: &Path) -> io::Result<Self> {
+        let path = directory.join(QUEUE_FILE_NAME);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path,
+            items: read_items(&text).collect(),
+        })
+    }
+
+    pub fn items(&self) -> &[QueuedItem] {
+        &self.items
+    }
+
+    /// Replaces the queue with whatever is still outstanding: the items this run never got to,
+    /// along with the ones that failed this time around.
+    pub fn save(self, seen: &HashSet<String>, failed: Vec<QueuedItem>) -> io::Result<()> {
+        let items = merge(self.items, seen, failed);
+        if items.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+
+        let mut text = String::new();
+        for item in &items {
+            text += &serde_json::to_string(item)?;
+            text.push('\n');
+        }
+        fs::write(&self.path, text)
+    }
+}
+
+fn merge(
+    queued: Vec<QueuedItem>,
+    seen: &HashSet<String>,
+    failed: Vec<QueuedItem>,
+) -> Vec<QueuedItem> {
+    let mut items: Vec<_> = queued
+        .into_iter()
+        .filter(|item| !seen.contains(&item.source))
+        .chain(failed)
+        .collect();
+
+    items.sort_by_key(|item| item.index);
+    items
+}
+
+fn read_items(text: &str) -> impl Iterator<Item = QueuedItem> + '_ {
+    text.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::QueuedItem;
+
+    fn item(index: usize, source: &str) -> QueuedItem {
+        QueuedItem {
+            index,
+            source: source.into(),
+            url: None,
+            name: None,
+            error: String::from("Server responded 503"),
+            timestamp: String::from("2023-02-26T12:00:00-06:00"),
+        }
+    }
+
+    #[test]
+    fn can_read_items() {
+        let text = "{\"index\":4,\"source\":\"https://a.com/s/4\",\"url\":\"https://i.a.com/4.jpg\",\
+                    \"error\":\"Server responded 503\",\"timestamp\":\"2023-02-26T12:00:00-06:00\"}\n\
+                    {\"index\":";
+        let items: Vec<_> = super::read_items(text).collect();
+        assert_eq!(1, items.len());
+        assert_eq!(Some("https://i.a.com/4.jpg"), items[0].url.as_deref());
+        assert_eq!(None, items[0].name);
+    }
+
+    #[test]
+    fn merge_keeps_what_is_outstanding() {
+        let queued = vec![item(2, "a"), item(9, "b")];
+        let seen: HashSet<_> = ["a", "c"].into_iter().map(String::from).collect();
+        let failed = vec![item(5, "c")];
+
+        let merged = super::merge(queued, &seen, failed);
+        assert_eq!(vec![item(5, "c"), item(9, "b")], merged);
+    }
+}
@@ -0,0 +1,147 @@
+//! Retries for requests which fail for reasons that have nothing to do with us.
+//!
+//! Rate limits, overloaded servers and dropped connections are all worth another try. Every
+//! request made by a gallery goes through [`Retry::send_with_retry`], which follows the policy
+//! of the [`Client`](crate::client::Client) that built the request.
+
+use std::{thread, time::Duration};
+
+use reqwest::{blocking::Response, header::RETRY_AFTER, StatusCode};
+
+use crate::client::RequestBuilder;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// The number of attempts made after the first one.
+    pub retries: u32,
+
+    /// The delay before the first retry; this doubles with each subsequent attempt.
+    pub delay: Duration,
+
+    /// The longest we'll wait between attempts, regardless of what the server asks for.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay)
+    }
+}
+
+pub trait Retry {
+    /// Sends the request, retrying transient failures according to the client's policy.
+    ///
+    /// Once retries are exhausted, the final response is returned as-is, error status and all.
+    fn send_with_retry(self) -> reqwest::Result<Response>;
+}
+
+impl Retry for RequestBuilder {
+    fn send_with_retry(self) -> reqwest::Result<Response> {
+        let policy = self.retry_policy();
+        let mut request = self;
+        let mut attempt = 0;
+
+        loop {
+            // Requests with streaming bodies can't be cloned and therefore can't be repeated.
+            let next = match request.try_clone() {
+                Some(next) if attempt < policy.retries => next,
+                _ => return request.send(),
+            };
+
+            let delay = match request.send() {
+                Ok(response) if !is_transient_status(response.status()) => return Ok(response),
+                Ok(response) => read_retry_after(&response)
+                    .map(|delay| delay.min(policy.max_delay))
+                    .unwrap_or_else(|| policy.backoff(attempt)),
+                Err(e) if is_transient_error(&e) => policy.backoff(attempt),
+                Err(e) => return Err(e),
+            };
+
+            thread::sleep(delay);
+            request = next;
+            attempt += 1;
+        }
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+fn is_transient_error(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout() || e.is_request()
+}
+
+fn read_retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, chrono::Utc::now())
+}
+
+/// Retry-After may be given either as a number of seconds or as an http date.
+fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    Some(date.signed_duration_since(now).to_std().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::client::Client;
+
+    use super::RetryPolicy;
+
+    #[test]
+    fn parse_retry_after() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap();
+        let cases = &[
+            ("120", Some(Duration::from_secs(120))),
+            (
+                "Wed, 21 Oct 2015 07:28:30 GMT",
+                Some(Duration::from_secs(30)),
+            ),
+            ("Wed, 21 Oct 2015 07:27:00 GMT", Some(Duration::ZERO)),
+            ("soon", None),
+        ];
+
+        for &(value, expected) in cases {
+            assert_eq!(super::parse_retry_after(value, now), expected);
+        }
+    }
+
+    #[test]
+    fn requests_follow_their_clients_policy() {
+        let policy = RetryPolicy {
+            retries: 7,
+            ..Default::default()
+        };
+        let client = Client::builder(policy).build().unwrap();
+        assert_eq!(client.get("https://example.com").retry_policy().retries, 7);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(0), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(8));
+        assert_eq!(policy.backoff(10), Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::{Condvar, Mutex};
+
+/// Grants access to a shared value strictly in index order.
+///
+/// Downloads may finish in any order, but file names have to be handed out in gallery order or
+/// else the storage provider's collision counter (and `--take-new`) would depend on which
+/// worker happened to be fastest. Each index must take exactly one turn; an index that never
+/// shows up will block every index after it.
+pub struct Sequencer<T> {
+    state: Mutex<(usize, T)>,
+    turn: Condvar,
+}
+
+impl<T> Sequencer<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            state: Mutex::new((0, value)),
+            turn: Condvar::new(),
+        }
+    }
+
+    /// Waits until it is `idx`'s turn and then applies `f` to the shared value.
+    pub fn with_turn<R>(&self, idx: usize, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut state = self
+            .turn
+            .wait_while(self.state.lock().unwrap(), |(next, _)| *next != idx)
+            .unwrap();
+
+        let result = f(&mut state.1);
+        state.0 += 1;
+        self.turn.notify_all();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::Sequencer;
+
+    #[test]
+    fn turns_are_taken_in_order() {
+        let sequencer = Sequencer::new(Vec::new());
+
+        thread::scope(|scope| {
+            for idx in (0..8).rev() {
+                let sequencer = &sequencer;
+                scope.spawn(move || {
+                    thread::sleep(Duration::from_millis(8 - idx as u64));
+                    sequencer.with_turn(idx, |order| order.push(idx));
+                });
+            }
+        });
+
+        let order = sequencer.with_turn(8, |order| order.clone());
+        assert_eq!(order, (0..8).collect::<Vec<_>>());
+    }
+}
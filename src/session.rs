@@ -0,0 +1,193 @@
+//! State worth keeping for the life of the process, and logins worth keeping longer.
+//!
+//! Logging in is slow, and some sites take a dim view of doing it over and over. When a batch
+//! of urls includes several from the same site, a [`Session`] lets every one of them share the
+//! first one's login. A [`SavedLogin`] carries that login over to the next run.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+
+pub struct Session<T> {
+    value: Mutex<Option<T>>,
+}
+
+impl<T: Clone> Session<T> {
+    pub const fn new() -> Self {
+        Self {
+            value: Mutex::new(None),
+        }
+    }
+
+    /// Returns the stored value, first initializing it if need be.
+    ///
+    /// A failed initialization stores nothing, so the next caller will try again.
+    pub fn get_or_try_init(&self, init: impl FnOnce() -> crate::Result<T>) -> crate::Result<T> {
+        let mut value = self.value.lock().unwrap();
+        if let Some(value) = &*value {
+            return Ok(value.clone());
+        }
+
+        let initialized = init()?;
+        *value = Some(initialized.clone());
+        Ok(initialized)
+    }
+
+    /// Throws away the stored value, e.g. a login the site no longer honors.
+    pub fn reset(&self) {
+        *self.value.lock().unwrap() = None;
+    }
+}
+
+/// A login kept on disk between runs, one file per site.
+///
+/// Saved logins are used until they expire or the site stops honoring them, whichever comes
+/// first. Loading and saving are best effort: a login we can't keep is merely one we'll have to
+/// repeat.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SavedLogin {
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub cookies: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// When the login runs out, in seconds since the epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<i64>,
+}
+
+impl SavedLogin {
+    /// Reads the saved login for a site, keyed by host.
+    pub fn load(key: &str) -> Option<Self> {
+        let text = fs::read_to_string(login_path(key)?).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    pub fn save(&self, key: &str) {
+        if let Some(path) = login_path(key) {
+            let _ = write_private(&path, &serde_json::to_vec_pretty(self).unwrap());
+        }
+    }
+
+    pub fn forget(key: &str) {
+        if let Some(path) = login_path(key) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    pub fn expires_in(mut self, seconds: i64) -> Self {
+        self.expires = Some(Utc::now().timestamp() + seconds);
+        self
+    }
+
+    /// True where the login has run out, or will within the minute.
+    pub fn is_expired(&self) -> bool {
+        self.expires
+            .map(|expires| expires - 60 <= Utc::now().timestamp())
+            .unwrap_or(false)
+    }
+}
+
+fn login_path(key: &str) -> Option<PathBuf> {
+    let dirs = ProjectDirs::from("", "", "imgrab")?;
+    Some(dirs.data_dir().join("sessions").join(format!("{key}.json")))
+}
+
+/// Writes a file only we can read, since it holds the keys to someone's account.
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(contents)
+}
+
+/// Reads when a `Set-Cookie` header's cookie expires, in seconds since the epoch.
+///
+/// Max-Age wins over Expires, as it does for browsers. Session cookies have no expiry.
+pub fn cookie_expiry(header: &str, now: i64) -> Option<i64> {
+    let attributes = header.split(';').skip(1).filter_map(|attribute| {
+        let (name, value) = attribute.split_once('=')?;
+        Some((name.trim().to_ascii_lowercase(), value.trim()))
+    });
+
+    let mut expires = None;
+    for (name, value) in attributes {
+        match &*name {
+            "max-age" => return value.parse::<i64>().ok().map(|age| now + age),
+            "expires" => expires = parse_cookie_date(value),
+            _ => {}
+        }
+    }
+    expires
+}
+
+fn parse_cookie_date(value: &str) -> Option<i64> {
+    // Most servers send an http date; older ones put dashes in it, Netscape-style.
+    DateTime::parse_from_rfc2822(value)
+        .map(|date| date.timestamp())
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(value, "%a, %d-%b-%Y %H:%M:%S GMT")
+                .map(|date| date.timestamp())
+        })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SavedLogin;
+
+    #[test]
+    fn cookie_expiry() {
+        let now = 1_677_400_000;
+        assert_eq!(
+            Some(1_708_936_000),
+            super::cookie_expiry(
+                "ipb_member_id=123; expires=Mon, 26-Feb-2024 08:26:40 GMT; path=/",
+                now
+            )
+        );
+        assert_eq!(
+            Some(1_708_936_000),
+            super::cookie_expiry(
+                "ipb_member_id=123; Expires=Mon, 26 Feb 2024 08:26:40 GMT; path=/",
+                now
+            )
+        );
+        assert_eq!(
+            Some(now + 3600),
+            super::cookie_expiry(
+                "a=b; expires=Mon, 26 Feb 2024 08:26:40 GMT; Max-Age=3600",
+                now
+            )
+        );
+        assert_eq!(None, super::cookie_expiry("ipb_coppa=0; path=/", now));
+    }
+
+    #[test]
+    fn saved_login_expiry() {
+        assert!(!SavedLogin::default().is_expired());
+        assert!(!SavedLogin::default().expires_in(3600).is_expired());
+        assert!(SavedLogin::default().expires_in(30).is_expired());
+        assert!(SavedLogin::default().expires_in(-30).is_expired());
+    }
+}
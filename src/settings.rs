@@ -0,0 +1,65 @@
+//! How galleries go about their business, as chosen by whoever asked for them.
+//!
+//! [`resolve`](crate::resolve) hands the same settings to whichever extractor serves the url,
+//! and the extractor passes them along to everything it builds. Two galleries resolved with
+//! different settings go their separate ways.
+
+use std::collections::HashMap;
+
+use crate::{
+    cookies::CookieFile, filter::Filter, quality::Quality, retry::RetryPolicy, site::Site,
+};
+
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    /// Cookies to send, by the name of the site they're for. Names are matched regardless of
+    /// case.
+    pub cookies: HashMap<String, CookieFile>,
+    pub retry: RetryPolicy,
+    pub filter: Filter,
+    pub quality: Quality,
+}
+
+impl Settings {
+    /// The settings as a site sees them: with its own cookies, and nobody else's.
+    pub fn for_site(&self, site: &Site) -> Settings {
+        let cookies = self
+            .cookies
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(site.name))
+            .map(|(name, file)| (name.clone(), file.clone()))
+            .collect();
+
+        Settings {
+            cookies,
+            retry: self.retry,
+            filter: self.filter.clone(),
+            quality: self.quality,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cookies::CookieFile, gallery::ehentai};
+
+    use super::Settings;
+
+    #[test]
+    fn sites_see_only_their_own_cookies() {
+        let settings = Settings {
+            cookies: [
+                ("e-hentai", CookieFile::default()),
+                ("ExHentai", CookieFile::default()),
+            ]
+            .into_iter()
+            .map(|(name, file)| (name.to_owned(), file))
+            .collect(),
+            ..Default::default()
+        };
+
+        let narrowed = settings.for_site(&ehentai::SITE);
+        assert_eq!(1, narrowed.cookies.len());
+        assert!(narrowed.cookies.contains_key("e-hentai"));
+    }
+}
@@ -0,0 +1,99 @@
+//! Json records written alongside downloads with --write-metadata.
+//!
+//! Each new file gets a sidecar of its own, named for the file with `.json` appended, holding
+//! whatever the site told us about it. The gallery as a whole gets a record of the run in
+//! `.imgrab.gallery.json`, which is rewritten each time the gallery is downloaded.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use imgrab::Metadata;
+use serde::Serialize;
+
+static GALLERY_FILE_NAME: &str = ".imgrab.gallery.json";
+
+#[derive(Debug, Serialize)]
+pub struct ItemRecord<'a> {
+    pub url: &'a str,
+    pub file: &'a str,
+    #[serde(flatten)]
+    pub metadata: &'a Metadata,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GalleryRecord<'a> {
+    pub url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub site: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'a str>,
+    pub started: String,
+    pub finished: String,
+    pub files: usize,
+    pub bytes: u64,
+    pub failures: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<&'a Metadata>,
+}
+
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".json");
+    path.into()
+}
+
+/// Writes the sidecar for the file at `path`.
+pub fn write_item(path: &Path, record: &ItemRecord) -> io::Result<()> {
+    write(&sidecar_path(path), record)
+}
+
+/// Writes the record of a run to the gallery's directory.
+pub fn write_gallery(directory: &Path, record: &GalleryRecord) -> io::Result<()> {
+    write(&directory.join(GALLERY_FILE_NAME), record)
+}
+
+fn write(path: &Path, record: &impl Serialize) -> io::Result<()> {
+    let mut text = serde_json::to_string_pretty(record)?;
+    text.push('\n');
+    fs::write(path, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use imgrab::Metadata;
+
+    use super::ItemRecord;
+
+    #[test]
+    fn sidecar_path() {
+        assert_eq!(
+            super::sidecar_path(Path::new("/foo/bar.jpg")),
+            Path::new("/foo/bar.jpg.json"),
+        );
+    }
+
+    #[test]
+    fn item_record_flattens_metadata() {
+        let metadata = Metadata {
+            id: Some("1".into()),
+            source: Some("https://a.com/post/1".into()),
+            tags: vec!["a".into(), "b".into()],
+            ..Default::default()
+        };
+
+        let record = ItemRecord {
+            url: "https://a.com/1.jpg",
+            file: "1.jpg",
+            metadata: &metadata,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            r#"{"url":"https://a.com/1.jpg","file":"1.jpg","id":"1","source":"https://a.com/post/1","tags":["a","b"]}"#,
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! The registry of supported sites.
+//!
+//! Each gallery module declares a [`Site`] describing the hosts it serves and the urls it
+//! understands, and registers it in [`SITES`](crate::gallery::SITES). Urls are dispatched to
+//! an extractor by host alone; the extractor itself decides whether it can make sense of the
+//! rest of the url.
+
+use url::Url;
+
+use crate::{
+    gallery::{booru, BoxedGallery, Gallery, SITES},
+    settings::Settings,
+};
+
+pub(crate) type Extractor = fn(&str, &Settings) -> crate::Result<(BoxedGallery, Option<String>)>;
+
+pub struct Site {
+    /// A human-readable name for the site.
+    pub name: &'static str,
+
+    /// The hosts served by the site, the first of which is canonical.
+    ///
+    /// A leading `www.` or `m.` is ignored on both sides of the comparison, and a pattern of
+    /// the form `*.example.com` also matches any subdomain of example.com.
+    pub hosts: &'static [&'static str],
+
+    /// The shapes of url the extractor understands, as paths relative to the canonical host.
+    pub routes: &'static [&'static str],
+
+    pub(crate) extractor: Extractor,
+}
+
+impl Site {
+    /// Every supported site, including booru instances named in the config file.
+    pub fn all() -> impl Iterator<Item = &'static Site> {
+        SITES.iter().copied().chain(booru::configured_sites())
+    }
+
+    /// Finds the site serving a given host.
+    pub fn find(host: &str) -> Option<&'static Site> {
+        Site::all().find(|site| site.serves(host))
+    }
+
+    /// Extracts the gallery at a url served by this site, along with a name for the gallery
+    /// if the site provides one.
+    pub fn extract(
+        &self,
+        url: &str,
+        settings: &Settings,
+    ) -> crate::Result<(BoxedGallery, Option<String>)> {
+        let settings = &settings.for_site(self);
+        let mut parsed_url = Url::parse(url)?;
+
+        // Extractors are written with the canonical host in mind, so that's what they get.
+        if parsed_url.host_str() == Some(self.canonical_host()) {
+            return (self.extractor)(url, settings);
+        }
+
+        parsed_url.set_host(Some(self.canonical_host()))?;
+        (self.extractor)(parsed_url.as_str(), settings)
+    }
+
+    pub fn canonical_host(&self) -> &'static str {
+        self.hosts[0]
+    }
+
+    pub fn serves(&self, host: &str) -> bool {
+        let host = strip_common_prefix(host);
+        self.hosts.iter().any(|&pattern| {
+            let pattern = strip_common_prefix(pattern);
+            match pattern.strip_prefix("*.") {
+                Some(domain) => {
+                    host == domain
+                        || host
+                            .strip_suffix(domain)
+                            .is_some_and(|subdomain| subdomain.ends_with('.'))
+                }
+                None => host == pattern,
+            }
+        })
+    }
+}
+
+fn strip_common_prefix(host: &str) -> &str {
+    host.strip_prefix("www.")
+        .or_else(|| host.strip_prefix("m."))
+        .unwrap_or(host)
+}
+
+/// Adapts a gallery module's extractor for use in the registry.
+pub(crate) fn boxed<T>(
+    url: &str,
+    settings: &Settings,
+    extractor: impl FnOnce(&str, &Settings) -> crate::Result<(T, Option<String>)>,
+) -> crate::Result<(BoxedGallery, Option<String>)>
+where
+    T: Gallery + 'static,
+    T::Context: Send + Sync + 'static,
+    T::Pending: Send + 'static,
+    T::Item: 'static,
+{
+    let (gallery, name) = extractor(url, settings)?;
+    Ok((BoxedGallery::new(gallery), name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Site;
+
+    #[test]
+    fn find_accepts_host_variants() {
+        let cases = &[
+            ("gelbooru.com", Some("Gelbooru")),
+            ("www.gelbooru.com", Some("Gelbooru")),
+            ("m.imgur.com", Some("Imgur")),
+            ("i.imgur.com", Some("Imgur")),
+            ("beautymuscle.net", Some("BeautyMuscle")),
+            ("www.rule34.xxx", Some("Rule34.xxx")),
+            ("wwww.rule34.xxx", Some("Rule34.xxx")),
+            ("notrule34.xxx", None),
+            ("example.com", None),
+        ];
+
+        for &(host, expected) in cases {
+            assert_eq!(Site::find(host).map(|site| site.name), expected, "{}", host);
+        }
+    }
+
+    #[test]
+    fn hosts_are_unique() {
+        for site in super::SITES {
+            for host in site.hosts {
+                let owner = Site::find(host.trim_start_matches("*.")).map(|site| site.name);
+                assert_eq!(owner, Some(site.name), "{}", host);
+            }
+        }
+    }
+}
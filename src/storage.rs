@@ -1,17 +1,25 @@
 use std::{
     borrow::Cow,
     collections::HashMap,
+    fs, io,
     path::{Path, PathBuf},
 };
 
 use reqwest::blocking::Response;
 
+use crate::{gallery::sniff, metadata::Metadata};
+
+mod template;
+
+pub use template::{GalleryFields, NameTemplate, TemplateError};
+
 #[derive(Debug)]
 pub struct StorageProvider {
     path: PathBuf,
     count: usize,
     filter: HashMap<String, usize>,
     name_override: Option<String>,
+    template: Option<(NameTemplate, GalleryFields)>,
 }
 
 impl StorageProvider {
@@ -21,9 +29,16 @@ impl StorageProvider {
             count: 0,
             filter: HashMap::new(),
             name_override,
+            template: None,
         }
     }
 
+    /// Names files after a template, which takes precedence over the base name.
+    pub fn with_template(mut self, template: NameTemplate, gallery: GalleryFields) -> Self {
+        self.template = Some((template, gallery));
+        self
+    }
+
     pub fn create_path(&mut self, context: NameContext) -> PathBuf {
         // Our name process may be a little branchy, but it's been abstracted a bit...
         //
@@ -35,14 +50,23 @@ impl StorageProvider {
         // gallery *or* the final segment of the url, which should somewhat resemble a filename.
         //
         // As a final fallback, we'll just use the count as a name, but that seems unlikely.
+        //
+        // Counter names get whatever extension the content calls for, if we know it.
 
         self.count += 1;
-        let name = match self.name_override.as_ref() {
-            Some(name) => Cow::from(format!("{}{:03}", name, self.count)),
-            None => context
+        let with_extension = |name: String| match context.extension() {
+            Some(extension) => format!("{}.{}", name, extension),
+            None => name,
+        };
+
+        let name = match (&self.template, self.name_override.as_ref()) {
+            (Some((template, gallery)), _) => {
+                Cow::from(template.render(self.count, &context, gallery))
+            }
+            (None, Some(name)) => Cow::from(with_extension(format!("{}{:03}", name, self.count))),
+            (None, None) => context
                 .name()
-                .map(Cow::from)
-                .unwrap_or_else(|| Cow::from(format!("{:03}", self.count))),
+                .unwrap_or_else(|| Cow::from(with_extension(format!("{:03}", self.count)))),
         };
 
         // Here we check to see how many files we've downloaded using this same name. If the number
@@ -59,6 +83,11 @@ impl StorageProvider {
         }
     }
 
+    /// Accounts for an item we won't be saving, so that counter-based names stay put.
+    pub fn skip(&mut self) {
+        self.count += 1;
+    }
+
     pub fn path(&self) -> &Path {
         &self.path
     }
@@ -71,6 +100,56 @@ impl StorageProvider {
     }
 }
 
+/// Files are written under a temporary name and only renamed once complete. Anything still
+/// wearing this suffix at the start of a run is left over from a download that never finished.
+static PARTIAL_SUFFIX: &str = ".imgrab-part";
+
+/// The temporary path a file is written to before being moved into place.
+pub fn partial_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}{}", name, PARTIAL_SUFFIX))
+}
+
+/// The first of `path`, `name-1.ext`, `name-2.ext` and so on that isn't taken.
+///
+/// Unlike asking for another path, this leaves the counter alone, so the names of items that
+/// follow don't shift.
+pub fn free_path(path: &Path, is_taken: impl Fn(&Path) -> bool) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy());
+    let mut candidate = path.to_owned();
+    let mut n = 0;
+    while is_taken(&candidate) {
+        n += 1;
+        let name = match &extension {
+            Some(extension) => format!("{stem}-{n}.{extension}"),
+            None => format!("{stem}-{n}"),
+        };
+        candidate = path.with_file_name(name);
+    }
+    candidate
+}
+
+/// Removes partial files left behind by earlier runs, returning the number removed.
+pub fn remove_partial_files(path: &Path) -> io::Result<usize> {
+    let mut count = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file()
+            && entry
+                .file_name()
+                .to_string_lossy()
+                .ends_with(PARTIAL_SUFFIX)
+        {
+            fs::remove_file(entry.path())?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
 fn nth_path(path: &Path, nth: usize) -> PathBuf {
     // Rust does some stupid things when it comes to identifying filename extensions. For instance,
     // the standard library thinks foo.tar.gz has a file stem of foo and an extension of .gz. For
@@ -149,34 +228,155 @@ fn nth_path(path: &Path, nth: usize) -> PathBuf {
 pub struct NameContext<'item> {
     url: &'item str,
     name: Option<Cow<'item, str>>,
+    extension: Option<&'static str>,
+    metadata: Option<&'item Metadata>,
 }
 
 impl<'a> NameContext<'a> {
     pub fn new(url: &'a str, name: Option<Cow<'a, str>>) -> Self {
-        NameContext { url, name }
+        NameContext {
+            url,
+            name,
+            extension: None,
+            metadata: None,
+        }
+    }
+
+    /// Replaces the name provided by the response with one from the gallery.
+    pub fn with_name(mut self, name: impl Into<Cow<'a, str>>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Settles the extension from the first bytes of the body, which know better than either
+    /// the url or the content type.
+    pub fn with_head(mut self, head: &[u8]) -> Self {
+        if let Some(extension) = sniff(head) {
+            self.extension = Some(extension);
+        }
+        self
+    }
+
+    /// Makes what the site says about the item available to name templates.
+    pub fn with_metadata(mut self, metadata: Option<&'a Metadata>) -> Self {
+        self.metadata = metadata;
+        self
     }
 
     pub fn from_response(response: &'a Response) -> Self {
         use reqwest::header::CONTENT_DISPOSITION;
+        use reqwest::header::CONTENT_TYPE;
+
         let name = response
             .headers()
             .get(CONTENT_DISPOSITION)
-            .and_then(|header| header.to_str().ok().and_then(read_filename));
-        NameContext::new(response.url().as_ref(), name.map(Cow::from))
+            .and_then(|header| read_filename(&String::from_utf8_lossy(header.as_bytes())));
+        let extension = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|header| header.to_str().ok())
+            .and_then(extension_for_content_type);
+
+        NameContext {
+            extension,
+            ..NameContext::new(response.url().as_ref(), name.map(Cow::from))
+        }
+    }
+
+    /// The url the item was retrieved from.
+    pub fn url(&self) -> &str {
+        self.url
+    }
+
+    pub fn metadata(&self) -> Option<&'a Metadata> {
+        self.metadata
+    }
+
+    /// The extension called for by the content, where we know it.
+    pub fn extension(&self) -> Option<&'static str> {
+        self.extension
     }
 
     /// Gets the best name from the gallery.
     ///
     /// This name may be the final segment of the URL, or it may be a more descriptive name
-    /// provided by some other means.
-    fn name(&self) -> Option<&str> {
-        self.name
+    /// provided by some other means. Either way, its extension is corrected to match the
+    /// content where we know better.
+    pub fn name(&self) -> Option<Cow<'_, str>> {
+        let name = self
+            .name
             .as_ref()
             .map(AsRef::as_ref)
-            .or_else(|| name_from_url(self.url))
+            .or_else(|| name_from_url(self.url))?;
+
+        match self.extension {
+            Some(extension) => Some(correct_extension(name, extension)),
+            None => Some(Cow::from(name)),
+        }
     }
 }
 
+/// Extensions which name the same format as the one we'd give it.
+static EQUIVALENT_EXTENSIONS: &[(&str, &[&str])] = &[
+    ("jpg", &["jpeg", "jpe", "jfif"]),
+    ("tif", &["tiff"]),
+    ("mp4", &["m4v", "m4a", "3gp"]),
+    ("webm", &["mkv"]),
+];
+
+/// Extensions we recognize as naming a media format, right or wrong.
+static MEDIA_EXTENSIONS: &[&str] = &[
+    "3gp", "avi", "avif", "bmp", "flv", "gif", "heic", "jfif", "jpe", "jpeg", "jpg", "m4a", "m4v",
+    "mkv", "mov", "mp4", "png", "swf", "tif", "tiff", "webm", "webp",
+];
+
+/// Gives a name the extension called for by its content.
+///
+/// A name with the wrong media extension has it replaced; one with no extension we recognize
+/// (including none at all) has the right one added.
+fn correct_extension<'a>(name: &'a str, extension: &str) -> Cow<'a, str> {
+    let (stem, current) = match name.rsplit_once('.') {
+        Some((stem, current)) if !stem.is_empty() => (stem, current.to_ascii_lowercase()),
+        _ => return Cow::from(format!("{}.{}", name, extension)),
+    };
+
+    let is_equivalent = current == extension
+        || EQUIVALENT_EXTENSIONS
+            .iter()
+            .any(|&(ext, equivalents)| ext == extension && equivalents.contains(&&*current));
+
+    if is_equivalent {
+        Cow::from(name)
+    } else if MEDIA_EXTENSIONS.contains(&&*current) {
+        Cow::from(format!("{}.{}", stem, extension))
+    } else {
+        Cow::from(format!("{}.{}", name, extension))
+    }
+}
+
+fn extension_for_content_type(content_type: &str) -> Option<&'static str> {
+    let content_type = content_type.split(';').next()?.trim().to_ascii_lowercase();
+    let extension = match &*content_type {
+        "image/jpeg" | "image/jpg" | "image/pjpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/bmp" => "bmp",
+        "image/tiff" => "tif",
+        "image/avif" => "avif",
+        "image/heic" => "heic",
+        "video/mp4" => "mp4",
+        "video/webm" => "webm",
+        "video/quicktime" => "mov",
+        "video/x-matroska" => "mkv",
+        "video/x-msvideo" => "avi",
+        "video/x-flv" => "flv",
+        "application/x-shockwave-flash" => "swf",
+        _ => return None,
+    };
+    Some(extension)
+}
+
 fn name_from_url(s: &str) -> Option<&str> {
     // Urls may have parameters, e.g. ?timestamp=2
     // We need to eliminate these as well.
@@ -212,16 +412,173 @@ fn name_from_url(s: &str) -> Option<&str> {
     }
 }
 
+/// Reads the file name from a Content-Disposition header, as described in RFC 6266.
+///
+/// An extended `filename*` parameter (RFC 5987) takes precedence over a plain `filename`. Names
+/// which could lead outside the target directory are refused outright.
 fn read_filename(disposition: &str) -> Option<String> {
     // "content-disposition": "attachment; filename=114_Turtlechan_312677_FISHOOKERS_PAGE_3.png"
-    disposition
-        .rfind("filename=")
-        .map(|idx| disposition[(idx + 9)..].to_owned())
+    let mut filename = None;
+    let mut extended_filename = None;
+
+    for (name, value) in disposition_parameters(disposition) {
+        if name.eq_ignore_ascii_case("filename*") {
+            extended_filename = extended_filename.or_else(|| decode_extended_value(&value));
+        } else if name.eq_ignore_ascii_case("filename") {
+            filename = filename.or(Some(value));
+        }
+    }
+
+    extended_filename
+        .or(filename)
+        .filter(|name| is_safe_filename(name))
+}
+
+/// Splits the parameters following the disposition type into names and (unquoted) values.
+fn disposition_parameters(disposition: &str) -> Vec<(&str, String)> {
+    let mut parameters = Vec::new();
+    let mut rest = match disposition.split_once(';') {
+        Some((_, rest)) => rest,
+        None => return parameters,
+    };
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ';' || c.is_whitespace());
+        if rest.is_empty() {
+            return parameters;
+        }
+
+        let end = rest.find([';', '=']).unwrap_or(rest.len());
+        let name = rest[..end].trim();
+        rest = &rest[end..];
+
+        // A parameter with no value is no use to us.
+        match rest.strip_prefix('=') {
+            Some(value) => rest = value.trim_start(),
+            None => continue,
+        }
+
+        let value = match rest.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((idx, c)) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                        '"' => {
+                            end = idx + 1;
+                            break;
+                        }
+                        c => value.push(c),
+                    }
+                }
+                rest = &quoted[end..];
+                value
+            }
+
+            // Plenty of servers don't bother quoting names with spaces in them, so an unquoted
+            // value runs all the way to the next semicolon.
+            None => {
+                let end = rest.find(';').unwrap_or(rest.len());
+                let value = rest[..end].trim().to_owned();
+                rest = &rest[end..];
+                value
+            }
+        };
+
+        parameters.push((name, value));
+    }
+}
+
+/// Decodes an RFC 5987 ext-value, e.g. `UTF-8''%e2%82%ac%20rates.txt`.
+fn decode_extended_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?;
+    let _language = parts.next()?;
+    let encoded = parts.next()?;
+    let bytes = urlencoding::decode_binary(encoded.as_bytes());
+
+    if charset.eq_ignore_ascii_case("utf-8") {
+        String::from_utf8(bytes.into_owned()).ok()
+    } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+        Some(bytes.iter().map(|&u| u as char).collect())
+    } else {
+        None
+    }
+}
+
+fn is_safe_filename(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains(['/', '\\'])
+        && !name.chars().any(char::is_control)
+}
+
+/// Makes a name safe for use in a path by replacing anything but ascii letters, numbers, spaces,
+/// dashes and parentheses. Url-encoded sequences are replaced along with their digits.
+pub fn make_safe_name(name: &str) -> Cow<'_, str> {
+    // Replace opening and closing parens
+    let name = name.replace("%28", "(").replace("%29", ")");
+
+    for (idx, u) in name.bytes().enumerate() {
+        if is_illegal_char(u) {
+            return Cow::from(build_filtered_string(
+                &name[..idx],
+                &name[idx + 1..],
+                u == b'%',
+            ));
+        }
+    }
+
+    Cow::from(name)
+}
+
+fn build_filtered_string(head: &str, tail: &str, mut skip_numerals: bool) -> String {
+    let mut has_invalid_char = false;
+    let mut buf = String::with_capacity(head.len() + tail.len() + 1);
+
+    buf.push_str(head);
+    buf.push('_');
+
+    for u in tail.bytes() {
+        if is_illegal_char(u) {
+            if u == b'%' {
+                skip_numerals = true;
+            }
+
+            if !has_invalid_char {
+                has_invalid_char = true;
+                buf.push('_');
+            }
+        } else {
+            if skip_numerals && u.is_ascii_digit() {
+                continue;
+            }
+
+            buf.push(u as char);
+            if has_invalid_char {
+                has_invalid_char = false;
+            }
+
+            if skip_numerals {
+                skip_numerals = false;
+            }
+        }
+    }
+
+    buf
+}
+
+fn is_illegal_char(u: u8) -> bool {
+    !u.is_ascii()
+        || !matches!(u, b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b' ' | b'-' | b'(' | b')')
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
 
     use super::{NameContext, StorageProvider};
 
@@ -242,6 +599,95 @@ mod tests {
         assert_eq!(expected, super::name_from_url(url));
     }
 
+    #[test]
+    fn correct_extension() {
+        let cases = &[
+            ("foo.jpg", "jpg", "foo.jpg"),
+            ("foo.JPEG", "jpg", "foo.JPEG"),
+            ("1234.jpg", "png", "1234.png"),
+            ("foo", "gif", "foo.gif"),
+            ("foo.1080p", "mp4", "foo.1080p.mp4"),
+            (".jpg", "png", ".jpg.png"),
+        ];
+
+        for &(name, extension, expected) in cases {
+            assert_eq!(super::correct_extension(name, extension), expected);
+        }
+    }
+
+    #[test]
+    fn create_path_corrects_extensions() {
+        let mut provider = StorageProvider::new("/", None);
+        let png = b"\x89PNG\r\n\x1A\n";
+
+        let context = NameContext::new("https://foo.com/1234.jpg", None).with_head(png);
+        assert_eq!(provider.create_path(context), Path::new("/1234.png"));
+
+        let context = NameContext::new("https://foo.com/view.php", None).with_head(png);
+        assert_eq!(provider.create_path(context), Path::new("/002.png"));
+
+        let mut provider = StorageProvider::new("/", Some(String::from("foo")));
+        let context = NameContext::new("https://foo.com/view.php", None).with_head(png);
+        assert_eq!(provider.create_path(context), Path::new("/foo001.png"));
+    }
+
+    #[test]
+    fn read_filename() {
+        let cases: &[(&str, Option<&str>)] = &[
+            (
+                "attachment; filename=114_Turtlechan_312677_FISHOOKERS_PAGE_3.png",
+                Some("114_Turtlechan_312677_FISHOOKERS_PAGE_3.png"),
+            ),
+            ("attachment; filename=\"foo.jpg\"", Some("foo.jpg")),
+            ("inline; filename=\"foo.jpg\"; size=12345", Some("foo.jpg")),
+            (
+                "inline; filename=foo bar.jpg; size=12345",
+                Some("foo bar.jpg"),
+            ),
+            ("attachment; FILENAME=\"upper.png\";", Some("upper.png")),
+            (
+                "attachment; filename=\"semi;colon.jpg\"",
+                Some("semi;colon.jpg"),
+            ),
+            (
+                "attachment; filename=\"\\\"quoted\\\".jpg\"",
+                Some("\"quoted\".jpg"),
+            ),
+            (
+                "attachment; filename=\"EURO rates.txt\"; filename*=utf-8''%e2%82%ac%20rates.txt",
+                Some("€ rates.txt"),
+            ),
+            (
+                "attachment;filename*=UTF-8''na%C3%AFve%20file.jpg;filename=\"naive file.jpg\"",
+                Some("naïve file.jpg"),
+            ),
+            (
+                "attachment; filename*=iso-8859-1'en'%A3%20rates.txt",
+                Some("£ rates.txt"),
+            ),
+            (
+                "attachment; filename=\"fallback.jpg\"; filename*=x-unknown''foo.jpg",
+                Some("fallback.jpg"),
+            ),
+            ("attachment; filename=\"../../etc/passwd\"", None),
+            ("attachment; filename=\"a\\\\b.jpg\"", None),
+            ("attachment; filename*=UTF-8''foo%2Fbar.jpg", None),
+            ("attachment; filename=..", None),
+            ("attachment; filename=\"\"", None),
+            ("attachment", None),
+            ("inline; size=12345", None),
+        ];
+
+        for &(header, expected) in cases {
+            assert_eq!(
+                super::read_filename(header).as_deref(),
+                expected,
+                "{}",
+                header
+            );
+        }
+    }
+
     #[test]
     fn nth_path() {
         let cases = &[
@@ -254,6 +700,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn free_path_leaves_the_name_alone_where_it_can() {
+        let taken = [PathBuf::from("dir/001.jpg"), PathBuf::from("dir/001-1.jpg")];
+        let is_taken = |path: &Path| taken.iter().any(|taken| taken == path);
+
+        assert_eq!(
+            PathBuf::from("dir/002.jpg"),
+            super::free_path(Path::new("dir/002.jpg"), is_taken)
+        );
+        assert_eq!(
+            PathBuf::from("dir/001-2.jpg"),
+            super::free_path(Path::new("dir/001.jpg"), is_taken)
+        );
+        assert_eq!(
+            PathBuf::from("dir/README"),
+            super::free_path(Path::new("dir/README"), is_taken)
+        );
+    }
+
+    #[test]
+    fn partial_path() {
+        assert_eq!(
+            super::partial_path(Path::new("/foo/bar.jpg")),
+            Path::new("/foo/.bar.jpg.imgrab-part")
+        );
+    }
+
     #[test]
     fn storage_provider_data_protection() {
         let mut provider = StorageProvider::new("/", None);
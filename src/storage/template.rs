@@ -0,0 +1,275 @@
+//! File names built from a template, e.g. `{gallery} {index:4} {id}`.
+//!
+//! Placeholders are filled from the item's name context and from the gallery being downloaded;
+//! anything unknown for a given item comes out empty. Values are made safe for use in a path,
+//! and if the template never mentions `{ext}`, the original extension is tacked on the end.
+
+use std::{fmt, str::FromStr};
+
+use super::{make_safe_name, NameContext};
+
+/// The padding given to `{index}` when none is specified.
+const DEFAULT_PADDING: usize = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    /// The item's position in the gallery, zero-padded to the given width.
+    Index(usize),
+    /// The site's id for the post.
+    Id,
+    /// The original file name, less its extension.
+    Name,
+    Ext,
+    Gallery,
+    Site,
+    /// The upload date where the site provides one, or else today.
+    Date,
+    Md5,
+}
+
+/// What a template knows about the gallery as a whole.
+#[derive(Clone, Debug, Default)]
+pub struct GalleryFields {
+    pub gallery: Option<String>,
+    pub site: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TemplateError(String);
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl FromStr for NameTemplate {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| TemplateError(format!("unclosed placeholder in {s:?}")))?;
+                    if !literal.is_empty() {
+                        parts.push(literal_part(literal.split_off(0))?);
+                    }
+                    parts.push(Part::Field(parse_field(&rest[..end])?));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(TemplateError(format!("unmatched }} in {s:?}"))),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(literal_part(literal)?);
+        }
+
+        if parts.is_empty() {
+            return Err(TemplateError(String::from("template is empty")));
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+/// Text between placeholders is used as is, so it mustn't be able to leave the directory.
+fn literal_part(literal: String) -> Result<Part, TemplateError> {
+    if literal.contains(['/', '\\']) || literal.contains("..") {
+        return Err(TemplateError(format!(
+            "{literal:?} would put the file outside the target directory"
+        )));
+    }
+
+    Ok(Part::Literal(literal))
+}
+
+fn parse_field(field: &str) -> Result<Field, TemplateError> {
+    let (name, padding) = match field.split_once(':') {
+        Some((name, padding)) => (name, Some(padding)),
+        None => (field, None),
+    };
+
+    let field = match name {
+        "index" => {
+            let padding = padding
+                .map(|padding| {
+                    padding
+                        .parse()
+                        .map_err(|_| TemplateError(format!("invalid padding in {{{field}}}")))
+                })
+                .transpose()?;
+            return Ok(Field::Index(padding.unwrap_or(DEFAULT_PADDING)));
+        }
+        "id" => Field::Id,
+        "name" => Field::Name,
+        "ext" => Field::Ext,
+        "gallery" => Field::Gallery,
+        "site" => Field::Site,
+        "date" => Field::Date,
+        "md5" => Field::Md5,
+        _ => return Err(TemplateError(format!("unknown placeholder {{{field}}}"))),
+    };
+
+    match padding {
+        Some(_) => Err(TemplateError(String::from("only {index} takes a padding"))),
+        None => Ok(field),
+    }
+}
+
+impl NameTemplate {
+    /// Renders a file name for the item at a given (one-based) index.
+    pub fn render(&self, index: usize, context: &NameContext, gallery: &GalleryFields) -> String {
+        let original = context.name();
+        let (stem, ext) = match original.as_deref().and_then(|name| name.rsplit_once('.')) {
+            Some((stem, ext)) if !stem.is_empty() => (Some(stem), Some(ext)),
+            _ => (original.as_deref(), context.extension()),
+        };
+
+        let metadata = context.metadata();
+        let mut name = String::new();
+        for part in &self.parts {
+            let value = match part {
+                Part::Literal(literal) => {
+                    name += literal;
+                    continue;
+                }
+                Part::Field(Field::Index(padding)) => {
+                    name += &format!("{:0padding$}", index);
+                    continue;
+                }
+                Part::Field(Field::Ext) => {
+                    name += ext.unwrap_or_default();
+                    continue;
+                }
+                Part::Field(Field::Id) => metadata.and_then(|m| m.id.clone()),
+                Part::Field(Field::Name) => stem.map(Into::into),
+                Part::Field(Field::Gallery) => gallery.gallery.clone(),
+                Part::Field(Field::Site) => gallery.site.clone(),
+                Part::Field(Field::Date) => {
+                    Some(date(metadata.and_then(|m| m.uploaded.as_deref())))
+                }
+                Part::Field(Field::Md5) => metadata.and_then(|m| m.md5.clone()),
+            };
+
+            if let Some(value) = value {
+                name += &make_safe_name(&value);
+            }
+        }
+
+        // A template of nothing but unknowns is no name at all.
+        if name.trim().is_empty() {
+            name = format!("{:0DEFAULT_PADDING$}", index);
+        }
+
+        let mentions_ext = self.parts.contains(&Part::Field(Field::Ext));
+        match ext {
+            Some(ext) if !mentions_ext => format!("{name}.{ext}"),
+            _ => name,
+        }
+    }
+}
+
+fn date(uploaded: Option<&str>) -> String {
+    uploaded
+        .and_then(|uploaded| chrono::DateTime::parse_from_rfc3339(uploaded).ok())
+        .map(|uploaded| uploaded.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| chrono::Local::now().format("%Y-%m-%d").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{metadata::Metadata, storage::NameContext};
+
+    use super::{GalleryFields, NameTemplate};
+
+    #[test]
+    fn parse_rejects_bad_templates() {
+        for template in ["", "{index", "index}", "{nope}", "{id:3}", "{index:x}"] {
+            assert!(template.parse::<NameTemplate>().is_err(), "{:?}", template);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_paths() {
+        for template in [
+            "../{index}",
+            "a/{index}",
+            "{id}\\{index}",
+            "{id}..",
+            "/{index}",
+        ] {
+            assert!(template.parse::<NameTemplate>().is_err(), "{:?}", template);
+        }
+
+        assert!("{id}.{ext}".parse::<NameTemplate>().is_ok());
+    }
+
+    #[test]
+    fn render() {
+        let metadata = Metadata {
+            id: Some("1234".into()),
+            md5: Some("abcdef".into()),
+            uploaded: Some("2023-02-26T12:00:00-06:00".into()),
+            ..Default::default()
+        };
+        let context = NameContext::new("https://a.com/images/sample.jpg", None)
+            .with_metadata(Some(&metadata));
+        let gallery = GalleryFields {
+            gallery: Some("korra/asami".into()),
+            site: Some("Rule34.xxx".into()),
+        };
+
+        let cases = &[
+            ("{index}", "007.jpg"),
+            ("{index:5} {id}", "00007 1234.jpg"),
+            ("{gallery} - {name}.{ext}", "korra_asami - sample.jpg"),
+            ("{site} {date} {md5}", "Rule34_xxx 2023-02-26 abcdef.jpg"),
+            ("{{{id}}}", "{1234}.jpg"),
+        ];
+
+        for &(template, expected) in cases {
+            let template: NameTemplate = template.parse().unwrap();
+            assert_eq!(template.render(7, &context, &gallery), expected);
+        }
+    }
+
+    #[test]
+    fn render_without_fields_falls_back_to_index() {
+        let context = NameContext::new("https://a.com/view.php", None);
+        let template: NameTemplate = "{id}".parse().unwrap();
+        assert_eq!(
+            template.render(12, &context, &GalleryFields::default()),
+            "012"
+        );
+    }
+}
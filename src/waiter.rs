@@ -1,10 +1,15 @@
-use std::{cell::Cell, thread, time::Duration};
+use std::{sync::Mutex, thread, time::Duration};
 
 use crate::options::WaitOption;
 
+/// Enforces a cooldown between downloads.
+///
+/// The waiter is shared by every download worker. The lock is held for the duration of the
+/// cooldown, so concurrent workers queue up behind one another rather than each waiting
+/// separately, which keeps the overall request rate the same regardless of `--jobs`.
 #[derive(Default)]
 pub struct Waiter {
-    is_active: Cell<bool>,
+    is_active: Mutex<bool>,
     time: Option<Duration>,
 }
 
@@ -16,17 +21,18 @@ impl Waiter {
         };
 
         Self {
-            is_active: Cell::new(false),
+            is_active: Mutex::new(false),
             time: Some(Duration::from_millis(milliseconds)),
         }
     }
 
     pub fn wait(&self) {
+        let mut is_active = self.is_active.lock().unwrap();
         if let Some(time) = self.time {
-            if self.is_active.get() {
+            if *is_active {
                 thread::sleep(time);
             }
         }
-        self.is_active.set(true);
+        *is_active = true;
     }
 }